@@ -0,0 +1,13 @@
+CREATE TABLE publish_jobs (
+    id          SERIAL PRIMARY KEY,
+    version_id  INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    status      INTEGER NOT NULL DEFAULT 0,
+    index_entry JSONB NOT NULL,
+    error       VARCHAR,
+    created_at  TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at  TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX index_publish_jobs_pending ON publish_jobs (id) WHERE status = 0;
+
+SELECT diesel_manage_updated_at('publish_jobs');
@@ -0,0 +1,3 @@
+CREATE INDEX index_publish_jobs_pending ON publish_jobs (id) WHERE status = 0;
+
+DROP TABLE background_jobs;
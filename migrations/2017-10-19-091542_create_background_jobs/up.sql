@@ -0,0 +1,19 @@
+CREATE TABLE background_jobs (
+    id         SERIAL PRIMARY KEY,
+    job_type   VARCHAR NOT NULL,
+    data       JSONB NOT NULL,
+    retries    INTEGER NOT NULL DEFAULT 0,
+    last_retry TIMESTAMP NOT NULL DEFAULT now(),
+    last_error VARCHAR,
+    dead       BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX index_background_jobs_live ON background_jobs (id) WHERE NOT dead;
+
+-- Publish jobs are now run by the generic job runner
+INSERT INTO background_jobs (job_type, data)
+    SELECT 'add_crate_to_index', json_build_object('publish_job_id', id)
+    FROM publish_jobs WHERE status = 0;
+
+DROP INDEX index_publish_jobs_pending;
@@ -0,0 +1,9 @@
+CREATE TABLE version_files (
+    id         SERIAL PRIMARY KEY,
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    path       VARCHAR NOT NULL,
+    size       BIGINT NOT NULL,
+    mode       INTEGER
+);
+
+CREATE UNIQUE INDEX index_version_files_version_id_path ON version_files (version_id, path);
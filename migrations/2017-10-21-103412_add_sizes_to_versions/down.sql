@@ -0,0 +1,3 @@
+ALTER TABLE versions
+    DROP COLUMN crate_size,
+    DROP COLUMN unpacked_size;
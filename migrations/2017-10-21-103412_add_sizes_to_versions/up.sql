@@ -0,0 +1,3 @@
+ALTER TABLE versions
+    ADD COLUMN crate_size INTEGER,
+    ADD COLUMN unpacked_size INTEGER;
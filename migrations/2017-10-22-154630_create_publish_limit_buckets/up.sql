@@ -0,0 +1,13 @@
+CREATE TABLE publish_limit_buckets (
+    bucket      VARCHAR NOT NULL,
+    action      INTEGER NOT NULL,
+    tokens      INTEGER NOT NULL,
+    last_refill TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (bucket, action)
+);
+
+CREATE TABLE publish_rate_overrides (
+    user_id           INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    new_crate_burst   INTEGER,
+    new_version_burst INTEGER
+);
@@ -0,0 +1,6 @@
+CREATE TABLE reserved_prefixes (
+    prefix     VARCHAR PRIMARY KEY,
+    team_id    INTEGER NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX ON reserved_prefixes (canon_crate_name(prefix));
@@ -0,0 +1,7 @@
+DELETE FROM dependencies WHERE crate_id IS NULL;
+ALTER TABLE dependencies
+    DROP CONSTRAINT dependencies_crate_or_registry,
+    ALTER COLUMN crate_id SET NOT NULL,
+    DROP COLUMN registry_crate_name,
+    DROP COLUMN registry,
+    DROP COLUMN explicit_name;
@@ -0,0 +1,12 @@
+-- Dependencies on crates from other registries don't have a `crate_id`, the
+-- URL of the registry's index and the name of the crate in it are stored
+-- instead.
+ALTER TABLE dependencies
+    ADD COLUMN explicit_name VARCHAR,
+    ADD COLUMN registry VARCHAR,
+    ADD COLUMN registry_crate_name VARCHAR,
+    ALTER COLUMN crate_id DROP NOT NULL,
+    ADD CONSTRAINT dependencies_crate_or_registry CHECK (
+        (crate_id IS NOT NULL AND registry IS NULL AND registry_crate_name IS NULL)
+        OR (crate_id IS NULL AND registry IS NOT NULL AND registry_crate_name IS NOT NULL)
+    );
@@ -0,0 +1,11 @@
+-- Names of crates deleted by their owners, which can't be used for a new
+-- crate until `available_at`.
+CREATE TABLE deleted_crates (
+    id              SERIAL PRIMARY KEY,
+    name            VARCHAR NOT NULL,
+    deleted_by      INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    deleted_at      TIMESTAMP NOT NULL DEFAULT now(),
+    available_at    TIMESTAMP NOT NULL
+);
+
+CREATE INDEX deleted_crates_canon_name ON deleted_crates (canon_crate_name(name));
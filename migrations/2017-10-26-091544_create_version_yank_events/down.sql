@@ -0,0 +1,2 @@
+DROP TABLE version_yank_events;
+ALTER TABLE versions DROP COLUMN yank_reason;
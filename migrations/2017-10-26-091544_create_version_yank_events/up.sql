@@ -0,0 +1,13 @@
+ALTER TABLE versions ADD COLUMN yank_reason VARCHAR;
+
+-- Every yank and unyank of a version, newest last.
+CREATE TABLE version_yank_events (
+    id          SERIAL PRIMARY KEY,
+    version_id  INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    user_id     INTEGER NOT NULL REFERENCES users (id),
+    yanked      BOOLEAN NOT NULL,
+    reason      VARCHAR,
+    created_at  TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX version_yank_events_version_id ON version_yank_events (version_id);
@@ -0,0 +1,8 @@
+ALTER TABLE versions ADD COLUMN checksum VARCHAR;
+
+-- Versions published since publish jobs were introduced already have their
+-- checksum in the index entry of their job. Older versions are backfilled
+-- from the git index by the `backfill-version-checksums` binary.
+UPDATE versions SET checksum = publish_jobs.index_entry->>'cksum'
+    FROM publish_jobs
+    WHERE publish_jobs.version_id = versions.id;
@@ -2,22 +2,22 @@
 
 use std::env;
 use std::error::Error;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use conduit::{Request, Response};
 use conduit_middleware::Middleware;
-use git2;
 use oauth2;
 use r2d2;
 use curl::easy::Easy;
 use scheduled_thread_pool::ScheduledThreadPool;
 
+use index::{CoalescingIndex, IndexBackend};
+use storage::Storage;
 use {db, Config};
 
 /// The `App` struct holds the main components of the application like
 /// the database connection pool and configurations
-// The db, oauth, and git2 types don't implement debug.
+// The db, oauth, index and storage types don't implement debug.
 #[allow(missing_debug_implementations)]
 pub struct App {
     /// The database connection pool
@@ -29,11 +29,11 @@ pub struct App {
     /// A unique key used with conduit_cookie to generate cookies
     pub session_key: String,
 
-    /// The crate index git repository
-    pub git_repo: Mutex<git2::Repository>,
+    /// The registry index
+    pub index: Box<IndexBackend>,
 
-    /// The location on disk of the checkout of the crate index git repository
-    pub git_repo_checkout: PathBuf,
+    /// Where crate files and readmes are uploaded
+    pub storage: Box<Storage>,
 
     /// The server configuration
     pub config: Config,
@@ -53,7 +53,9 @@ impl App {
     ///
     /// - GitHub OAuth
     /// - Database connection pools
-    /// - A `git2::Repository` instance from the index repo checkout (that server.rs ensures exists)
+    /// - The index backend selected by `Config::index`, batching updates if
+    ///   `Config::index_batch` is set
+    /// - The storage selected by `Config::storage`
     pub fn new(config: &Config) -> App {
         let mut github = oauth2::Config::new(
             &config.gh_client_id,
@@ -88,26 +90,28 @@ impl App {
             .min_idle(db_min_idle)
             .thread_pool(thread_pool);
 
-        let repo = git2::Repository::open(&config.git_repo_checkout).unwrap();
+        let mut index = config.index.open().unwrap();
+        if let Some(batch) = config.index_batch {
+            index = Box::new(CoalescingIndex::new(index, batch));
+        }
 
         App {
             diesel_database: db::diesel_pool(&config.db_url, diesel_db_config),
             github: github,
             session_key: config.session_key.clone(),
-            git_repo: Mutex::new(repo),
-            git_repo_checkout: config.git_repo_checkout.clone(),
+            index: index,
+            storage: config.storage.open(),
             config: config.clone(),
         }
     }
 
-    /// Returns a handle for making HTTP requests to upload crate files.
+    /// Returns a handle for making HTTP requests to other services.
     ///
-    /// The handle will go through a proxy if the uploader being used has specified one, which
-    /// is only done in test mode in order to be able to record and inspect the HTTP requests
-    /// that tests make.
+    /// The handle will go through `Config::http_proxy` if it is set, which is only done in test
+    /// mode in order to be able to record and inspect the HTTP requests that tests make.
     pub fn handle(&self) -> Easy {
         let mut handle = Easy::new();
-        if let Some(proxy) = self.config.uploader.proxy() {
+        if let Some(ref proxy) = self.config.http_proxy {
             handle.proxy(proxy).unwrap();
         }
         handle
@@ -0,0 +1,408 @@
+//! A Postgres-backed queue for work that doesn't need to happen while a
+//! request is being served.
+//!
+//! Jobs are stored in the `background_jobs` table as a `job_type` and a JSON
+//! payload. Each kind of job is a type implementing `Job`, registered with the
+//! `Runner` which polls the table from a `ScheduledThreadPool`.
+//!
+//! A job that returns an error is retried with exponential backoff. After
+//! `MAX_RETRIES` failed attempts it is marked as dead and kept in the table so
+//! that it shows up in the `GET /admin/background_jobs` listing.
+//!
+//! Kinds of jobs which are cheaper to run together, such as adding versions
+//! to the index, can set `Job::BATCH_SIZE` to be claimed and performed
+//! several at a time.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use conduit::{Request, Response};
+use diesel;
+use diesel::dsl::now;
+use diesel::prelude::*;
+use scheduled_thread_pool::ScheduledThreadPool;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use app::App;
+use db::RequestTransaction;
+use pagination::Paginate;
+use krate::delete::PurgeDeletedCrate;
+use publish_job::AddCrateToIndex;
+use schema::background_jobs;
+use user::RequestUser;
+use util::errors::CargoError;
+use util::{internal, CargoResult, RequestUtils};
+
+/// How many times a job is attempted before it is marked as dead.
+pub const MAX_RETRIES: i32 = 8;
+
+/// How long each worker sleeps between two polls of the `background_jobs`
+/// table.
+const POLL_INTERVAL_SECS: u64 = 1;
+
+/// Selects the jobs which are due. A job which has failed `n` times waits
+/// `2^n - 1` minutes after its last attempt before being tried again.
+const DUE_JOBS: &str = "NOT dead \
+                        AND last_retry + INTERVAL '1 minute' * (POWER(2, retries) - 1) <= now()";
+
+/// A kind of work which can be deferred to the `Runner`.
+///
+/// The job itself is serialized into the `data` column, so it should only
+/// hold what's needed to find its inputs again (usually ids).
+pub trait Job: Serialize + DeserializeOwned {
+    /// The value of the `job_type` column for this kind of job. This must be
+    /// unique among the registered jobs, and shouldn't change once jobs of
+    /// this type have been enqueued.
+    const JOB_TYPE: &'static str;
+
+    /// How many jobs of this type are claimed at once and handed to
+    /// `perform_all`.
+    const BATCH_SIZE: usize = 1;
+
+    /// Does the work. This runs in its own transaction, which is rolled back
+    /// if an error is returned.
+    fn perform(self, app: &App, conn: &PgConnection) -> CargoResult<()>;
+
+    /// Does the work of several jobs, returning the result of each of them in
+    /// order. Each job is retried on its own if it fails.
+    ///
+    /// By default each job is performed in its own transaction. Overriding
+    /// this allows sharing work between the jobs, in which case the jobs are
+    /// responsible for not leaving changes behind when they fail.
+    fn perform_all(jobs: Vec<Self>, app: &App, conn: &PgConnection) -> Vec<CargoResult<()>> {
+        jobs.into_iter()
+            .map(|job| conn.transaction::<_, Box<CargoError>, _>(|| job.perform(app, conn)))
+            .collect()
+    }
+
+    /// Called once the job has failed for the last time. `error` is the
+    /// error returned by the final attempt.
+    fn dead(self, _app: &App, _conn: &PgConnection, _error: &str) -> CargoResult<()> {
+        Ok(())
+    }
+
+    /// Adds this job to the queue. If `conn` is in a transaction the job will
+    /// only be visible to the workers once it commits.
+    fn enqueue(&self, conn: &PgConnection) -> CargoResult<BackgroundJob> {
+        let new_job = NewBackgroundJob {
+            job_type: Self::JOB_TYPE,
+            data: serde_json::to_value(self)?,
+        };
+
+        diesel::insert_into(background_jobs::table)
+            .values(&new_job)
+            .get_result(conn)
+            .map_err(Into::into)
+    }
+}
+
+#[derive(Queryable, QueryableByName, Identifiable, Debug)]
+#[table_name = "background_jobs"]
+pub struct BackgroundJob {
+    pub id: i32,
+    pub job_type: String,
+    pub data: serde_json::Value,
+    pub retries: i32,
+    pub last_retry: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub dead: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "background_jobs"]
+struct NewBackgroundJob {
+    job_type: &'static str,
+    data: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodableBackgroundJob {
+    pub id: i32,
+    pub job_type: String,
+    pub data: serde_json::Value,
+    pub retries: i32,
+    #[serde(with = "::util::rfc3339")] pub last_retry: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub dead: bool,
+    #[serde(with = "::util::rfc3339")] pub created_at: NaiveDateTime,
+}
+
+impl BackgroundJob {
+    pub fn encodable(self) -> EncodableBackgroundJob {
+        let BackgroundJob {
+            id,
+            job_type,
+            data,
+            retries,
+            last_retry,
+            last_error,
+            dead,
+            created_at,
+        } = self;
+        EncodableBackgroundJob {
+            id: id,
+            job_type: job_type,
+            data: data,
+            retries: retries,
+            last_retry: last_retry,
+            last_error: last_error,
+            dead: dead,
+            created_at: created_at,
+        }
+    }
+}
+
+/// The type-erased entry points of a registered `Job`.
+#[derive(Clone, Copy)]
+struct Handler {
+    batch_size: usize,
+    perform_all: fn(Vec<serde_json::Value>, &App, &PgConnection) -> Vec<CargoResult<()>>,
+    dead: fn(serde_json::Value, &App, &PgConnection, &str) -> CargoResult<()>,
+}
+
+fn perform_all<J: Job>(
+    data: Vec<serde_json::Value>,
+    app: &App,
+    conn: &PgConnection,
+) -> Vec<CargoResult<()>> {
+    // Jobs which can't be deserialized fail on their own, without being
+    // handed to `perform_all`.
+    let mut results: Vec<Option<CargoResult<()>>> = Vec::new();
+    let mut jobs = Vec::new();
+    for data in data {
+        match serde_json::from_value::<J>(data) {
+            Ok(job) => {
+                jobs.push(job);
+                results.push(None);
+            }
+            Err(e) => results.push(Some(Err(e.into()))),
+        }
+    }
+    let mut performed = J::perform_all(jobs, app, conn).into_iter();
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| performed.next().unwrap()))
+        .collect()
+}
+
+fn dead<J: Job>(
+    data: serde_json::Value,
+    app: &App,
+    conn: &PgConnection,
+    error: &str,
+) -> CargoResult<()> {
+    serde_json::from_value::<J>(data)?.dead(app, conn, error)
+}
+
+/// Pulls jobs out of the `background_jobs` table and runs them.
+// Can't derive Debug because `App` can't.
+#[allow(missing_debug_implementations)]
+pub struct Runner {
+    app: Arc<App>,
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Runner {
+    /// Creates a runner which knows about every job defined in this crate.
+    pub fn new(app: Arc<App>) -> Self {
+        let mut runner = Runner {
+            app: app,
+            handlers: HashMap::new(),
+        };
+        runner.register::<AddCrateToIndex>();
+        runner.register::<PurgeDeletedCrate>();
+        runner
+    }
+
+    /// Allows jobs of type `J` to be run.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another job was already registered with the same
+    /// `JOB_TYPE`.
+    pub fn register<J: Job>(&mut self) {
+        let handler = Handler {
+            batch_size: J::BATCH_SIZE,
+            perform_all: perform_all::<J>,
+            dead: dead::<J>,
+        };
+        if self.handlers.insert(J::JOB_TYPE, handler).is_some() {
+            panic!("job type `{}` was registered twice", J::JOB_TYPE);
+        }
+    }
+
+    /// Runs every job which is currently due, oldest first, and returns how
+    /// many were attempted.
+    ///
+    /// Jobs are claimed with `FOR UPDATE SKIP LOCKED` so that several workers
+    /// (or several instances of the server) can poll the same table without
+    /// running the same job twice.
+    pub fn run_pending(&self) -> CargoResult<usize> {
+        let conn = self.app.diesel_database.get()?;
+        let mut attempted = 0;
+        loop {
+            match self.run_next(&conn)? {
+                0 => return Ok(attempted),
+                n => attempted += n,
+            }
+        }
+    }
+
+    /// Runs the oldest job which is due, along with the next ones of the
+    /// same type if it is run in batches, and returns how many were
+    /// attempted.
+    fn run_next(&self, conn: &PgConnection) -> CargoResult<usize> {
+        use diesel::sql_query;
+        use diesel::types::{BigInt, Integer, Text};
+
+        conn.transaction::<_, Box<CargoError>, _>(|| {
+            let job = sql_query(format!(
+                "SELECT * FROM background_jobs WHERE {} \
+                 ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED",
+                DUE_JOBS
+            )).load::<BackgroundJob>(conn)?
+                .pop();
+            let job = match job {
+                Some(job) => job,
+                None => return Ok(0),
+            };
+
+            let handler = self.handlers.get(&*job.job_type).cloned();
+            let batch_size = handler.map_or(1, |handler| handler.batch_size);
+            let mut jobs = vec![job];
+            if batch_size > 1 {
+                let more = sql_query(format!(
+                    "SELECT * FROM background_jobs \
+                     WHERE job_type = $1 AND id > $2 AND {} \
+                     ORDER BY id LIMIT $3 FOR UPDATE SKIP LOCKED",
+                    DUE_JOBS
+                )).bind::<Text, _>(&jobs[0].job_type)
+                    .bind::<Integer, _>(jobs[0].id)
+                    .bind::<BigInt, _>((batch_size - 1) as i64)
+                    .load::<BackgroundJob>(conn)?;
+                jobs.extend(more);
+            }
+
+            let results = match handler {
+                Some(handler) => {
+                    let data = jobs.iter().map(|job| job.data.clone()).collect();
+                    (handler.perform_all)(data, &self.app, conn)
+                }
+                None => vec![
+                    Err(internal(&format_args!(
+                        "unknown job type `{}`",
+                        jobs[0].job_type
+                    ))),
+                ],
+            };
+
+            for (job, result) in jobs.iter().zip(results) {
+                self.finish(conn, job, handler, result)?;
+            }
+            Ok(jobs.len())
+        })
+    }
+
+    /// Deletes a job which succeeded, or schedules its next attempt.
+    fn finish(
+        &self,
+        conn: &PgConnection,
+        job: &BackgroundJob,
+        handler: Option<Handler>,
+        result: CargoResult<()>,
+    ) -> CargoResult<()> {
+        let error = match result {
+            Ok(()) => {
+                diesel::delete(job).execute(conn)?;
+                return Ok(());
+            }
+            Err(e) => e.to_string(),
+        };
+
+        println!(
+            "background job {} (`{}`) failed: {}",
+            job.id,
+            job.job_type,
+            error
+        );
+        let is_dead = job.retries + 1 >= MAX_RETRIES;
+        diesel::update(job)
+            .set((
+                background_jobs::retries.eq(background_jobs::retries + 1),
+                background_jobs::last_retry.eq(now),
+                background_jobs::last_error.eq(&error),
+                background_jobs::dead.eq(is_dead),
+            ))
+            .execute(conn)?;
+        if is_dead {
+            if let Some(handler) = handler {
+                (handler.dead)(job.data.clone(), &self.app, conn, &error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts `threads` workers which poll for jobs until the returned pool
+    /// is dropped.
+    pub fn start(self, threads: usize) -> ScheduledThreadPool {
+        let pool = ScheduledThreadPool::with_name("background-job-{}", threads);
+        let runner = Arc::new(self);
+        for _ in 0..threads {
+            let runner = Arc::clone(&runner);
+            pool.execute_with_fixed_delay(
+                Duration::from_secs(0),
+                Duration::from_secs(POLL_INTERVAL_SECS),
+                move || if let Err(e) = runner.run_pending() {
+                    println!("failed to run background jobs: {}", e);
+                },
+            );
+        }
+        pool
+    }
+}
+
+/// Handles the `GET /admin/background_jobs` route.
+///
+/// Lists the jobs that haven't completed yet, newest first. Passing
+/// `?dead=true` or `?dead=false` only lists the dead or live ones.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    req.user()?.require_admin()?;
+
+    let conn = req.db_conn()?;
+    let (offset, limit) = req.pagination(20, 100)?;
+
+    let mut query = background_jobs::table
+        .order(background_jobs::id.desc())
+        .into_boxed();
+    if let Some(dead) = req.query().get("dead") {
+        query = query.filter(background_jobs::dead.eq(dead == "true"));
+    }
+
+    let data = query
+        .paginate(limit, offset)
+        .load::<(BackgroundJob, i64)>(&*conn)?;
+    let total = data.get(0).map(|&(_, t)| t).unwrap_or(0);
+    let jobs = data.into_iter()
+        .map(|(job, _)| job.encodable())
+        .collect::<Vec<_>>();
+
+    #[derive(Serialize)]
+    struct R {
+        background_jobs: Vec<EncodableBackgroundJob>,
+        meta: Meta,
+    }
+    #[derive(Serialize)]
+    struct Meta {
+        total: i64,
+    }
+
+    Ok(req.json(&R {
+        background_jobs: jobs,
+        meta: Meta { total: total },
+    }))
+}
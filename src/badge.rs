@@ -90,8 +90,7 @@ impl Badge {
             for (k, v) in badges {
                 let attributes_json = serde_json::to_value(v).unwrap();
 
-                let json = json!({"badge_type": k, "attributes": attributes_json});
-                if serde_json::from_value::<Badge>(json).is_ok() {
+                if Badge::is_valid(k, &attributes_json) {
                     new_badges.push((
                         badges::crate_id.eq(krate.id),
                         badges::badge_type.eq(k),
@@ -113,4 +112,21 @@ impl Badge {
             Ok(invalid_badges)
         })
     }
+
+    /// Returns the badges `update_crate` would ignore.
+    pub fn invalid_badges<'a>(
+        badges: Option<&'a HashMap<String, HashMap<String, String>>>,
+    ) -> Vec<&'a str> {
+        badges
+            .into_iter()
+            .flat_map(|badges| badges.iter())
+            .filter(|&(k, v)| !Badge::is_valid(k, &serde_json::to_value(v).unwrap()))
+            .map(|(k, _)| &**k)
+            .collect()
+    }
+
+    fn is_valid(badge_type: &str, attributes: &serde_json::Value) -> bool {
+        let json = json!({"badge_type": badge_type, "attributes": attributes});
+        serde_json::from_value::<Badge>(json).is_ok()
+    }
 }
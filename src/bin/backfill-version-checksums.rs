@@ -0,0 +1,98 @@
+// Iterates over every crate version which doesn't have its checksum recorded
+// yet, and records the checksum of its entry in the index.
+//
+// Versions published before checksums were recorded in the database are left
+// out of the sparse index until this has been run.
+//
+// Usage:
+//      cargo run --bin backfill-version-checksums -- [--crate NAME]
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate cargo_registry;
+extern crate diesel;
+extern crate docopt;
+extern crate itertools;
+
+use diesel::prelude::*;
+use docopt::Docopt;
+use itertools::Itertools;
+
+use cargo_registry::schema::*;
+use cargo_registry::{App, Config, Version};
+
+const USAGE: &str = "
+Usage: backfill-version-checksums [options]
+       backfill-version-checksums --help
+
+Options:
+    -h, --help         Show this message.
+    --crate NAME       Only backfill the versions of the specified crate.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_crate: Option<String>,
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let config: Config = Default::default();
+    let app = App::new(&config);
+    let conn = cargo_registry::db::connect_now().unwrap();
+
+    let mut query = versions::table
+        .inner_join(crates::table)
+        .filter(versions::checksum.is_null())
+        .select((versions::all_columns, crates::name))
+        .order((crates::name, versions::id))
+        .into_boxed();
+
+    if let Some(crate_name) = args.flag_crate {
+        println!("Backfilling checksums for {}", crate_name);
+        query = query.filter(crates::name.eq(crate_name));
+    }
+
+    let versions = query
+        .load::<(Version, String)>(&conn)
+        .expect("error loading versions");
+    println!("Backfilling {} versions", versions.len());
+
+    let mut missing = 0;
+    let by_crate = versions.into_iter().group_by(|&(_, ref name)| name.clone());
+    for (krate_name, krate_versions) in &by_crate {
+        let entries = match app.index.entries(&krate_name) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("[{}] Unable to read index file: {}", krate_name, err);
+                continue;
+            }
+        };
+        for (version, _) in krate_versions {
+            let num = version.num.to_string();
+            let entry = entries
+                .iter()
+                .find(|entry| entry.vers == num && !entry.cksum.is_empty());
+            match entry {
+                Some(entry) => {
+                    version
+                        .record_checksum(&conn, &entry.cksum)
+                        .expect(&format!("[{}-{}] Couldn't record checksum", krate_name, num));
+                }
+                None => {
+                    println!("[{}-{}] Not in the index", krate_name, num);
+                    missing += 1;
+                }
+            }
+        }
+    }
+
+    if missing > 0 {
+        println!("{} versions couldn't be backfilled", missing);
+    }
+}
@@ -0,0 +1,155 @@
+// Iterates over every crate version which doesn't have its sizes recorded yet,
+// downloads its crate file and records `crate_size` and `unpacked_size`.
+//
+// Warning: this can take a lot of time.
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate cargo_registry;
+extern crate diesel;
+extern crate docopt;
+extern crate itertools;
+
+use diesel::prelude::*;
+use diesel::dsl::any;
+use docopt::Docopt;
+use itertools::Itertools;
+use std::sync::Arc;
+use std::thread;
+
+use cargo_registry::{Config, Storage, Version};
+use cargo_registry::schema::*;
+use cargo_registry::tarball;
+
+const DEFAULT_PAGE_SIZE: usize = 25;
+const USAGE: &str = "
+Usage: backfill-version-sizes [options]
+       backfill-version-sizes --help
+
+Options:
+    -h, --help         Show this message.
+    --page-size NUM    How many versions should be queried and processed at a time.
+    --crate NAME       Only backfill the versions of the specified crate.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_page_size: Option<usize>,
+    flag_crate: Option<String>,
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let config: Config = Default::default();
+    let storage: Arc<Storage> = Arc::from(config.storage.open());
+    let conn = cargo_registry::db::connect_now().unwrap();
+
+    let mut query = versions::table
+        .inner_join(crates::table)
+        .filter(versions::crate_size.is_null())
+        .select(versions::id)
+        .into_boxed();
+
+    if let Some(crate_name) = args.flag_crate {
+        println!("Backfilling sizes for {}", crate_name);
+        query = query.filter(crates::name.eq(crate_name));
+    }
+
+    let version_ids = query
+        .load::<(i32)>(&conn)
+        .expect("error loading version ids");
+
+    let total_versions = version_ids.len();
+    println!("Backfilling {} versions", total_versions);
+
+    let page_size = args.flag_page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    let total_pages = total_versions / page_size;
+    let total_pages = if total_versions % page_size == 0 {
+        total_pages
+    } else {
+        total_pages + 1
+    };
+
+    for (page_num, version_ids_chunk) in version_ids
+        .into_iter()
+        .chunks(page_size)
+        .into_iter()
+        .enumerate()
+    {
+        println!(
+            "= Page {} of {} ==================================",
+            page_num + 1,
+            total_pages
+        );
+
+        let ids: Vec<_> = version_ids_chunk.collect();
+
+        let versions = versions::table
+            .inner_join(crates::table)
+            .filter(versions::id.eq(any(ids)))
+            .select((versions::all_columns, crates::name))
+            .load::<(Version, String)>(&conn)
+            .expect("error loading versions");
+
+        let mut tasks = Vec::with_capacity(page_size as usize);
+        for (version, krate_name) in versions {
+            let storage = Arc::clone(&storage);
+            let handle = thread::spawn(move || {
+                println!("[{}-{}] Measuring crate file...", krate_name, version.num);
+                let sizes = get_sizes(&*storage, &version, &krate_name);
+                (version, krate_name, sizes)
+            });
+            tasks.push(handle);
+        }
+        for handle in tasks {
+            match handle.join() {
+                Ok((version, krate_name, Some((crate_size, unpacked_size)))) => {
+                    version
+                        .record_sizes(&conn, crate_size, unpacked_size)
+                        .expect(&format!(
+                            "[{}-{}] Couldn't record sizes",
+                            krate_name,
+                            version.num
+                        ));
+                }
+                Ok((_, _, None)) => {}
+                Err(err) => println!("Thread panicked: {:?}", err),
+            }
+        }
+    }
+}
+
+/// Downloads the crate file of a version and returns its size and the total
+/// size of the files inside it.
+fn get_sizes(storage: &Storage, version: &Version, krate_name: &str) -> Option<(i32, i32)> {
+    let tarball = match storage.download_crate(krate_name, &version.num.to_string()) {
+        Ok(tarball) => tarball,
+        Err(err) => {
+            println!(
+                "[{}-{}] Unable to fetch crate: {}",
+                krate_name,
+                version.num,
+                err
+            );
+            return None;
+        }
+    };
+    match tarball::unpacked_size(&tarball) {
+        Ok(unpacked_size) => Some((tarball.len() as i32, unpacked_size as i32)),
+        Err(err) => {
+            println!(
+                "[{}-{}] Unable to read crate file: {}",
+                krate_name,
+                version.num,
+                err
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Compares every crate in the database with its file in the index, and
+// prints the differences as JSON. Index files with no crate in the database
+// are reported too, unless only some crates are checked. The database is the
+// source of truth: with `--repair`, the files of the crates which differ are
+// rewritten from it and the files with no crate are deleted, in a single
+// commit, checking them again so that concurrent publishes and yanks aren't
+// reverted.
+//
+// Exits with status 2 if differences were found and not repaired.
+//
+// Usage:
+//      cargo run --bin check-index -- [--repair] [crate-name...]
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate cargo_registry;
+extern crate diesel;
+extern crate docopt;
+extern crate serde_json;
+
+use diesel::prelude::*;
+use docopt::Docopt;
+use std::process;
+
+use cargo_registry::index::check::{check_crate, check_orphan, orphaned_files, repair, Discrepancy};
+use cargo_registry::schema::crates;
+use cargo_registry::util::CargoResult;
+use cargo_registry::{App, Config, Crate};
+
+const USAGE: &str = "
+Usage: check-index [options] [<crate>...]
+       check-index --help
+
+Options:
+    -h, --help    Show this message.
+    --repair      Rewrite the index files of the crates which differ from the database,
+                  and delete those with no crate in it.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    arg_crate: Vec<String>,
+    flag_repair: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    checked: usize,
+    discrepancies: Vec<Discrepancy>,
+    orphaned: Vec<String>,
+    repaired: Vec<String>,
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let config: Config = Default::default();
+    let app = App::new(&config);
+    let conn = cargo_registry::db::connect_now().unwrap();
+
+    match check(&app, &conn, &args) {
+        Ok(_) if args.flag_repair => {}
+        Ok(ref report) if report.discrepancies.is_empty() && report.orphaned.is_empty() => {}
+        Ok(_) => process::exit(2),
+        Err(e) => {
+            println!("failed to check the index: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn check(app: &App, conn: &PgConnection, args: &Args) -> CargoResult<Report> {
+    let crates = if args.arg_crate.is_empty() {
+        crates::table.order(crates::name).load::<Crate>(conn)?
+    } else {
+        crates::table
+            .filter(crates::name.eq_any(&args.arg_crate))
+            .order(crates::name)
+            .load::<Crate>(conn)?
+    };
+
+    let mut discrepancies = Vec::new();
+    let mut differing = Vec::new();
+    for krate in &crates {
+        let check = check_crate(conn, &*app.index, krate)?;
+        if !check.discrepancies.is_empty() {
+            discrepancies.extend(check.discrepancies);
+            differing.push(krate.clone());
+        }
+    }
+
+    // Crates left out of the check can't tell which files are orphaned.
+    let mut orphaned = Vec::new();
+    if args.arg_crate.is_empty() {
+        orphaned = orphaned_files(conn, &*app.index)?;
+        for name in &orphaned {
+            discrepancies.extend(check_orphan(&*app.index, name)?);
+        }
+    }
+
+    let mut repaired = Vec::new();
+    if args.flag_repair && (!differing.is_empty() || !orphaned.is_empty()) {
+        repaired = repair(conn, &*app.index, &differing, &orphaned)?;
+    }
+
+    let report = Report {
+        checked: crates.len(),
+        discrepancies: discrepancies,
+        orphaned: orphaned,
+        repaired: repaired,
+    };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    Ok(report)
+}
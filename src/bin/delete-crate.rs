@@ -1,65 +1,109 @@
-// Purge all references to a crate from the database.
+// Purge all references to a crate from the database, the index and storage.
 //
 // Please be super sure you want to do this before running this.
 //
+// The index is updated through the backend configured by `INDEX_BACKEND`,
+// see `Config::default`.
+//
 // Usage:
-//      cargo run --bin delete-crate crate-name
+//      cargo run --bin delete-crate -- [--dry-run] [--yes] crate-name
 
 #![deny(warnings)]
 
+#[macro_use]
+extern crate serde_derive;
+
 extern crate cargo_registry;
 extern crate diesel;
+extern crate docopt;
 
 use diesel::prelude::*;
-use std::env;
+use docopt::Docopt;
 use std::io;
 use std::io::prelude::*;
+use std::process;
+
+use cargo_registry::{App, Config, Crate};
+use cargo_registry::schema::{crates, versions};
+use cargo_registry::storage;
+use cargo_registry::util::{CargoError, CargoResult};
 
-use cargo_registry::Crate;
-use cargo_registry::schema::crates;
+const USAGE: &str = "
+Usage: delete-crate [options] <crate>
+       delete-crate --help
+
+Options:
+    -h, --help    Show this message.
+    --dry-run     Only print what would be deleted.
+    -y, --yes     Don't ask for confirmation before deleting.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    arg_crate: String,
+    flag_dry_run: bool,
+    flag_yes: bool,
+}
 
 #[allow(dead_code)]
 fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let config: Config = Default::default();
+    let app = App::new(&config);
     let conn = cargo_registry::db::connect_now().unwrap();
-    conn.transaction::<_, diesel::result::Error, _>(|| {
-        delete(&conn);
-        Ok(())
-    }).unwrap()
+
+    if let Err(e) = delete(&app, &conn, &args) {
+        println!("failed to delete {}: {}", args.arg_crate, e);
+        process::exit(1);
+    }
 }
 
-fn delete(conn: &PgConnection) {
-    let name = match env::args().nth(1) {
-        None => {
-            println!("needs a crate-name argument");
-            return;
-        }
-        Some(s) => s,
-    };
-
-    let krate = Crate::by_name(&name).first::<Crate>(conn).unwrap();
-    print!(
-        "Are you sure you want to delete {} ({}) [y/N]: ",
-        name,
-        krate.id
-    );
-    io::stdout().flush().unwrap();
-    let mut line = String::new();
-    io::stdin().read_line(&mut line).unwrap();
-    if !line.starts_with('y') {
-        return;
+fn delete(app: &App, conn: &PgConnection, args: &Args) -> CargoResult<()> {
+    let krate = Crate::by_name(&args.arg_crate).first::<Crate>(conn)?;
+    let versions = versions::table
+        .filter(versions::crate_id.eq(krate.id))
+        .select(versions::num)
+        .order(versions::id)
+        .load::<String>(conn)?;
+
+    println!("crate {} ({}), with {} versions:", krate.name, krate.id, versions.len());
+    for version in &versions {
+        println!("  {}", version);
+        println!("    {}", storage::crate_path(&krate.name, version));
+        println!("    {}", storage::readme_path(&krate.name, version));
+    }
+
+    if args.flag_dry_run {
+        println!("dry run, nothing was deleted");
+        return Ok(());
+    }
+    if !args.flag_yes && !confirm(&format!("Are you sure you want to delete {}", krate.name)) {
+        return Ok(());
     }
 
-    println!("deleting the crate");
-    let n = diesel::delete(crates::table.find(krate.id))
-        .execute(conn)
-        .unwrap();
-    println!("  {} deleted", n);
+    // The crate is only removed from the database once it is gone from the
+    // index, so that a failed push can simply be retried.
+    conn.transaction::<_, Box<CargoError>, _>(|| {
+        diesel::delete(crates::table.find(krate.id)).execute(conn)?;
+        app.index.remove_crate(&krate.name)?;
+        Ok(())
+    })?;
+    println!("deleted {} and its versions from the database", krate.name);
+    println!("removed {} from the index", krate.name);
 
-    print!("commit? [y/N]: ");
+    for version in &versions {
+        app.storage.delete_version(&krate.name, version)?;
+    }
+    println!("deleted the files of {} versions from storage", versions.len());
+    Ok(())
+}
+
+fn confirm(msg: &str) -> bool {
+    print!("{} [y/N]: ", msg);
     io::stdout().flush().unwrap();
     let mut line = String::new();
     io::stdin().read_line(&mut line).unwrap();
-    if !line.starts_with('y') {
-        panic!("aborting transaction");
-    }
+    line.starts_with('y')
 }
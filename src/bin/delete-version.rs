@@ -1,76 +1,111 @@
-// Purge all references to a crate's version from the database.
+// Purge all references to a crate's version from the database, the index and
+// storage.
 //
 // Please be super sure you want to do this before running this.
 //
+// The index is updated through the backend configured by `INDEX_BACKEND`,
+// see `Config::default`.
+//
 // Usage:
-//      cargo run --bin delete-version crate-name version-number
+//      cargo run --bin delete-version -- [--dry-run] [--yes] crate-name version-number
 
 #![deny(warnings)]
 
+#[macro_use]
+extern crate serde_derive;
+
 extern crate cargo_registry;
 extern crate diesel;
+extern crate docopt;
 
 use diesel::prelude::*;
-use std::env;
+use docopt::Docopt;
 use std::io;
 use std::io::prelude::*;
+use std::process;
 
-use cargo_registry::{Crate, Version};
+use cargo_registry::{App, Config, Crate, Version};
 use cargo_registry::schema::versions;
+use cargo_registry::storage;
+use cargo_registry::util::{CargoError, CargoResult};
+
+const USAGE: &str = "
+Usage: delete-version [options] <crate> <version>
+       delete-version --help
+
+Options:
+    -h, --help    Show this message.
+    --dry-run     Only print what would be deleted.
+    -y, --yes     Don't ask for confirmation before deleting.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    arg_crate: String,
+    arg_version: String,
+    flag_dry_run: bool,
+    flag_yes: bool,
+}
 
 #[allow(dead_code)]
 fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let config: Config = Default::default();
+    let app = App::new(&config);
     let conn = cargo_registry::db::connect_now().unwrap();
-    conn.transaction::<_, diesel::result::Error, _>(|| {
-        delete(&conn);
-        Ok(())
-    }).unwrap()
+
+    if let Err(e) = delete(&app, &conn, &args) {
+        println!(
+            "failed to delete {}#{}: {}",
+            args.arg_crate,
+            args.arg_version,
+            e
+        );
+        process::exit(1);
+    }
 }
 
-fn delete(conn: &PgConnection) {
-    let name = match env::args().nth(1) {
-        None => {
-            println!("needs a crate-name argument");
-            return;
-        }
-        Some(s) => s,
-    };
-    let version = match env::args().nth(2) {
-        None => {
-            println!("needs a version argument");
-            return;
-        }
-        Some(s) => s,
-    };
-
-    let krate = Crate::by_name(&name).first::<Crate>(conn).unwrap();
+fn delete(app: &App, conn: &PgConnection, args: &Args) -> CargoResult<()> {
+    let krate = Crate::by_name(&args.arg_crate).first::<Crate>(conn)?;
     let v = Version::belonging_to(&krate)
-        .filter(versions::num.eq(&version))
-        .first::<Version>(conn)
-        .unwrap();
-    print!(
-        "Are you sure you want to delete {}#{} ({}) [y/N]: ",
-        name,
-        version,
-        v.id
-    );
-    io::stdout().flush().unwrap();
-    let mut line = String::new();
-    io::stdin().read_line(&mut line).unwrap();
-    if !line.starts_with('y') {
-        return;
+        .filter(versions::num.eq(&args.arg_version))
+        .first::<Version>(conn)?;
+    let num = v.num.to_string();
+
+    println!("version {}#{} ({}):", krate.name, num, v.id);
+    println!("  {}", storage::crate_path(&krate.name, &num));
+    println!("  {}", storage::readme_path(&krate.name, &num));
+
+    if args.flag_dry_run {
+        println!("dry run, nothing was deleted");
+        return Ok(());
+    }
+    let msg = format!("Are you sure you want to delete {}#{}", krate.name, num);
+    if !args.flag_yes && !confirm(&msg) {
+        return Ok(());
     }
 
-    println!("deleting version {} ({})", v.num, v.id);
-    diesel::delete(versions::table.find(&v.id))
-        .execute(conn)
-        .unwrap();
+    // The version is only removed from the database once it is gone from the
+    // index, so that a failed push can simply be retried.
+    conn.transaction::<_, Box<CargoError>, _>(|| {
+        diesel::delete(versions::table.find(v.id)).execute(conn)?;
+        app.index.remove_version(&krate.name, &num)?;
+        Ok(())
+    })?;
+    println!("deleted {}#{} from the database", krate.name, num);
+    println!("removed {}#{} from the index", krate.name, num);
+
+    app.storage.delete_version(&krate.name, &num)?;
+    println!("deleted the files of {}#{} from storage", krate.name, num);
+    Ok(())
+}
 
-    print!("commit? [y/N]: ");
+fn confirm(msg: &str) -> bool {
+    print!("{} [y/N]: ", msg);
     io::stdout().flush().unwrap();
     let mut line = String::new();
     io::stdin().read_line(&mut line).unwrap();
-    if !line.starts_with('y') {
-        panic!("aborting transaction");
-    }
+    line.starts_with('y')
 }
@@ -10,16 +10,13 @@ extern crate serde_derive;
 
 extern crate cargo_registry;
 extern crate chrono;
-extern crate curl;
 extern crate diesel;
 extern crate docopt;
 extern crate flate2;
 extern crate itertools;
 extern crate tar;
 extern crate toml;
-extern crate url;
 
-use curl::easy::{Easy, List};
 use chrono::{TimeZone, Utc};
 use diesel::prelude::*;
 use diesel::dsl::any;
@@ -28,13 +25,14 @@ use flate2::read::GzDecoder;
 use itertools::Itertools;
 use std::io::{Cursor, Read};
 use std::path::Path;
+use std::sync::Arc;
 use std::thread;
 use tar::Archive;
-use url::Url;
 
-use cargo_registry::{Config, Version};
+use cargo_registry::{Config, Storage, Version};
 use cargo_registry::schema::*;
 use cargo_registry::render::readme_to_html;
+use cargo_registry::storage::readme_path;
 
 const DEFAULT_PAGE_SIZE: usize = 25;
 const USAGE: &str = "
@@ -60,6 +58,7 @@ fn main() {
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
     let config: Config = Default::default();
+    let storage: Arc<Storage> = Arc::from(config.storage.open());
     let conn = cargo_registry::db::connect_now().unwrap();
 
     let start_time = Utc::now();
@@ -129,7 +128,7 @@ fn main() {
 
         let mut tasks = Vec::with_capacity(page_size as usize);
         for (version, krate_name) in versions {
-            let config = config.clone();
+            let storage = Arc::clone(&storage);
             version.record_readme_rendering(&conn).expect(&format!(
                 "[{}-{}] Couldn't record rendering time",
                 krate_name,
@@ -137,24 +136,16 @@ fn main() {
             ));
             let handle = thread::spawn(move || {
                 println!("[{}-{}] Rendering README...", krate_name, version.num);
-                let readme = get_readme(&config, &version, &krate_name);
+                let readme = get_readme(&*storage, &version, &krate_name);
                 if readme.is_none() {
                     return;
                 }
                 let readme = readme.unwrap();
-                let readme_path = format!("readmes/{0}/{0}-{1}.html", krate_name, version.num);
-                let readme_len = readme.len();
-                config
-                    .uploader
-                    .upload(
-                        Easy::new(),
-                        &readme_path,
-                        readme.as_bytes(),
-                        "text/html",
-                        readme_len as u64,
-                    )
+                let path = readme_path(&krate_name, &version.num.to_string());
+                storage
+                    .put(&path, readme.as_bytes(), "text/html")
                     .expect(&format!(
-                        "[{}-{}] Couldn't upload file to S3",
+                        "[{}-{}] Couldn't upload file to storage",
                         krate_name,
                         version.num
                     ));
@@ -170,40 +161,10 @@ fn main() {
 }
 
 /// Renders the readme of an uploaded crate version.
-fn get_readme(config: &Config, version: &Version, krate_name: &str) -> Option<String> {
-    let mut handle = Easy::new();
-    let location = match config
-        .uploader
-        .crate_location(krate_name, &version.num.to_string())
-    {
-        Some(l) => l,
-        None => return None,
-    };
-    let date = Utc::now().to_rfc2822();
-    let url = Url::parse(&location).expect(&format!(
-        "[{}-{}] Couldn't parse crate URL",
-        krate_name,
-        version.num
-    ));
-
-    let mut headers = List::new();
-    headers
-        .append(&format!("Host: {}", url.host().unwrap()))
-        .unwrap();
-    headers.append(&format!("Date: {}", date)).unwrap();
-
-    handle.url(url.as_str()).unwrap();
-    handle.get(true).unwrap();
-    handle.http_headers(headers).unwrap();
-
-    let mut response = Vec::new();
-    {
-        let mut req = handle.transfer();
-        req.write_function(|data| {
-            response.extend(data);
-            Ok(data.len())
-        }).unwrap();
-        if let Err(err) = req.perform() {
+fn get_readme(storage: &Storage, version: &Version, krate_name: &str) -> Option<String> {
+    let response = match storage.download_crate(krate_name, &version.num.to_string()) {
+        Ok(response) => response,
+        Err(err) => {
             println!(
                 "[{}-{}] Unable to fetch crate: {}",
                 krate_name,
@@ -212,17 +173,7 @@ fn get_readme(config: &Config, version: &Version, krate_name: &str) -> Option<St
             );
             return None;
         }
-    }
-    if handle.response_code().unwrap() != 200 {
-        let response = String::from_utf8_lossy(&response);
-        println!(
-            "[{}-{}] Failed to get a 200 response: {}",
-            krate_name,
-            version.num,
-            response
-        );
-        return None;
-    }
+    };
     let reader = Cursor::new(response);
     let reader = GzDecoder::new(reader).expect(&format!(
         "[{}-{}] Invalid gzip header",
@@ -6,6 +6,7 @@ extern crate env_logger;
 extern crate git2;
 
 use cargo_registry::{env, Env};
+use cargo_registry::index::{self, IndexConfig, RemoteGitIndex};
 use civet::Server;
 use std::env;
 use std::fs::{self, File};
@@ -20,33 +21,61 @@ fn main() {
 
     // If there isn't a git checkout containing the crate index repo at the path specified
     // by `GIT_REPO_CHECKOUT`, delete that directory and clone the repo specified by `GIT_REPO_URL`
-    // into that directory instead. Uses the credentials specified in `GIT_HTTP_USER` and
-    // `GIT_HTTP_PWD` via the `cargo_registry::git::credentials` function.
-    let url = env("GIT_REPO_URL");
-    let repo = match git2::Repository::open(&config.git_repo_checkout) {
-        Ok(r) => r,
-        Err(..) => {
-            let _ = fs::remove_dir_all(&config.git_repo_checkout);
-            fs::create_dir_all(&config.git_repo_checkout).unwrap();
-            let mut cb = git2::RemoteCallbacks::new();
-            cb.credentials(cargo_registry::git::credentials);
-            let mut opts = git2::FetchOptions::new();
-            opts.remote_callbacks(cb);
-            git2::build::RepoBuilder::new()
-                .fetch_options(opts)
-                .clone(&url, &config.git_repo_checkout)
-                .unwrap()
+    // into that directory instead, authenticating as configured by `GIT_AUTH` (see
+    // `cargo_registry::git::Credentials`). The other index backends don't need anything set up
+    // beforehand.
+    if let IndexConfig::RemoteGit {
+        ref checkout,
+        ref credentials,
+    } = config.index
+    {
+        let url = env("GIT_REPO_URL");
+        let repo = match git2::Repository::open(checkout) {
+            Ok(r) => r,
+            Err(..) => {
+                let _ = fs::remove_dir_all(checkout);
+                fs::create_dir_all(checkout).unwrap();
+                let mut opts = git2::FetchOptions::new();
+                opts.remote_callbacks(credentials.callbacks());
+                git2::build::RepoBuilder::new()
+                    .fetch_options(opts)
+                    .clone(&url, checkout)
+                    .unwrap()
+            }
+        };
+
+        // All commits to the index registry made through crates.io will be made by bors, the
+        // Rust community's friendly GitHub bot.
+        let mut cfg = repo.config().unwrap();
+        cfg.set_str("user.name", "bors").unwrap();
+        cfg.set_str("user.email", "bors@rust-lang.org").unwrap();
+
+        // Refuse to start rather than failing every publish if the remote can't be pushed to.
+        let index = RemoteGitIndex::open(checkout, credentials.clone()).unwrap();
+        if let Err(e) = index.check_remote() {
+            panic!("the index at `{}` isn't usable: {}", url, e);
         }
-    };
+    }
+
+    let app = Arc::new(cargo_registry::App::new(&config));
+
+    // Make sure cargo is pointed at this server, see `Config::domain_name`.
+    if let Some(expected) = config.registry_config() {
+        if let Err(e) = index::sync_config(&*app.index, &expected, config.repair_index_config) {
+            panic!("{} (set `REPAIR_INDEX_CONFIG` to replace it)", e);
+        }
+    }
 
-    // All commits to the index registry made through crates.io will be made by bors, the Rust
-    // community's friendly GitHub bot.
-    let mut cfg = repo.config().unwrap();
-    cfg.set_str("user.name", "bors").unwrap();
-    cfg.set_str("user.email", "bors@rust-lang.org").unwrap();
+    // Work that doesn't need to happen during a request, such as pushing newly published
+    // versions to the index, is picked up from the `background_jobs` table by these workers.
+    // The versions published since the last poll are pushed as a single commit.
+    let job_threads = env::var("BACKGROUND_JOB_THREADS")
+        .ok()
+        .map(|s| s.parse().expect("couldn't parse BACKGROUND_JOB_THREADS"))
+        .unwrap_or(1);
+    let _jobs = cargo_registry::background_job::Runner::new(Arc::clone(&app)).start(job_threads);
 
-    let app = cargo_registry::App::new(&config);
-    let app = cargo_registry::middleware(Arc::new(app));
+    let app = cargo_registry::middleware(app);
 
     // On every server restart, ensure the categories available in the database match
     // the information in *src/categories.toml*.
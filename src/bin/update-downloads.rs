@@ -138,7 +138,7 @@ mod test {
         let krate = NewCrate {
             name: "foo",
             ..Default::default()
-        }.create_or_update(&conn, None, user_id)
+        }.create_or_update(&conn, None, user_id, |_| Ok(false))
             .unwrap();
         let version = NewVersion::new(
             krate.id,
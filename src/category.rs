@@ -75,17 +75,8 @@ impl Category {
         krate: &Crate,
         slugs: &[&'a str],
     ) -> QueryResult<Vec<&'a str>> {
-        use diesel::dsl::any;
-
         conn.transaction(|| {
-            let categories = categories::table
-                .filter(categories::slug.eq(any(slugs)))
-                .load::<Category>(conn)?;
-            let invalid_categories = slugs
-                .iter()
-                .cloned()
-                .filter(|s| !categories.iter().any(|c| c.slug == *s))
-                .collect();
+            let (categories, invalid_categories) = Category::by_slugs(conn, slugs)?;
             let crate_categories = categories
                 .iter()
                 .map(|c| {
@@ -104,6 +95,29 @@ impl Category {
         })
     }
 
+    /// Returns the slugs of `slugs` which aren't categories, the ones
+    /// `update_crate` would ignore.
+    pub fn invalid_slugs<'a>(conn: &PgConnection, slugs: &[&'a str]) -> QueryResult<Vec<&'a str>> {
+        Ok(Category::by_slugs(conn, slugs)?.1)
+    }
+
+    fn by_slugs<'a>(
+        conn: &PgConnection,
+        slugs: &[&'a str],
+    ) -> QueryResult<(Vec<Category>, Vec<&'a str>)> {
+        use diesel::dsl::any;
+
+        let categories = categories::table
+            .filter(categories::slug.eq(any(slugs)))
+            .load::<Category>(conn)?;
+        let invalid_slugs = slugs
+            .iter()
+            .cloned()
+            .filter(|s| !categories.iter().any(|c| c.slug == *s))
+            .collect();
+        Ok((categories, invalid_slugs))
+    }
+
     pub fn count_toplevel(conn: &PgConnection) -> QueryResult<i64> {
         use self::categories::dsl::*;
 
@@ -2,14 +2,20 @@ use s3;
 
 use std::env;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use {env, Env, Replica, Uploader};
+use git::Credentials;
+use index::{BatchConfig, IndexConfig, RegistryConfig};
+use publish_rate_limit::PublishRateLimit;
+use storage::StorageConfig;
+use {env, Env, Replica};
 
 #[derive(Clone, Debug)]
 pub struct Config {
-    pub uploader: Uploader,
+    pub storage: StorageConfig,
     pub session_key: String,
-    pub git_repo_checkout: PathBuf,
+    pub index: IndexConfig,
+    pub index_batch: Option<BatchConfig>,
     pub gh_client_id: String,
     pub gh_client_secret: String,
     pub db_url: String,
@@ -18,6 +24,13 @@ pub struct Config {
     pub max_unpack_size: u64,
     pub mirror: Replica,
     pub api_protocol: String,
+    pub domain_name: Option<String>,
+    pub repair_index_config: bool,
+    pub serve_git_index: bool,
+    pub private_index: bool,
+    pub publish_rate_limit: PublishRateLimit,
+    pub allowed_registries: Vec<String>,
+    pub http_proxy: Option<String>,
 }
 
 impl Default for Config {
@@ -27,23 +40,74 @@ impl Default for Config {
     ///
     /// - `Config::max_upload_size`: 10MiB
     /// - `Config::api_protocol`: `https`
+    /// - `Config::publish_rate_limit`: see `PublishRateLimit::default`, overridden by the
+    /// `PUBLISH_RATE_*` and `PUBLISH_BURST_*` variables below
     ///
     /// Pulls values from the following environment variables:
     ///
-    /// - `GIT_REPO_CHECKOUT`: The directory where the registry index was cloned.
+    /// - `INDEX_BACKEND`: Where to keep the registry index, `remote-git` (the default) for a
+    /// checkout pushing to its `origin` remote, `local-git` for a repository with no remote, or
+    /// `memory`.
+    /// - `GIT_REPO_CHECKOUT`: The directory of the index repository, for the git backends.
+    /// - `GIT_AUTH`: How to authenticate to the remote of the `remote-git` backend, see
+    /// `Credentials::from_env`.
+    /// - `INDEX_BATCH_WINDOW_MS`: If set, updates to the index made within this many milliseconds
+    /// of each other are written as a single commit. Unset by default.
+    /// - `INDEX_BATCH_MAX_UPDATES`: The most updates written in a single commit. Defaults to 50.
     /// - `MIRROR`: Is this instance of cargo_registry a mirror of crates.io.
     /// - `HEROKU`: Is this instance of cargo_registry currently running on Heroku.
+    /// - `FILESYSTEM_STORAGE_ROOT`: If set, crate files are stored in this directory and served
+    /// from there rather than using S3, including in production.
     /// - `S3_BUCKET`: The S3 bucket used to store crate files. If not present during development,
-    /// cargo_registry will fall back to a local uploader.
+    /// cargo_registry will fall back to storing them in `local_uploads`.
     /// - `S3_REGION`: The region in which the bucket was created. Optional if US standard.
+    /// - `S3_ENDPOINT`: The URL of an S3-compatible store such as MinIO to use instead of AWS,
+    /// e.g. `http://localhost:9000`. Optional.
     /// - `S3_ACCESS_KEY`: The access key to interact with S3. Optional if running a mirror.
     /// - `S3_SECRET_KEY`: The secret key to interact with S3. Optional if running a mirror.
     /// - `SESSION_KEY`: The key used to sign and encrypt session cookies.
     /// - `GH_CLIENT_ID`: The client ID of the associated GitHub application.
     /// - `GH_CLIENT_SECRET`: The client secret of the associated GitHub application.
     /// - `DATABASE_URL`: The URL of the postgres database to use.
+    /// - `DOMAIN_NAME`: The host this server is reached at, which the index's `config.json` is
+    /// checked against at startup. The check is skipped if it isn't set.
+    /// - `REPAIR_INDEX_CONFIG`: If set, a `config.json` not matching `DOMAIN_NAME` is replaced
+    /// at startup. Otherwise the server refuses to start.
+    /// - `SERVE_GIT_INDEX`: If set, the repository of a git index backend is served read-only over
+    /// smart HTTP at `/git/index` outside of development too, so that cargo can fetch it from
+    /// this server.
+    /// - `PRIVATE_INDEX`: If set, fetching the index, both the git index served by
+    /// `SERVE_GIT_INDEX` and the sparse index at `/index`, requires an API token. git sends it as
+    /// the password of HTTP basic authentication.
+    /// - `PUBLISH_RATE_NEW_CRATE_SECONDS` and `PUBLISH_BURST_NEW_CRATE`: How many seconds it
+    /// takes for a user to be allowed another new crate, and how many they can publish in a row.
+    /// Default to 600 and 5.
+    /// - `PUBLISH_RATE_NEW_VERSION_SECONDS` and `PUBLISH_BURST_NEW_VERSION`: The same for new
+    /// versions of existing crates. Default to 60 and 30.
+    /// - `ALLOWED_REGISTRIES`: A comma separated list of the index URLs of other registries which
+    /// crates may depend on. Defaults to none.
     fn default() -> Config {
-        let checkout = PathBuf::from(env("GIT_REPO_CHECKOUT"));
+        let index = match env::var("INDEX_BACKEND").as_ref().map(String::as_str) {
+            Ok("remote-git") | Err(..) => IndexConfig::RemoteGit {
+                checkout: PathBuf::from(env("GIT_REPO_CHECKOUT")),
+                credentials: Credentials::from_env(),
+            },
+            Ok("local-git") => IndexConfig::LocalGit {
+                path: PathBuf::from(env("GIT_REPO_CHECKOUT")),
+            },
+            Ok("memory") => IndexConfig::Memory,
+            Ok(s) => panic!("unknown INDEX_BACKEND `{}`", s),
+        };
+        let index_batch = env::var("INDEX_BATCH_WINDOW_MS").ok().map(|ms| {
+            BatchConfig {
+                window: Duration::from_millis(
+                    ms.parse().expect("couldn't parse INDEX_BATCH_WINDOW_MS"),
+                ),
+                max_updates: env::var("INDEX_BATCH_MAX_UPDATES")
+                    .map(|n| n.parse().expect("couldn't parse INDEX_BATCH_MAX_UPDATES"))
+                    .unwrap_or(50),
+            }
+        });
         let api_protocol = String::from("https");
         let mirror = if env::var("MIRROR").is_ok() {
             Replica::ReadOnlyMirror
@@ -56,19 +120,19 @@ impl Default for Config {
         } else {
             Env::Development
         };
-        let uploader = match (cargo_env, mirror) {
+        let filesystem_root = env::var("FILESYSTEM_STORAGE_ROOT").ok();
+        let storage = match (cargo_env, mirror) {
+            // Crate files are kept on the local filesystem in any environment if asked to, e.g.
+            // by registries which can't use S3.
+            _ if filesystem_root.is_some() => StorageConfig::Filesystem {
+                root: PathBuf::from(filesystem_root.unwrap()),
+            },
             (Env::Production, Replica::Primary) => {
                 // `env` panics if these vars are not set, and in production for a primary instance,
                 // that's what we want since we don't want to be able to start the server if the
                 // server doesn't know where to upload crates.
-                Uploader::S3 {
-                    bucket: s3::Bucket::new(
-                        env("S3_BUCKET"),
-                        env::var("S3_REGION").ok(),
-                        env("S3_ACCESS_KEY"),
-                        env("S3_SECRET_KEY"),
-                        &api_protocol,
-                    ),
+                StorageConfig::S3 {
+                    bucket: s3_bucket(env("S3_ACCESS_KEY"), env("S3_SECRET_KEY"), &api_protocol),
                     cdn: env::var("S3_CDN").ok(),
                     proxy: None,
                 }
@@ -82,10 +146,8 @@ impl Default for Config {
                 //
                 // Read-only mirrors definitely need bucket though, so that they know where
                 // to serve crate files from.
-                Uploader::S3 {
-                    bucket: s3::Bucket::new(
-                        env("S3_BUCKET"),
-                        env::var("S3_REGION").ok(),
+                StorageConfig::S3 {
+                    bucket: s3_bucket(
                         env::var("S3_ACCESS_KEY").unwrap_or_default(),
                         env::var("S3_SECRET_KEY").unwrap_or_default(),
                         &api_protocol,
@@ -101,11 +163,9 @@ impl Default for Config {
                     // for the related S3 environment variables and configure the app to upload to
                     // and read from S3 like production does. All values except for bucket are
                     // optional, like production read-only mirrors.
-                    println!("Using S3 uploader");
-                    Uploader::S3 {
-                        bucket: s3::Bucket::new(
-                            env("S3_BUCKET"),
-                            env::var("S3_REGION").ok(),
+                    println!("Using S3 storage");
+                    StorageConfig::S3 {
+                        bucket: s3_bucket(
                             env::var("S3_ACCESS_KEY").unwrap_or_default(),
                             env::var("S3_SECRET_KEY").unwrap_or_default(),
                             &api_protocol,
@@ -114,20 +174,24 @@ impl Default for Config {
                         proxy: None,
                     }
                 } else {
-                    // If we don't set the `S3_BUCKET` variable, we'll use a development-only
-                    // uploader that makes it possible to run and publish to a locally-running
-                    // crates.io instance without needing to set up an account and a bucket in S3.
+                    // If we don't set the `S3_BUCKET` variable, we'll store crate files on the
+                    // local filesystem, which makes it possible to run and publish to a
+                    // locally-running crates.io instance without needing to set up an account and
+                    // a bucket in S3.
                     println!(
-                        "Using local uploader, crate files will be in the local_uploads directory"
+                        "Using local storage, crate files will be in the local_uploads directory"
                     );
-                    Uploader::Local
+                    StorageConfig::Filesystem {
+                        root: env::current_dir().unwrap().join("local_uploads"),
+                    }
                 }
             }
         };
         Config {
-            uploader: uploader,
+            storage: storage,
             session_key: env("SESSION_KEY"),
-            git_repo_checkout: checkout,
+            index: index,
+            index_batch: index_batch,
             gh_client_id: env("GH_CLIENT_ID"),
             gh_client_secret: env("GH_CLIENT_SECRET"),
             db_url: env("DATABASE_URL"),
@@ -136,6 +200,46 @@ impl Default for Config {
             max_unpack_size: 512 * 1024 * 1024, // 512 MB max when decompressed
             mirror: mirror,
             api_protocol: api_protocol,
+            domain_name: env::var("DOMAIN_NAME").ok(),
+            repair_index_config: env::var("REPAIR_INDEX_CONFIG").is_ok(),
+            serve_git_index: env::var("SERVE_GIT_INDEX").is_ok(),
+            private_index: env::var("PRIVATE_INDEX").is_ok(),
+            publish_rate_limit: PublishRateLimit::from_env(),
+            allowed_registries: env::var("ALLOWED_REGISTRIES")
+                .map(|s| {
+                    s.split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            http_proxy: None,
         }
     }
 }
+
+impl Config {
+    /// What the index's `config.json` should contain, if `domain_name` is set.
+    pub fn registry_config(&self) -> Option<RegistryConfig> {
+        self.domain_name.as_ref().map(|domain| {
+            let mut config = RegistryConfig::new(format!("{}://{}", self.api_protocol, domain));
+            config.auth_required = self.private_index;
+            config
+        })
+    }
+}
+
+/// The bucket named by `S3_BUCKET`, in `S3_REGION` and at `S3_ENDPOINT` if set.
+fn s3_bucket(access_key: String, secret_key: String, api_protocol: &str) -> s3::Bucket {
+    let bucket = s3::Bucket::new(
+        env("S3_BUCKET"),
+        env::var("S3_REGION").ok(),
+        access_key,
+        secret_key,
+        api_protocol,
+    );
+    match env::var("S3_ENDPOINT") {
+        Ok(endpoint) => bucket.with_endpoint(endpoint),
+        Err(..) => bucket,
+    }
+}
@@ -18,13 +18,21 @@ use version::Version;
 pub struct Dependency {
     pub id: i32,
     pub version_id: i32,
-    pub crate_id: i32,
+    /// `None` for dependencies on crates from other registries.
+    pub crate_id: Option<i32>,
     pub req: semver::VersionReq,
     pub optional: bool,
     pub default_features: bool,
     pub features: Vec<String>,
     pub target: Option<String>,
     pub kind: Kind,
+    /// The name the dependency was renamed to in `Cargo.toml`, if any.
+    pub explicit_name: Option<String>,
+    /// The URL of the index of the registry the dependency is from, if it
+    /// isn't this one.
+    pub registry: Option<String>,
+    /// The name of the crate in `registry`.
+    pub registry_crate_name: Option<String>,
 }
 
 #[derive(Debug)]
@@ -46,6 +54,8 @@ pub struct EncodableDependency {
     pub target: Option<String>,
     pub kind: Kind,
     pub downloads: i32,
+    pub explicit_name: Option<String>,
+    pub registry: Option<String>,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
@@ -62,13 +72,16 @@ pub enum Kind {
 #[table_name = "dependencies"]
 pub struct NewDependency<'a> {
     pub version_id: i32,
-    pub crate_id: i32,
+    pub crate_id: Option<i32>,
     pub req: String,
     pub optional: bool,
     pub default_features: bool,
     pub features: Vec<&'a str>,
     pub target: Option<&'a str>,
     pub kind: i32,
+    pub explicit_name: Option<&'a str>,
+    pub registry: Option<&'a str>,
+    pub registry_crate_name: Option<&'a str>,
 }
 
 impl Dependency {
@@ -85,6 +98,8 @@ impl Dependency {
             target: self.target,
             kind: self.kind,
             downloads: downloads.unwrap_or(0),
+            explicit_name: self.explicit_name,
+            registry: self.registry,
         }
     }
 }
@@ -96,49 +111,57 @@ impl ReverseDependency {
     }
 }
 
+/// Records the dependencies of a new version, and returns them in the form
+/// they are written to the index in.
+///
+/// Dependencies on crates from other registries are only accepted if the URL
+/// of the registry's index is in `allowed_registries`.
 pub fn add_dependencies(
     conn: &PgConnection,
     deps: &[::upload::CrateDependency],
     version_id: i32,
+    allowed_registries: &[String],
 ) -> CargoResult<Vec<git::Dependency>> {
     use diesel::insert_into;
 
     let git_and_new_dependencies = deps.iter()
         .map(|dep| {
-            let krate = Crate::by_name(&dep.name)
-                .first::<Crate>(&*conn)
-                .map_err(|_| {
-                    human(&format_args!("no known crate named `{}`", &*dep.name))
-                })?;
-            if dep.version_req == semver::VersionReq::parse("*").unwrap() {
-                return Err(human(
-                    "wildcard (`*`) dependency constraints are not allowed \
-                     on crates.io. See http://doc.crates.io/faq.html#can-\
-                     libraries-use--as-a-version-for-their-dependencies for more \
-                     information",
-                ));
-            }
+            let crate_id = check_dependency(conn, dep, allowed_registries)?;
             let features: Vec<_> = dep.features.iter().map(|s| &**s).collect();
 
+            let explicit_name = dep.explicit_name_in_toml.as_ref().map(|s| &**s);
+
+            // Like cargo, the index uses the name the dependency was renamed
+            // to as its name, and the name of the crate as `package`.
+            let (name, package) = match explicit_name {
+                Some(explicit_name) => (explicit_name.to_string(), Some(dep.name.to_string())),
+                None => (dep.name.to_string(), None),
+            };
+
             Ok((
                 git::Dependency {
-                    name: dep.name.to_string(),
+                    name: name,
                     req: dep.version_req.to_string(),
                     features: features.iter().map(|s| s.to_string()).collect(),
                     optional: dep.optional,
                     default_features: dep.default_features,
                     target: dep.target.clone(),
                     kind: dep.kind.or(Some(Kind::Normal)),
+                    registry: dep.registry.clone(),
+                    package: package,
                 },
                 NewDependency {
                     version_id: version_id,
-                    crate_id: krate.id,
+                    crate_id: crate_id,
                     req: dep.version_req.to_string(),
                     kind: dep.kind.unwrap_or(Kind::Normal) as i32,
                     optional: dep.optional,
                     default_features: dep.default_features,
                     features: features,
                     target: dep.target.as_ref().map(|s| &**s),
+                    explicit_name: explicit_name,
+                    registry: dep.registry.as_ref().map(|s| &**s),
+                    registry_crate_name: dep.registry.as_ref().map(|_| &**dep.name),
                 },
             ))
         })
@@ -154,17 +177,70 @@ pub fn add_dependencies(
     Ok(git_deps)
 }
 
+/// Makes the checks of `add_dependencies` without recording anything, for
+/// when there is no version to add the dependencies to.
+pub fn check_dependencies(
+    conn: &PgConnection,
+    deps: &[::upload::CrateDependency],
+    allowed_registries: &[String],
+) -> CargoResult<()> {
+    for dep in deps {
+        check_dependency(conn, dep, allowed_registries)?;
+    }
+    Ok(())
+}
+
+/// Checks that a dependency can be depended on, and returns the id of its
+/// crate if it is from this registry.
+fn check_dependency(
+    conn: &PgConnection,
+    dep: &::upload::CrateDependency,
+    allowed_registries: &[String],
+) -> CargoResult<Option<i32>> {
+    let crate_id = match dep.registry {
+        Some(ref registry) => {
+            if !allowed_registries.contains(registry) {
+                return Err(human(&format_args!(
+                    "dependency `{}` is from the registry `{}`, \
+                     which crates are not allowed to depend on",
+                    &*dep.name,
+                    registry
+                )));
+            }
+            None
+        }
+        None => {
+            let krate = Crate::by_name(&dep.name)
+                .first::<Crate>(&*conn)
+                .map_err(|_| human(&format_args!("no known crate named `{}`", &*dep.name)))?;
+            Some(krate.id)
+        }
+    };
+    if dep.version_req == semver::VersionReq::parse("*").unwrap() {
+        return Err(human(
+            "wildcard (`*`) dependency constraints are not allowed \
+             on crates.io. See http://doc.crates.io/faq.html#can-\
+             libraries-use--as-a-version-for-their-dependencies for more \
+             information",
+        ));
+    }
+    Ok(crate_id)
+}
+
 impl Queryable<dependencies::SqlType, Pg> for Dependency {
     type Row = (
         i32,
         i32,
-        i32,
+        Option<i32>,
         String,
         bool,
         bool,
         Vec<String>,
         Option<String>,
         i32,
+        Option<String>,
+        Option<String>,
+        Option<String>,
     );
 
     fn build(row: Self::Row) -> Self {
@@ -183,6 +259,9 @@ impl Queryable<dependencies::SqlType, Pg> for Dependency {
                 2 => Kind::Dev,
                 n => panic!("unknown kind: {}", n),
             },
+            explicit_name: row.9,
+            registry: row.10,
+            registry_crate_name: row.11,
         }
     }
 }
@@ -208,6 +287,11 @@ impl QueryableByName<Pg> for Dependency {
                 2 => Kind::Dev,
                 n => return Err(format!("unknown kind: {}", n).into()),
             },
+            explicit_name: row.get::<SqlTypeOf<explicit_name>, _>("explicit_name")?,
+            registry: row.get::<SqlTypeOf<registry>, _>("registry")?,
+            registry_crate_name: row.get::<SqlTypeOf<registry_crate_name>, _>(
+                "registry_crate_name",
+            )?,
         })
     }
 }
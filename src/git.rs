@@ -1,18 +1,16 @@
 use std::collections::HashMap;
 use std::env;
-use std::fs::{self, File};
-use std::io::prelude::*;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use semver;
 use git2;
-use serde_json;
 
-use app::App;
 use dependency::Kind;
-use util::{internal, CargoResult};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The entry of a crate version in the registry index, see `index`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Crate {
     pub name: String,
     pub vers: String,
@@ -22,7 +20,7 @@ pub struct Crate {
     pub yanked: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Dependency {
     pub name: String,
     pub req: String,
@@ -31,179 +29,229 @@ pub struct Dependency {
     pub default_features: bool,
     pub target: Option<String>,
     pub kind: Option<Kind>,
+    /// The URL of the index of the registry the dependency is from, if it
+    /// isn't this one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// The name of the crate, if the dependency was renamed. `name` is the
+    /// name it was renamed to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
 }
 
-fn index_file(base: &Path, name: &str) -> PathBuf {
-    let name = name.chars()
-        .flat_map(|c| c.to_lowercase())
-        .collect::<String>();
-    match name.len() {
-        1 => base.join("1").join(&name),
-        2 => base.join("2").join(&name),
-        3 => base.join("3").join(&name[..1]).join(&name),
-        _ => base.join(&name[0..2]).join(&name[2..4]).join(&name),
-    }
+/// How to authenticate to the remote of the index when fetching and pushing.
+#[derive(Clone)]
+pub enum Credentials {
+    /// Anonymous access, which can't push anywhere but `file://` remotes.
+    None,
+    /// A username and password (or token) over HTTPS.
+    Password { username: String, password: String },
+    /// A private key file over SSH. The public key is expected next to it,
+    /// with `.pub` appended to its name.
+    SshKey {
+        username: Option<String>,
+        private_key: PathBuf,
+        passphrase: Option<String>,
+    },
+    /// The keys of the running ssh-agent.
+    SshAgent { username: Option<String> },
+    /// A file holding `username:password`, or a token on its own, which is
+    /// read again on every use so that it can be rotated without a restart.
+    File { path: PathBuf },
 }
 
-pub fn add_crate(app: &App, krate: &Crate) -> CargoResult<()> {
-    let repo = app.git_repo.lock().unwrap();
-    let repo = &*repo;
-    let repo_path = repo.workdir().unwrap();
-    let dst = index_file(repo_path, &krate.name);
-
-    commit_and_push(repo, || {
-        // Add the crate to its relevant file
-        fs::create_dir_all(dst.parent().unwrap())?;
-        let mut prev = String::new();
-        if fs::metadata(&dst).is_ok() {
-            File::open(&dst).and_then(|mut f| f.read_to_string(&mut prev))?;
+impl Credentials {
+    /// Picks the credentials from the environment.
+    ///
+    /// - `GIT_AUTH`: `password` (the default), `ssh-key`, `ssh-agent` or
+    /// `file`.
+    /// - `GIT_HTTP_USER` and `GIT_HTTP_PWD`: For `password`. No
+    /// authentication is used if they aren't set.
+    /// - `GIT_SSH_KEY` and `GIT_SSH_KEY_PASSPHRASE`: The path of the private
+    /// key and its optional passphrase, for `ssh-key`.
+    /// - `GIT_SSH_USER`: The user to log in as with `ssh-key` and `ssh-agent`.
+    /// Defaults to the user in the URL of the remote, then `git`.
+    /// - `GIT_CREDENTIALS_FILE`: The path of the file, for `file`.
+    pub fn from_env() -> Credentials {
+        let ssh_user = env::var("GIT_SSH_USER").ok();
+        match env::var("GIT_AUTH").as_ref().map(String::as_str) {
+            Ok("password") | Err(..) => {
+                match (env::var("GIT_HTTP_USER"), env::var("GIT_HTTP_PWD")) {
+                    (Ok(u), Ok(p)) => Credentials::Password {
+                        username: u,
+                        password: p,
+                    },
+                    _ => Credentials::None,
+                }
+            }
+            Ok("ssh-key") => Credentials::SshKey {
+                username: ssh_user,
+                private_key: PathBuf::from(::env("GIT_SSH_KEY")),
+                passphrase: env::var("GIT_SSH_KEY_PASSPHRASE").ok(),
+            },
+            Ok("ssh-agent") => Credentials::SshAgent { username: ssh_user },
+            Ok("file") => Credentials::File {
+                path: PathBuf::from(::env("GIT_CREDENTIALS_FILE")),
+            },
+            Ok(s) => panic!("unknown GIT_AUTH `{}`", s),
         }
-        let s = serde_json::to_string(krate).unwrap();
-        let new = prev + &s;
-        let mut f = File::create(&dst)?;
-        f.write_all(new.as_bytes())?;
-        f.write_all(b"\n")?;
-
-        Ok((
-            format!("Updating crate `{}#{}`", krate.name, krate.vers),
-            dst.clone(),
-        ))
-    })
-}
+    }
 
-/// Yanks or unyanks a crate version. This requires finding the index
-/// file, deserlialise the crate from JSON, change the yank boolean to
-/// `true` or `false`, write all the lines back out, and commit and
-/// push the changes.
-pub fn yank(app: &App, krate: &str, version: &semver::Version, yanked: bool) -> CargoResult<()> {
-    let repo = app.git_repo.lock().unwrap();
-    let repo_path = repo.workdir().unwrap();
-    let dst = index_file(repo_path, krate);
-
-    commit_and_push(&repo, || {
-        let mut prev = String::new();
-        File::open(&dst).and_then(|mut f| f.read_to_string(&mut prev))?;
-        let new = prev.lines()
-            .map(|line| {
-                let mut git_crate = serde_json::from_str::<Crate>(line)
-                    .map_err(|_| internal(&format_args!("couldn't decode: `{}`", line)))?;
-                if git_crate.name != krate || git_crate.vers != version.to_string() {
-                    return Ok(line.to_string());
-                }
-                git_crate.yanked = Some(yanked);
-                Ok(serde_json::to_string(&git_crate).unwrap())
-            })
-            .collect::<CargoResult<Vec<String>>>();
-        let new = new?.join("\n");
-        let mut f = File::create(&dst)?;
-        f.write_all(new.as_bytes())?;
-        f.write_all(b"\n")?;
-
-        Ok((
-            format!(
-                "{} crate `{}#{}`",
-                if yanked { "Yanking" } else { "Unyanking" },
-                krate,
-                version
-            ),
-            dst.clone(),
-        ))
-    })
-}
+    /// Returns callbacks authenticating with these credentials.
+    ///
+    /// libgit2 asks for credentials again for as long as they are rejected,
+    /// so they are only offered once per connection.
+    pub fn callbacks(&self) -> git2::RemoteCallbacks {
+        let mut offered = false;
+        let mut callbacks = git2::RemoteCallbacks::new();
+        callbacks.credentials(move |_url, user_from_url, allowed| {
+            if allowed.contains(git2::USERNAME) {
+                return git2::Cred::username(&self.ssh_username(user_from_url));
+            }
+            if offered {
+                return Err(git2::Error::from_str("the index credentials were rejected"));
+            }
+            offered = true;
+            self.cred(user_from_url)
+        });
+        callbacks
+    }
+
+    fn cred(&self, user_from_url: Option<&str>) -> Result<git2::Cred, git2::Error> {
+        match *self {
+            Credentials::None => Err(git2::Error::from_str("no authentication set")),
+            Credentials::Password {
+                ref username,
+                ref password,
+            } => git2::Cred::userpass_plaintext(username, password),
+            Credentials::SshKey {
+                ref private_key,
+                ref passphrase,
+                ..
+            } => {
+                let public_key = public_key_path(private_key);
+                git2::Cred::ssh_key(
+                    &self.ssh_username(user_from_url),
+                    if public_key.exists() {
+                        Some(public_key.as_path())
+                    } else {
+                        None
+                    },
+                    private_key,
+                    passphrase.as_ref().map(|s| &**s),
+                )
+            }
+            Credentials::SshAgent { .. } => {
+                git2::Cred::ssh_key_from_agent(&self.ssh_username(user_from_url))
+            }
+            Credentials::File { ref path } => {
+                let mut contents = String::new();
+                File::open(path)
+                    .and_then(|mut f| f.read_to_string(&mut contents))
+                    .map_err(|e| {
+                        git2::Error::from_str(&format!(
+                            "failed to read `{}`: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                let (username, password) = parse_credentials_file(&contents, user_from_url);
+                git2::Cred::userpass_plaintext(&username, &password)
+            }
+        }
+    }
 
-/// Commits and pushes to the crates.io index.
-///
-/// There are currently 2 instances of the crates.io backend running
-/// on Heroku, and they race against each other e.g. if 2 pushes occur,
-/// then one will succeed while the other will need to be rebased before
-/// being pushed.
-///
-/// A maximum of 20 attempts to commit and push to the index currently
-/// accounts for the amount of traffic publishing crates, though this may
-/// have to be changed in the future.
-///
-/// Notes:
-/// Currently, this function is called on the HTTP thread and is blocking.
-/// Spawning a separate thread for this function means that the request
-/// can return without waiting for completion, and other methods of
-/// notifying upon completion or error can be used.
-fn commit_and_push<F>(repo: &git2::Repository, mut f: F) -> CargoResult<()>
-where
-    F: FnMut() -> CargoResult<(String, PathBuf)>,
-{
-    let repo_path = repo.workdir().unwrap();
-
-    // Attempt to commit in a loop. It's possible that we're going to need to
-    // rebase our repository, and after that it's possible that we're going to
-    // race to commit the changes. For now we just cap out the maximum number of
-    // retries at a fixed number.
-    for _ in 0..20 {
-        let (msg, dst) = f()?;
-
-        // git add $file
-        let mut index = repo.index()?;
-        let mut repo_path = repo_path.iter();
-        let dst = dst.iter()
-            .skip_while(|s| Some(*s) == repo_path.next())
-            .collect::<PathBuf>();
-        index.add_path(&dst)?;
-        index.write()?;
-        let tree_id = index.write_tree()?;
-        let tree = repo.find_tree(tree_id)?;
-
-        // git commit -m "..."
-        let head = repo.head()?;
-        let parent = repo.find_commit(head.target().unwrap())?;
-        let sig = repo.signature()?;
-        repo.commit(Some("HEAD"), &sig, &sig, &msg, &tree, &[&parent])?;
-
-        // git push
-        let mut ref_status = None;
-        let mut origin = repo.find_remote("origin")?;
-        let res = {
-            let mut callbacks = git2::RemoteCallbacks::new();
-            callbacks.credentials(credentials);
-            callbacks.push_update_reference(|refname, status| {
-                assert_eq!(refname, "refs/heads/master");
-                ref_status = status.map(|s| s.to_string());
-                Ok(())
-            });
-            let mut opts = git2::PushOptions::new();
-            opts.remote_callbacks(callbacks);
-            origin.push(&["refs/heads/master"], Some(&mut opts))
+    fn ssh_username(&self, user_from_url: Option<&str>) -> String {
+        let configured = match *self {
+            Credentials::SshKey { ref username, .. }
+            | Credentials::SshAgent { ref username } => username.as_ref(),
+            _ => None,
         };
-        match res {
-            Ok(()) if ref_status.is_none() => return Ok(()),
-            Ok(()) => info!("failed to push a ref: {:?}", ref_status),
-            Err(e) => info!("failure to push: {}", e),
+        configured
+            .map(|s| &**s)
+            .or(user_from_url)
+            .unwrap_or("git")
+            .to_string()
+    }
+}
+
+// Leaves the secrets out of the logs.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Credentials::None => write!(f, "None"),
+            Credentials::Password { ref username, .. } => {
+                write!(f, "Password {{ username: {:?}, .. }}", username)
+            }
+            Credentials::SshKey {
+                ref username,
+                ref private_key,
+                ..
+            } => write!(
+                f,
+                "SshKey {{ username: {:?}, private_key: {:?}, .. }}",
+                username,
+                private_key
+            ),
+            Credentials::SshAgent { ref username } => {
+                write!(f, "SshAgent {{ username: {:?} }}", username)
+            }
+            Credentials::File { ref path } => write!(f, "File {{ path: {:?} }}", path),
         }
+    }
+}
 
-        let mut callbacks = git2::RemoteCallbacks::new();
-        callbacks.credentials(credentials);
-        origin.update_tips(
-            Some(&mut callbacks),
-            true,
-            git2::AutotagOption::Unspecified,
-            None,
-        )?;
-
-        // Ok, we need to update, so fetch and reset --hard
-        origin.fetch(&["refs/heads/*:refs/heads/*"], None, None)?;
-        let head = repo.head()?.target().unwrap();
-        let obj = repo.find_object(head, None)?;
-        repo.reset(&obj, git2::ResetType::Hard, None)?;
+/// Splits the contents of a credentials file into a username and password.
+/// A token on its own is used as the password of the user in the URL.
+fn parse_credentials_file(contents: &str, user_from_url: Option<&str>) -> (String, String) {
+    let line = contents.lines().next().unwrap_or("").trim();
+    match line.find(':') {
+        Some(i) => (line[..i].to_string(), line[i + 1..].to_string()),
+        None => (user_from_url.unwrap_or("git").to_string(), line.to_string()),
     }
+}
 
-    Err(internal("Too many rebase failures"))
+/// The path of the public key of `private_key`, which has `.pub` appended
+/// rather than replacing the extension: `deploy.key.pub`, not `deploy.pub`.
+fn public_key_path(private_key: &Path) -> PathBuf {
+    let mut path = private_key.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
 }
 
-pub fn credentials(
-    _user: &str,
-    _user_from_url: Option<&str>,
-    _cred: git2::CredentialType,
-) -> Result<git2::Cred, git2::Error> {
-    match (env::var("GIT_HTTP_USER"), env::var("GIT_HTTP_PWD")) {
-        (Ok(u), Ok(p)) => git2::Cred::userpass_plaintext(&u, &p),
-        _ => Err(git2::Error::from_str("no authentication set")),
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_credentials_file, public_key_path};
+
+    #[test]
+    fn credentials_files() {
+        let pair = |u: &str, p: &str| (u.to_string(), p.to_string());
+        assert_eq!(
+            parse_credentials_file("bors:hunter2\n", None),
+            pair("bors", "hunter2")
+        );
+        assert_eq!(
+            parse_credentials_file("bors:a:b", Some("x")),
+            pair("bors", "a:b")
+        );
+        assert_eq!(
+            parse_credentials_file("  token\n", Some("x-access-token")),
+            pair("x-access-token", "token")
+        );
+        assert_eq!(parse_credentials_file("token", None), pair("git", "token"));
+    }
+
+    #[test]
+    fn public_key_paths() {
+        assert_eq!(
+            public_key_path(Path::new("/keys/id_rsa")),
+            Path::new("/keys/id_rsa.pub")
+        );
+        assert_eq!(
+            public_key_path(Path::new("/keys/deploy.key")),
+            Path::new("/keys/deploy.key.pub")
+        );
     }
 }
@@ -7,7 +7,7 @@ use conduit_middleware::Middleware;
 use std::error::Error;
 use std::collections::HashMap;
 
-use Uploader;
+use StorageConfig;
 
 #[derive(Clone, Debug)]
 pub struct SecurityHeadersMiddleware {
@@ -15,7 +15,7 @@ pub struct SecurityHeadersMiddleware {
 }
 
 impl SecurityHeadersMiddleware {
-    pub fn new(uploader: &Uploader) -> Self {
+    pub fn new(storage: &StorageConfig) -> Self {
         let mut headers = HashMap::new();
 
         headers.insert("X-Content-Type-Options".into(), vec!["nosniff".into()]);
@@ -24,20 +24,12 @@ impl SecurityHeadersMiddleware {
 
         headers.insert("X-XSS-Protection".into(), vec!["1; mode=block".into()]);
 
-        let s3_host = match *uploader {
-            Uploader::S3 {
-                ref bucket,
-                ref cdn,
-                ..
-            } => match *cdn {
-                Some(ref s) => s.clone(),
-                None => bucket.host(),
-            },
-            _ => unreachable!(
-                "This middleware should only be used in the production environment, \
-                 which should also require an S3 uploader, QED"
-            ),
-        };
+        // Crate files are fetched from the storage's host, unless this server
+        // serves them itself.
+        let download_host = storage
+            .download_host()
+            .map(|host| format!(" https://{}", host))
+            .unwrap_or_default();
 
         // It would be better if we didn't have to have 'unsafe-eval' in the `script-src`
         // policy, but google charts (used for the download graph on crate pages) uses `eval`
@@ -49,13 +41,13 @@ impl SecurityHeadersMiddleware {
             vec![
                 format!(
                     "default-src 'self'; \
-                     connect-src 'self' https://docs.rs https://{}; \
+                     connect-src 'self' https://docs.rs{}; \
                      script-src 'self' 'unsafe-eval' \
                      https://www.google-analytics.com https://www.google.com; \
                      style-src 'self' https://www.google.com https://ajax.googleapis.com; \
                      img-src *; \
                      object-src 'none'",
-                    s3_host
+                    download_host
                 ),
             ],
         );
@@ -0,0 +1,232 @@
+//! Compares the index with the database, which is the source of truth.
+//!
+//! The two drift apart when a change is committed to the database but never
+//! makes it to the index, e.g. when pushing a publish keeps failing, or when
+//! one of the admin binaries stops halfway through.
+
+use std::collections::{HashMap, HashSet};
+
+use diesel::prelude::*;
+use serde_json;
+
+use krate::canon_crate_name;
+use schema::crates;
+use util::CargoResult;
+use Crate;
+
+use super::{parse_line, sparse, Changes, IndexBackend};
+
+/// A version of a crate whose index entry doesn't match the database.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub version: String,
+    pub problem: Problem,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// The version is in the database but not in the index.
+    MissingFromIndex,
+    /// The version is in the index but not in the database.
+    MissingFromDatabase,
+    /// The version is in the index more than once.
+    Duplicated,
+    YankedMismatch,
+    ChecksumMismatch,
+    DependenciesMismatch,
+    FeaturesMismatch,
+}
+
+/// The result of checking a single crate.
+#[derive(Debug)]
+pub struct CrateCheck {
+    pub discrepancies: Vec<Discrepancy>,
+    /// What the index file of the crate should contain, or `None` if there
+    /// shouldn't be one.
+    pub expected: Option<String>,
+}
+
+/// Compares the index file of a crate with its versions in the database.
+pub fn check_crate(
+    conn: &PgConnection,
+    index: &IndexBackend,
+    krate: &Crate,
+) -> CargoResult<CrateCheck> {
+    let contents = index.read_file(&krate.name)?;
+    check_file(conn, krate, contents.as_ref().map(|s| &**s))
+}
+
+/// Compares `contents`, the index file of a crate or `None` if it has none,
+/// with the versions of the crate in the database.
+///
+/// The checksums of versions published before they were recorded in the
+/// database can't be checked, so the expected file keeps the ones in the
+/// index for those, and leaves out versions which aren't in the index at
+/// all.
+pub fn check_file(
+    conn: &PgConnection,
+    krate: &Crate,
+    contents: Option<&str>,
+) -> CargoResult<CrateCheck> {
+    let expected = sparse::entries(conn, krate)?;
+    let actual = contents
+        .unwrap_or("")
+        .lines()
+        .map(parse_line)
+        .collect::<CargoResult<Vec<_>>>()?;
+    let discrepancy = |version: &str, problem| Discrepancy {
+        krate: krate.name.clone(),
+        version: version.to_string(),
+        problem: problem,
+    };
+    let mut discrepancies = Vec::new();
+
+    let mut indexed = HashMap::new();
+    for entry in &actual {
+        if indexed.insert(&*entry.vers, entry).is_some() {
+            discrepancies.push(discrepancy(&entry.vers, Problem::Duplicated));
+        }
+    }
+
+    for entry in &expected {
+        let found = match indexed.get(&*entry.vers) {
+            Some(found) => found,
+            None => {
+                discrepancies.push(discrepancy(&entry.vers, Problem::MissingFromIndex));
+                continue;
+            }
+        };
+        let mut problems = Vec::new();
+        if entry.yanked.unwrap_or(false) != found.yanked.unwrap_or(false) {
+            problems.push(Problem::YankedMismatch);
+        }
+        if !entry.cksum.is_empty() && entry.cksum != found.cksum {
+            problems.push(Problem::ChecksumMismatch);
+        }
+        if serde_json::to_value(&entry.deps)? != serde_json::to_value(&found.deps)? {
+            problems.push(Problem::DependenciesMismatch);
+        }
+        if entry.features != found.features {
+            problems.push(Problem::FeaturesMismatch);
+        }
+        for problem in problems {
+            discrepancies.push(discrepancy(&entry.vers, problem));
+        }
+    }
+
+    {
+        let known = expected.iter().map(|e| &*e.vers).collect::<HashSet<_>>();
+        for entry in &actual {
+            if !known.contains(&*entry.vers) {
+                discrepancies.push(discrepancy(&entry.vers, Problem::MissingFromDatabase));
+            }
+        }
+    }
+
+    let mut contents = String::new();
+    for mut entry in expected {
+        if entry.cksum.is_empty() {
+            match indexed.get(&*entry.vers) {
+                Some(found) => entry.cksum = found.cksum.clone(),
+                None => continue,
+            }
+        }
+        contents.push_str(&serde_json::to_string(&entry)?);
+        contents.push('\n');
+    }
+
+    Ok(CrateCheck {
+        discrepancies: discrepancies,
+        expected: if contents.is_empty() {
+            None
+        } else {
+            Some(contents)
+        },
+    })
+}
+
+/// Returns the names of the index files which have no crate in the
+/// database, e.g. left behind by deleting a crate from the database only.
+pub fn orphaned_files(conn: &PgConnection, index: &IndexBackend) -> CargoResult<Vec<String>> {
+    let known = crates::table
+        .select(crates::name)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(|name| name.to_lowercase())
+        .collect::<HashSet<_>>();
+    Ok(index
+        .crate_names()?
+        .into_iter()
+        .filter(|name| !known.contains(name))
+        .collect())
+}
+
+/// Reports every version in the index file `name` of a crate which isn't in
+/// the database as missing from it.
+pub fn check_orphan(index: &IndexBackend, name: &str) -> CargoResult<Vec<Discrepancy>> {
+    Ok(index
+        .entries(name)?
+        .into_iter()
+        .map(|entry| Discrepancy {
+            krate: name.to_string(),
+            version: entry.vers,
+            problem: Problem::MissingFromDatabase,
+        })
+        .collect())
+}
+
+/// Whether the index file `name` belongs to a crate in the database.
+fn has_crate(conn: &PgConnection, name: &str) -> CargoResult<bool> {
+    let names = crates::table
+        .filter(canon_crate_name(crates::name).eq(canon_crate_name(name)))
+        .select(crates::name)
+        .load::<String>(conn)?;
+    Ok(names.iter().any(|n| n.to_lowercase() == name))
+}
+
+/// Rewrites the index files of the `crates` which differ from the database
+/// and deletes the `orphans` found by `orphaned_files`, as a single change,
+/// and returns the names of the crates whose files were rewritten or
+/// deleted.
+///
+/// The files are checked again while the index is being modified, so that
+/// the versions published or yanked since they were last checked aren't
+/// reverted, and crates published since aren't deleted.
+pub fn repair(
+    conn: &PgConnection,
+    index: &IndexBackend,
+    crates: &[Crate],
+    orphans: &[String],
+) -> CargoResult<Vec<String>> {
+    let mut repaired = Vec::new();
+    index.modify_files(&mut |read| {
+        // Called again from scratch if the backend has to retry.
+        repaired.clear();
+        let mut files = Vec::new();
+        for krate in crates {
+            let contents = read(&krate.name)?;
+            let check = check_file(conn, krate, contents.as_ref().map(|s| &**s))?;
+            if !check.discrepancies.is_empty() {
+                repaired.push(krate.name.clone());
+                files.push((krate.name.clone(), check.expected));
+            }
+        }
+        for name in orphans {
+            if read(name)?.is_some() && !has_crate(conn, name)? {
+                repaired.push(name.clone());
+                files.push((name.clone(), None));
+            }
+        }
+        if files.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Changes {
+            message: format!("Repairing {} crates from the database", files.len()),
+            files: files,
+        }))
+    })?;
+    Ok(repaired)
+}
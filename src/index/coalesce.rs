@@ -0,0 +1,279 @@
+//! Batches concurrent index updates into a single commit.
+//!
+//! Every publish or yank used to be its own commit and push, so that under
+//! load each of them raced the others to push, fetching and resetting on
+//! every failure. `CoalescingIndex` instead hands updates to a single writer
+//! thread, which waits a short window for more to come in and then writes
+//! all of them at once.
+//!
+//! Publishes are already written in batches by the background job runner
+//! (see `AddCrateToIndex`), so this helps with yanks, which are made while
+//! serving requests, and with more than one `BACKGROUND_JOB_THREADS`.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use util::{internal, CargoResult};
+
+use super::{Changes, IndexBackend, ReadFile, Update};
+
+/// How long `CoalescingIndex` gathers updates for before writing them.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    /// How long to wait for more updates after the first one of a batch.
+    pub window: Duration,
+    /// Writes the batch as soon as it has this many updates.
+    pub max_updates: usize,
+}
+
+/// Wraps another backend, writing the updates made concurrently by
+/// `add_version`, `set_yanked` and the like as a single change.
+///
+/// Each caller still blocks until its own update was written, and gets its
+/// own result: an update which can't be applied (e.g. yanking a crate which
+/// isn't in the index) is left out of the batch without failing the others.
+/// `modify_files` and `write_config` go straight to the wrapped backend.
+// The wrapped backend doesn't implement debug.
+#[allow(missing_debug_implementations)]
+pub struct CoalescingIndex {
+    inner: Arc<IndexBackend>,
+    sender: Mutex<Sender<Pending>>,
+}
+
+struct Pending {
+    update: Update,
+    result: Sender<CargoResult<()>>,
+}
+
+impl CoalescingIndex {
+    /// Starts the thread writing the batches of updates to `inner`, which
+    /// stops when the returned index is dropped.
+    pub fn new(inner: Box<IndexBackend>, config: BatchConfig) -> CoalescingIndex {
+        let inner: Arc<IndexBackend> = Arc::from(inner);
+        let (sender, receiver) = channel();
+        let writer = Arc::clone(&inner);
+        thread::Builder::new()
+            .name("index-writer".to_string())
+            .spawn(move || write_batches(&*writer, &receiver, config))
+            .unwrap();
+        CoalescingIndex {
+            inner: inner,
+            sender: Mutex::new(sender),
+        }
+    }
+}
+
+impl IndexBackend for CoalescingIndex {
+    fn read_file(&self, name: &str) -> CargoResult<Option<String>> {
+        self.inner.read_file(name)
+    }
+
+    fn crate_names(&self) -> CargoResult<Vec<String>> {
+        self.inner.crate_names()
+    }
+
+    fn modify_files(
+        &self,
+        f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+    ) -> CargoResult<()> {
+        self.inner.modify_files(f)
+    }
+
+    fn read_config(&self) -> CargoResult<Option<String>> {
+        self.inner.read_config()
+    }
+
+    fn write_config(&self, contents: &str) -> CargoResult<()> {
+        self.inner.write_config(contents)
+    }
+
+    fn update(&self, update: Update) -> CargoResult<()> {
+        let (sender, receiver) = channel();
+        let pending = Pending {
+            update: update,
+            result: sender,
+        };
+        self.sender
+            .lock()
+            .unwrap()
+            .send(pending)
+            .map_err(|_| internal("the index writer has stopped"))?;
+        receiver
+            .recv()
+            .map_err(|_| internal("the index writer has stopped"))?
+    }
+}
+
+fn write_batches(inner: &IndexBackend, receiver: &Receiver<Pending>, config: BatchConfig) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + config.window;
+        let mut batch = vec![first];
+        while batch.len() < config.max_updates {
+            // The updates which queued up while the previous batch was being
+            // written are taken even once the window is over.
+            let now = Instant::now();
+            let next = if now >= deadline {
+                receiver.try_recv().ok()
+            } else {
+                receiver.recv_timeout(deadline - now).ok()
+            };
+            match next {
+                Some(pending) => batch.push(pending),
+                None => break,
+            }
+        }
+
+        let updates = batch
+            .iter()
+            .map(|pending| pending.update.clone())
+            .collect::<Vec<_>>();
+        let results = inner.update_all(&updates);
+        for (pending, result) in batch.into_iter().zip(results) {
+            // The caller may have gone away, there's nobody left to tell.
+            let _ = pending.result.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use semver;
+
+    use git::Crate;
+    use index::{Changes, IndexBackend, MemoryIndex, ReadFile, Update};
+    use util::CargoResult;
+    use super::{write_batches, BatchConfig, CoalescingIndex, Pending};
+
+    /// Counts the changes made to a `MemoryIndex`.
+    struct CountingIndex {
+        inner: MemoryIndex,
+        changes: Arc<AtomicUsize>,
+    }
+
+    impl IndexBackend for CountingIndex {
+        fn read_file(&self, name: &str) -> CargoResult<Option<String>> {
+            self.inner.read_file(name)
+        }
+
+        fn crate_names(&self) -> CargoResult<Vec<String>> {
+            self.inner.crate_names()
+        }
+
+        fn modify_files(
+            &self,
+            f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+        ) -> CargoResult<()> {
+            let changes = &self.changes;
+            self.inner.modify_files(&mut |read| {
+                let res = f(read)?;
+                if res.is_some() {
+                    changes.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(res)
+            })
+        }
+
+        fn read_config(&self) -> CargoResult<Option<String>> {
+            self.inner.read_config()
+        }
+
+        fn write_config(&self, contents: &str) -> CargoResult<()> {
+            self.inner.write_config(contents)
+        }
+    }
+
+    fn counting_index() -> (CountingIndex, Arc<AtomicUsize>) {
+        let changes = Arc::new(AtomicUsize::new(0));
+        let index = CountingIndex {
+            inner: MemoryIndex::new(),
+            changes: Arc::clone(&changes),
+        };
+        (index, changes)
+    }
+
+    fn index(window_ms: u64) -> (Arc<CoalescingIndex>, Arc<AtomicUsize>) {
+        let (inner, changes) = counting_index();
+        let config = BatchConfig {
+            window: Duration::from_millis(window_ms),
+            max_updates: 100,
+        };
+        let index = CoalescingIndex::new(Box::new(inner), config);
+        (Arc::new(index), changes)
+    }
+
+    fn entry(name: &str, vers: &str) -> Crate {
+        Crate {
+            name: name.to_string(),
+            vers: vers.to_string(),
+            deps: Vec::new(),
+            cksum: String::new(),
+            features: HashMap::new(),
+            yanked: None,
+        }
+    }
+
+    #[test]
+    fn queued_updates_are_written_together() {
+        let (inner, changes) = counting_index();
+
+        // Queue the updates as if the writer was busy with a previous batch,
+        // then let it write them.
+        let (sender, receiver) = channel();
+        let results = (0..10)
+            .map(|i| {
+                let (result_sender, result) = channel();
+                let pending = Pending {
+                    update: Update::AddVersion(entry("foo", &format!("1.0.{}", i))),
+                    result: result_sender,
+                };
+                sender.send(pending).unwrap();
+                result
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        let config = BatchConfig {
+            window: Duration::from_millis(0),
+            max_updates: 4,
+        };
+        write_batches(&inner, &receiver, config);
+
+        for result in results {
+            result.recv().unwrap().unwrap();
+        }
+        assert_eq!(inner.entries("foo").unwrap().len(), 10);
+        assert_eq!(changes.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn failed_updates_only_fail_their_caller() {
+        let (index, _) = index(100);
+        index
+            .modify_files(&mut |_| {
+                Ok(Some(Changes {
+                    message: "Corrupt a file".to_string(),
+                    files: vec![("broken".to_string(), Some("not json\n".to_string()))],
+                }))
+            })
+            .unwrap();
+        let yank = {
+            let index = Arc::clone(&index);
+            thread::spawn(move || {
+                let v = semver::Version::parse("1.0.0").unwrap();
+                index.set_yanked("broken", &[v], true)
+            })
+        };
+        index.add_version(&entry("foo", "1.0.0")).unwrap();
+
+        assert!(yank.join().unwrap().is_err());
+        assert_eq!(index.entries("foo").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,420 @@
+//! Indexes stored in git repositories.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use git2;
+
+use git::Credentials;
+use util::{human, internal, CargoResult};
+
+use super::{file_path, Changes, IndexBackend, ReadFile, CONFIG_PATH};
+
+/// An index kept in a checkout of a git repository, pushing every change to
+/// the `master` branch of its `origin` remote.
+// git2 types don't implement debug.
+#[allow(missing_debug_implementations)]
+pub struct RemoteGitIndex {
+    repo: Mutex<git2::Repository>,
+    credentials: Credentials,
+}
+
+/// An index kept in a git repository with no remote. Every change is
+/// committed, and the repository can be served to cargo as is.
+#[allow(missing_debug_implementations)]
+pub struct LocalGitIndex {
+    repo: Mutex<git2::Repository>,
+}
+
+impl RemoteGitIndex {
+    /// Opens an existing checkout of the index.
+    pub fn open(checkout: &Path, credentials: Credentials) -> CargoResult<RemoteGitIndex> {
+        Ok(RemoteGitIndex {
+            repo: Mutex::new(git2::Repository::open(checkout)?),
+            credentials: credentials,
+        })
+    }
+
+    /// Checks that `origin` can be fetched from and pushed to with the
+    /// configured credentials, so that a misconfigured server fails at
+    /// startup rather than on the first publish.
+    ///
+    /// This pushes back the `master` branch it just fetched, which changes
+    /// nothing but still needs write access.
+    pub fn check_remote(&self) -> CargoResult<()> {
+        let repo = self.repo.lock().unwrap();
+        let mut origin = repo.find_remote("origin")?;
+
+        let mut opts = git2::FetchOptions::new();
+        opts.remote_callbacks(self.credentials.callbacks());
+        origin
+            .fetch(
+                &["refs/heads/master:refs/remotes/origin/master"],
+                Some(&mut opts),
+                None,
+            )
+            .map_err(|e| human(&format_args!("failed to fetch the index from `origin`: {}", e)))?;
+
+        let mut ref_status = None;
+        let res = {
+            let mut callbacks = self.credentials.callbacks();
+            callbacks.push_update_reference(|_refname, status| {
+                ref_status = status.map(|s| s.to_string());
+                Ok(())
+            });
+            let mut opts = git2::PushOptions::new();
+            opts.remote_callbacks(callbacks);
+            origin.push(
+                &["refs/remotes/origin/master:refs/heads/master"],
+                Some(&mut opts),
+            )
+        };
+        res.map_err(|e| human(&format_args!("failed to push the index to `origin`: {}", e)))?;
+        match ref_status {
+            None => Ok(()),
+            Some(status) => Err(human(&format_args!(
+                "`origin` refused a push to the index: {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl LocalGitIndex {
+    /// Opens the repository at `path`, creating it with an empty initial
+    /// commit if it doesn't exist yet.
+    pub fn open(path: &Path) -> CargoResult<LocalGitIndex> {
+        let repo = match git2::Repository::open(path) {
+            Ok(repo) => repo,
+            Err(..) => {
+                fs::create_dir_all(path)?;
+                let repo = git2::Repository::init(path)?;
+                {
+                    let tree_id = repo.index()?.write_tree()?;
+                    let tree = repo.find_tree(tree_id)?;
+                    let sig = signature(&repo)?;
+                    repo.commit(Some("HEAD"), &sig, &sig, "Initial Commit", &tree, &[])?;
+                }
+                repo
+            }
+        };
+        Ok(LocalGitIndex {
+            repo: Mutex::new(repo),
+        })
+    }
+}
+
+impl IndexBackend for RemoteGitIndex {
+    fn read_file(&self, name: &str) -> CargoResult<Option<String>> {
+        let repo = self.repo.lock().unwrap();
+        read_file(&repo, name)
+    }
+
+    fn crate_names(&self) -> CargoResult<Vec<String>> {
+        let repo = self.repo.lock().unwrap();
+        crate_names(&repo)
+    }
+
+    fn modify_files(
+        &self,
+        f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+    ) -> CargoResult<()> {
+        let repo = self.repo.lock().unwrap();
+        commit_and_push(&repo, &self.credentials, &mut || {
+            commit_changes(&repo, &mut *f)
+        })
+    }
+
+    fn read_config(&self) -> CargoResult<Option<String>> {
+        let repo = self.repo.lock().unwrap();
+        read_path(&repo, Path::new(CONFIG_PATH))
+    }
+
+    fn write_config(&self, contents: &str) -> CargoResult<()> {
+        let repo = self.repo.lock().unwrap();
+        commit_and_push(&repo, &self.credentials, &mut || {
+            commit_config(&repo, contents)
+        })
+    }
+}
+
+impl IndexBackend for LocalGitIndex {
+    fn read_file(&self, name: &str) -> CargoResult<Option<String>> {
+        let repo = self.repo.lock().unwrap();
+        read_file(&repo, name)
+    }
+
+    fn crate_names(&self) -> CargoResult<Vec<String>> {
+        let repo = self.repo.lock().unwrap();
+        crate_names(&repo)
+    }
+
+    fn modify_files(
+        &self,
+        f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+    ) -> CargoResult<()> {
+        let repo = self.repo.lock().unwrap();
+        commit_changes(&repo, f)?;
+        Ok(())
+    }
+
+    fn read_config(&self) -> CargoResult<Option<String>> {
+        let repo = self.repo.lock().unwrap();
+        read_path(&repo, Path::new(CONFIG_PATH))
+    }
+
+    fn write_config(&self, contents: &str) -> CargoResult<()> {
+        let repo = self.repo.lock().unwrap();
+        commit_config(&repo, contents)?;
+        Ok(())
+    }
+}
+
+fn read_file(repo: &git2::Repository, name: &str) -> CargoResult<Option<String>> {
+    read_path(repo, &file_path(name))
+}
+
+/// Lists the crates with a file in the working tree. Files which aren't
+/// where the index file of a crate with their name would be, such as
+/// `config.json`, are skipped.
+fn crate_names(repo: &git2::Repository) -> CargoResult<Vec<String>> {
+    let root = repo.workdir().unwrap();
+    let mut names = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name() != Some(OsStr::new(".git")) {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if root.join(file_path(&name)) == path {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Reads a file of the working tree, `path` being relative to its root.
+fn read_path(repo: &git2::Repository, path: &Path) -> CargoResult<Option<String>> {
+    let path = repo.workdir().unwrap().join(path);
+    let mut contents = String::new();
+    match File::open(&path) {
+        Ok(mut f) => {
+            f.read_to_string(&mut contents)?;
+            Ok(Some(contents))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Commits the changes `f` makes to the working tree, returning whether
+/// there was anything to commit.
+fn commit_changes(
+    repo: &git2::Repository,
+    f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+) -> CargoResult<bool> {
+    match f(&|name: &str| read_file(repo, name))? {
+        Some(changes) => {
+            commit(repo, &changes)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Commits `config.json` unless it already has the given contents,
+/// returning whether there was anything to commit.
+fn commit_config(repo: &git2::Repository, contents: &str) -> CargoResult<bool> {
+    let path = Path::new(CONFIG_PATH);
+    if read_path(repo, path)?.as_ref().map(|s| &**s) == Some(contents) {
+        return Ok(false);
+    }
+    commit_paths(
+        repo,
+        "Updating config.json",
+        &[(path.to_path_buf(), Some(contents))],
+    )?;
+    Ok(true)
+}
+
+/// Writes the files of the given crates to the working tree, deleting those
+/// without contents, then commits them.
+fn commit(repo: &git2::Repository, changes: &Changes) -> CargoResult<()> {
+    let files = changes
+        .files
+        .iter()
+        .map(|&(ref name, ref contents)| (file_path(name), contents.as_ref().map(|s| &**s)))
+        .collect::<Vec<_>>();
+    commit_paths(repo, &changes.message, &files)
+}
+
+/// Like `commit`, with paths relative to the root of the index rather than
+/// crate names.
+fn commit_paths(
+    repo: &git2::Repository,
+    message: &str,
+    files: &[(PathBuf, Option<&str>)],
+) -> CargoResult<()> {
+    // git add $file, or git rm $file if it was deleted
+    let mut index = repo.index()?;
+    for &(ref path, contents) in files {
+        let dst = repo.workdir().unwrap().join(path);
+        match contents {
+            Some(contents) => {
+                fs::create_dir_all(dst.parent().unwrap())?;
+                File::create(&dst)?.write_all(contents.as_bytes())?;
+                index.add_path(path)?;
+            }
+            None => {
+                if dst.exists() {
+                    fs::remove_file(&dst)?;
+                }
+                index.remove_path(path)?;
+            }
+        }
+    }
+    index.write()?;
+    let tree_id = index.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+
+    // git commit -m "..."
+    let head = repo.head()?;
+    let parent = repo.find_commit(head.target().unwrap())?;
+    let sig = signature(repo)?;
+    repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])?;
+    Ok(())
+}
+
+/// Commits and pushes to the crates.io index.
+///
+/// There are currently 2 instances of the crates.io backend running
+/// on Heroku, and they race against each other e.g. if 2 pushes occur,
+/// then one will succeed while the other will need to be rebased before
+/// being pushed.
+///
+/// A maximum of 20 attempts to commit and push to the index currently
+/// accounts for the amount of traffic publishing crates, though this may
+/// have to be changed in the future.
+///
+/// Notes:
+/// Publishes reach this function through an `AddCrateToIndex` background
+/// job, but yanking still calls it on the HTTP thread and is blocking.
+///
+/// `f` makes the commit to push, and returns `false` if there was nothing to
+/// commit. It is called again after each rebase.
+fn commit_and_push(
+    repo: &git2::Repository,
+    credentials: &Credentials,
+    f: &mut FnMut() -> CargoResult<bool>,
+) -> CargoResult<()> {
+    // Race to commit the changes. For now we just cap out the maximum number
+    // of retries at a fixed number.
+    for _ in 0..20 {
+        if !f()? {
+            return Ok(());
+        }
+
+        // git push
+        let mut ref_status = None;
+        let mut origin = repo.find_remote("origin")?;
+        let res = {
+            let mut callbacks = credentials.callbacks();
+            callbacks.push_update_reference(|refname, status| {
+                assert_eq!(refname, "refs/heads/master");
+                ref_status = status.map(|s| s.to_string());
+                Ok(())
+            });
+            let mut opts = git2::PushOptions::new();
+            opts.remote_callbacks(callbacks);
+            origin.push(&["refs/heads/master"], Some(&mut opts))
+        };
+        match res {
+            Ok(()) if ref_status.is_none() => return Ok(()),
+            Ok(()) => info!("failed to push a ref: {:?}", ref_status),
+            Err(e) => info!("failure to push: {}", e),
+        }
+
+        let mut callbacks = credentials.callbacks();
+        origin.update_tips(
+            Some(&mut callbacks),
+            true,
+            git2::AutotagOption::Unspecified,
+            None,
+        )?;
+
+        // Ok, we need to update, so fetch and reset --hard
+        let mut opts = git2::FetchOptions::new();
+        opts.remote_callbacks(credentials.callbacks());
+        origin.fetch(&["refs/heads/*:refs/heads/*"], Some(&mut opts), None)?;
+        let head = repo.head()?.target().unwrap();
+        let obj = repo.find_object(head, None)?;
+        repo.reset(&obj, git2::ResetType::Hard, None)?;
+    }
+
+    Err(internal("Too many rebase failures"))
+}
+
+/// Returns the signature configured for the repository, falling back to a
+/// generic one so that a fresh local index works without any git config.
+fn signature(repo: &git2::Repository) -> CargoResult<git2::Signature<'static>> {
+    match repo.signature() {
+        Ok(sig) => Ok(sig),
+        Err(..) => Ok(git2::Signature::now("crates.io", "noreply@crates.io")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use git2;
+
+    use git::Crate;
+    use index::IndexBackend;
+    use super::LocalGitIndex;
+
+    #[test]
+    fn local_index_commits_without_a_remote() {
+        let path = env::current_dir()
+            .unwrap()
+            .join("tmp")
+            .join("local_index_commits_without_a_remote");
+        let _ = fs::remove_dir_all(&path);
+
+        let index = LocalGitIndex::open(&path).unwrap();
+        let krate = Crate {
+            name: "foo".to_string(),
+            vers: "1.0.0".to_string(),
+            deps: Vec::new(),
+            cksum: String::new(),
+            features: HashMap::new(),
+            yanked: None,
+        };
+        index.add_version(&krate).unwrap();
+        assert!(path.join("3/f/foo").exists());
+        assert_eq!(index.entries("foo").unwrap().len(), 1);
+
+        index.remove_crate("foo").unwrap();
+        assert!(!path.join("3/f/foo").exists());
+
+        let repo = git2::Repository::open(&path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.message(), Some("Deleting crate `foo`"));
+        assert_eq!(head.parent(0).unwrap().message(), Some("Updating crate `foo#1.0.0`"));
+    }
+}
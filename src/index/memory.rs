@@ -0,0 +1,167 @@
+//! An index kept in memory, for unit tests.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use util::CargoResult;
+
+use super::{file_path, Changes, IndexBackend, ReadFile};
+
+/// An index which only lives as long as the value itself.
+#[derive(Debug, Default)]
+pub struct MemoryIndex {
+    files: Mutex<HashMap<PathBuf, String>>,
+    config: Mutex<Option<String>>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> MemoryIndex {
+        Default::default()
+    }
+}
+
+impl IndexBackend for MemoryIndex {
+    fn read_file(&self, name: &str) -> CargoResult<Option<String>> {
+        let files = self.files.lock().unwrap();
+        Ok(files.get(&file_path(name)).cloned())
+    }
+
+    fn crate_names(&self) -> CargoResult<Vec<String>> {
+        let files = self.files.lock().unwrap();
+        let mut names = files
+            .keys()
+            .filter_map(|path| path.file_name())
+            .filter_map(|name| name.to_str())
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    fn modify_files(
+        &self,
+        f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+    ) -> CargoResult<()> {
+        let mut files = self.files.lock().unwrap();
+        let changes = {
+            let read = |name: &str| -> CargoResult<Option<String>> {
+                Ok(files.get(&file_path(name)).cloned())
+            };
+            match f(&read)? {
+                Some(changes) => changes,
+                None => return Ok(()),
+            }
+        };
+        for (name, contents) in changes.files {
+            match contents {
+                Some(contents) => {
+                    files.insert(file_path(&name), contents);
+                }
+                None => {
+                    files.remove(&file_path(&name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_config(&self) -> CargoResult<Option<String>> {
+        Ok(self.config.lock().unwrap().clone())
+    }
+
+    fn write_config(&self, contents: &str) -> CargoResult<()> {
+        *self.config.lock().unwrap() = Some(contents.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use semver;
+    use serde_json;
+
+    use git::Crate;
+    use index::{sync_config, IndexBackend, RegistryConfig};
+    use super::MemoryIndex;
+
+    fn entry(name: &str, vers: &str) -> Crate {
+        Crate {
+            name: name.to_string(),
+            vers: vers.to_string(),
+            deps: Vec::new(),
+            cksum: String::new(),
+            features: HashMap::new(),
+            yanked: None,
+        }
+    }
+
+    fn versions(index: &MemoryIndex, name: &str) -> Vec<(String, Option<bool>)> {
+        index
+            .entries(name)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.vers, c.yanked))
+            .collect()
+    }
+
+    #[test]
+    fn add_yank_and_remove_versions() {
+        let index = MemoryIndex::new();
+        index.add_version(&entry("foo", "1.0.0")).unwrap();
+        index.add_version(&entry("foo", "1.1.0")).unwrap();
+        index.add_version(&entry("Foo_Bar", "0.1.0")).unwrap();
+        assert!(index.read_file("foo_bar").unwrap().is_some());
+
+        // Adding a version twice, e.g. from a retried job, keeps one entry
+        index.add_version(&entry("foo", "1.1.0")).unwrap();
+        assert_eq!(index.entries("foo").unwrap().len(), 2);
+
+        let v = semver::Version::parse("1.1.0").unwrap();
+        index.set_yanked("foo", &[v], true).unwrap();
+        assert_eq!(
+            versions(&index, "foo"),
+            vec![
+                ("1.0.0".to_string(), None),
+                ("1.1.0".to_string(), Some(true)),
+            ]
+        );
+
+        index.remove_version("foo", "1.0.0").unwrap();
+        index.remove_version("foo", "2.0.0").unwrap();
+        assert_eq!(versions(&index, "foo"), vec![("1.1.0".to_string(), Some(true))]);
+        index.remove_version("foo", "1.1.0").unwrap();
+        assert_eq!(index.read_file("foo").unwrap(), None);
+
+        index.remove_crate("Foo_Bar").unwrap();
+        assert!(index.entries("foo_bar").unwrap().is_empty());
+    }
+
+    #[test]
+    fn yanking_a_missing_crate_does_nothing() {
+        let index = MemoryIndex::new();
+        let v = semver::Version::parse("1.0.0").unwrap();
+        index.set_yanked("foo", &[v], true).unwrap();
+        assert_eq!(index.read_file("foo").unwrap(), None);
+    }
+
+    #[test]
+    fn config_is_checked_and_repaired() {
+        let index = MemoryIndex::new();
+        index
+            .write_config("{\"dl\": \"http://old/api/v1/crates\", \"auth-required\": true}")
+            .unwrap();
+        let expected = RegistryConfig::new("https://example.com".to_string());
+
+        assert!(sync_config(&index, &expected, false).is_err());
+        sync_config(&index, &expected, true).unwrap();
+        let config = index.read_config().unwrap().unwrap();
+        let fields: serde_json::Value = serde_json::from_str(&config).unwrap();
+        assert_eq!(fields["dl"], "https://example.com/api/v1/crates");
+        assert_eq!(fields["api"], "https://example.com");
+        assert_eq!(fields["auth-required"], true);
+        sync_config(&index, &expected, false).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+//! Storage for the registry index, the files cargo reads to resolve
+//! dependencies.
+//!
+//! The index has one file per crate, holding one JSON line (a `git::Crate`)
+//! per version. Where those files live depends on the `IndexBackend` picked
+//! through `Config::index`:
+//!
+//! - `RemoteGitIndex`: a checkout of a git repository, whose changes are
+//!   pushed to its `origin` remote. This is what crates.io runs.
+//! - `LocalGitIndex`: a git repository with no remote, for self-hosted
+//!   registries and development.
+//! - `MemoryIndex`: kept in memory only, for unit tests.
+//!
+//! Any of them can be wrapped in a `CoalescingIndex` (see
+//! `Config::index_batch`) to write concurrent updates as a single commit.
+//!
+//! The `sparse` module also serves the index over plain HTTP, straight from
+//! the database, and `smart_http` serves the repository of the git backends
+//! to git clients.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use semver;
+use serde_json;
+
+use git::{Crate, Credentials};
+use util::{human, internal, CargoResult};
+
+pub mod check;
+pub mod coalesce;
+pub mod git;
+pub mod memory;
+pub mod smart_http;
+pub mod sparse;
+
+pub use self::coalesce::{BatchConfig, CoalescingIndex};
+pub use self::git::{LocalGitIndex, RemoteGitIndex};
+pub use self::memory::MemoryIndex;
+
+/// Selects the `IndexBackend` used by the server.
+#[derive(Clone, Debug)]
+pub enum IndexConfig {
+    /// A checkout of the index, pushing each change to its `origin` remote.
+    /// The checkout must already exist.
+    RemoteGit {
+        checkout: PathBuf,
+        credentials: Credentials,
+    },
+
+    /// A git repository with no remote, which is created if it doesn't exist.
+    LocalGit { path: PathBuf },
+
+    /// An index which is lost when the server stops.
+    Memory,
+}
+
+impl IndexConfig {
+    /// Opens the configured backend.
+    pub fn open(&self) -> CargoResult<Box<IndexBackend>> {
+        Ok(match *self {
+            IndexConfig::RemoteGit {
+                ref checkout,
+                ref credentials,
+            } => Box::new(RemoteGitIndex::open(checkout, credentials.clone())?),
+            IndexConfig::LocalGit { ref path } => Box::new(LocalGitIndex::open(path)?),
+            IndexConfig::Memory => Box::new(MemoryIndex::new()),
+        })
+    }
+
+    /// Returns the directory of the git repository holding the index, if it
+    /// is stored in one.
+    pub fn repo_path(&self) -> Option<&Path> {
+        match *self {
+            IndexConfig::RemoteGit { ref checkout, .. } => Some(checkout),
+            IndexConfig::LocalGit { ref path } => Some(path),
+            IndexConfig::Memory => None,
+        }
+    }
+}
+
+/// A change to the index file of a crate.
+#[derive(Debug)]
+pub struct Edit {
+    /// Describes the change, used as the commit message by git backends.
+    pub message: String,
+    /// The new contents of the file, or `None` to delete it.
+    pub contents: Option<String>,
+}
+
+/// The path of the file telling cargo where the API is and where to download
+/// crates from, relative to the root of the index.
+pub const CONFIG_PATH: &'static str = "config.json";
+
+/// The contents of `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegistryConfig {
+    pub dl: String,
+    pub api: String,
+    /// Makes cargo send its API token when fetching the index over HTTP,
+    /// which a private index requires.
+    #[serde(rename = "auth-required", default, skip_serializing_if = "is_false")]
+    pub auth_required: bool,
+}
+
+impl RegistryConfig {
+    /// The config of a registry whose API is at `api`, e.g.
+    /// `https://crates.io`.
+    ///
+    /// Downloads go through the API, which counts them and redirects to
+    /// `Storage::crate_location`.
+    pub fn new(api: String) -> RegistryConfig {
+        RegistryConfig {
+            dl: format!("{}/api/v1/crates", api),
+            api: api,
+            auth_required: false,
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Changes to the index files of several crates, made as a single commit.
+#[derive(Debug)]
+pub struct Changes {
+    pub message: String,
+    /// The new contents of the file of each crate, `None` deleting it.
+    pub files: Vec<(String, Option<String>)>,
+}
+
+/// Reads the current contents of the index file of a crate.
+pub type ReadFile<'a> = &'a Fn(&str) -> CargoResult<Option<String>>;
+
+/// Somewhere to keep the registry index.
+///
+/// Backends only need to read files and apply changes to them, the updates
+/// made by publishing, yanking and deleting are built on top of that.
+pub trait IndexBackend: Send + Sync {
+    /// Returns the contents of the index file of the crate `name`, or `None`
+    /// if it isn't in the index.
+    fn read_file(&self, name: &str) -> CargoResult<Option<String>>;
+
+    /// Returns the names of the crates which have a file in the index, in
+    /// lowercase like the files themselves, sorted.
+    fn crate_names(&self) -> CargoResult<Vec<String>>;
+
+    /// Calls `f` with a function reading the current contents of index
+    /// files, then applies the returned changes, if any.
+    ///
+    /// `f` may be called more than once if the files changed concurrently
+    /// while the changes were being applied.
+    fn modify_files(
+        &self,
+        f: &mut FnMut(ReadFile) -> CargoResult<Option<Changes>>,
+    ) -> CargoResult<()>;
+
+    /// Returns the contents of `config.json`, or `None` if there is none.
+    fn read_config(&self) -> CargoResult<Option<String>>;
+
+    /// Replaces `config.json`.
+    fn write_config(&self, contents: &str) -> CargoResult<()>;
+
+    /// Calls `f` with the current contents of the index file of the crate
+    /// `name`, then applies the returned edit, if any.
+    fn modify_file(
+        &self,
+        name: &str,
+        f: &mut FnMut(Option<&str>) -> CargoResult<Option<Edit>>,
+    ) -> CargoResult<()> {
+        self.modify_files(&mut |read| {
+            let edit = f(read(name)?.as_ref().map(|s| &**s))?;
+            Ok(edit.map(|edit| {
+                Changes {
+                    message: edit.message,
+                    files: vec![(name.to_string(), edit.contents)],
+                }
+            }))
+        })
+    }
+
+    /// Applies an update to the file of the crate it is about.
+    fn update(&self, update: Update) -> CargoResult<()> {
+        self.modify_file(update.crate_name(), &mut |prev| update.apply(prev))
+    }
+
+    /// Applies several updates in order, as a single change, and returns the
+    /// result of each of them. An update which can't be applied (e.g. yanking
+    /// a crate which isn't in the index) is left out of the change without
+    /// failing the others.
+    fn update_all(&self, updates: &[Update]) -> Vec<CargoResult<()>> {
+        let mut results = Vec::new();
+        let res = self.modify_files(&mut |read| {
+            // Called again from scratch if the backend has to retry.
+            results.clear();
+            let mut files = HashMap::<PathBuf, (String, Option<String>)>::new();
+            let mut paths = Vec::new();
+            let mut messages = Vec::new();
+            for update in updates {
+                let name = update.crate_name();
+                let path = file_path(name);
+                let prev = match files.get(&path) {
+                    Some(&(_, ref contents)) => contents.clone(),
+                    None => read(name)?,
+                };
+                match update.apply(prev.as_ref().map(|s| &**s)) {
+                    Ok(Some(edit)) => {
+                        if !files.contains_key(&path) {
+                            paths.push(path.clone());
+                        }
+                        files.insert(path, (name.to_string(), edit.contents));
+                        messages.push(edit.message);
+                        results.push(Ok(()));
+                    }
+                    Ok(None) => results.push(Ok(())),
+                    Err(e) => results.push(Err(e)),
+                }
+            }
+
+            if messages.is_empty() {
+                return Ok(None);
+            }
+            let message = if messages.len() == 1 {
+                messages.pop().unwrap()
+            } else {
+                format!(
+                    "Updating {} crates\n\n{}",
+                    paths.len(),
+                    messages.join("\n")
+                )
+            };
+            Ok(Some(Changes {
+                message: message,
+                files: paths
+                    .iter()
+                    .map(|path| files.remove(path).unwrap())
+                    .collect(),
+            }))
+        });
+
+        match res {
+            Ok(()) => results,
+            Err(e) => updates.iter().map(|_| Err(internal(&e))).collect(),
+        }
+    }
+
+    /// Adds a new version of a crate. Does nothing if the version is already
+    /// in the index.
+    fn add_version(&self, krate: &Crate) -> CargoResult<()> {
+        self.update(Update::AddVersion(krate.clone()))
+    }
+
+    /// Yanks or unyanks versions of a crate, as a single change.
+    fn set_yanked(
+        &self,
+        name: &str,
+        versions: &[semver::Version],
+        yanked: bool,
+    ) -> CargoResult<()> {
+        self.update(Update::SetYanked {
+            name: name.to_string(),
+            versions: versions.iter().map(|v| v.to_string()).collect(),
+            yanked: yanked,
+        })
+    }
+
+    /// Removes a single version of a crate, deleting the crate's file if it
+    /// was the last version in it. Does nothing if the version isn't in the
+    /// index.
+    fn remove_version(&self, name: &str, version: &str) -> CargoResult<()> {
+        self.update(Update::RemoveVersion {
+            name: name.to_string(),
+            version: version.to_string(),
+        })
+    }
+
+    /// Removes every version of a crate. Does nothing if the crate isn't in
+    /// the index.
+    fn remove_crate(&self, name: &str) -> CargoResult<()> {
+        self.update(Update::RemoveCrate {
+            name: name.to_string(),
+        })
+    }
+
+    /// Returns the entries of every version of a crate, in the order they
+    /// were published.
+    fn entries(&self, name: &str) -> CargoResult<Vec<Crate>> {
+        match self.read_file(name)? {
+            Some(contents) => contents.lines().map(parse_line).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// A change made to the index by publishing, yanking or deleting.
+#[derive(Debug, Clone)]
+pub enum Update {
+    AddVersion(Crate),
+    SetYanked {
+        name: String,
+        versions: Vec<String>,
+        yanked: bool,
+    },
+    RemoveVersion { name: String, version: String },
+    RemoveCrate { name: String },
+}
+
+impl Update {
+    /// The name of the crate whose file is updated.
+    pub fn crate_name(&self) -> &str {
+        match *self {
+            Update::AddVersion(ref krate) => &krate.name,
+            Update::SetYanked { ref name, .. }
+            | Update::RemoveVersion { ref name, .. }
+            | Update::RemoveCrate { ref name } => name,
+        }
+    }
+
+    /// Returns the edit making this update to the crate's file, given its
+    /// current contents, or `None` if there is nothing to change.
+    pub fn apply(&self, prev: Option<&str>) -> CargoResult<Option<Edit>> {
+        match *self {
+            Update::AddVersion(ref krate) => {
+                // A retried publish job may have added the version already.
+                for line in prev.unwrap_or("").lines() {
+                    let git_crate = parse_line(line)?;
+                    if git_crate.name == krate.name && git_crate.vers == krate.vers {
+                        return Ok(None);
+                    }
+                }
+                let mut new = prev.unwrap_or("").to_string();
+                new.push_str(&serde_json::to_string(krate).unwrap());
+                new.push('\n');
+                Ok(Some(Edit {
+                    message: format!("Updating crate `{}#{}`", krate.name, krate.vers),
+                    contents: Some(new),
+                }))
+            }
+            Update::SetYanked {
+                ref name,
+                ref versions,
+                yanked,
+            } => {
+                // The versions of a crate whose first publish job hasn't run
+                // yet aren't in the index. The job copies their yanked flag
+                // from the database when it adds them.
+                let prev = match prev {
+                    Some(prev) => prev,
+                    None => return Ok(None),
+                };
+                let new = prev.lines()
+                    .map(|line| {
+                        let mut git_crate = parse_line(line)?;
+                        if git_crate.name != *name || !versions.contains(&git_crate.vers) {
+                            return Ok(line.to_string());
+                        }
+                        git_crate.yanked = Some(yanked);
+                        Ok(serde_json::to_string(&git_crate).unwrap())
+                    })
+                    .collect::<CargoResult<Vec<String>>>()?;
+
+                let action = if yanked { "Yanking" } else { "Unyanking" };
+                let message = if versions.len() == 1 {
+                    format!("{} crate `{}#{}`", action, name, versions[0])
+                } else {
+                    format!("{} crate `{}` versions {}", action, name, versions.join(", "))
+                };
+                Ok(Some(Edit {
+                    message: message,
+                    contents: Some(new.join("\n") + "\n"),
+                }))
+            }
+            Update::RemoveVersion {
+                ref name,
+                ref version,
+            } => {
+                let prev = match prev {
+                    Some(prev) => prev,
+                    None => return Ok(None),
+                };
+                let mut new = Vec::new();
+                for line in prev.lines() {
+                    let git_crate = parse_line(line)?;
+                    if git_crate.name != *name || git_crate.vers != *version {
+                        new.push(line);
+                    }
+                }
+                if new.len() == prev.lines().count() {
+                    return Ok(None);
+                }
+                Ok(Some(Edit {
+                    message: format!("Deleting crate `{}#{}`", name, version),
+                    contents: if new.is_empty() {
+                        None
+                    } else {
+                        Some(new.join("\n") + "\n")
+                    },
+                }))
+            }
+            Update::RemoveCrate { ref name } => Ok(prev.map(|_| {
+                Edit {
+                    message: format!("Deleting crate `{}`", name),
+                    contents: None,
+                }
+            })),
+        }
+    }
+}
+
+/// Checks that `config.json` matches `expected`. If it doesn't, replaces it
+/// when `repair` is set, or returns an error otherwise.
+///
+/// Only `dl` and `api` are checked, any other keys in the file are kept.
+pub fn sync_config(
+    index: &IndexBackend,
+    expected: &RegistryConfig,
+    repair: bool,
+) -> CargoResult<()> {
+    let current = index.read_config()?;
+    let mut fields = current
+        .as_ref()
+        .and_then(|contents| {
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(contents).ok()
+        })
+        .unwrap_or_default();
+    let dl = serde_json::Value::String(expected.dl.clone());
+    let api = serde_json::Value::String(expected.api.clone());
+    if fields.get("dl") == Some(&dl) && fields.get("api") == Some(&api) {
+        return Ok(());
+    }
+    if !repair {
+        return Err(human(&format_args!(
+            "the index's config.json should contain `{}`, but is `{}`",
+            serde_json::to_string(expected).unwrap(),
+            current.as_ref().map(|s| s.trim()).unwrap_or("")
+        )));
+    }
+
+    fields.insert("dl".to_string(), dl);
+    fields.insert("api".to_string(), api);
+    let mut contents = serde_json::to_string_pretty(&fields)?;
+    contents.push('\n');
+    index.write_config(&contents)
+}
+
+/// Returns the path of the index file of the crate `name`, relative to the
+/// root of the index.
+pub fn file_path(name: &str) -> PathBuf {
+    let name = name.chars()
+        .flat_map(|c| c.to_lowercase())
+        .collect::<String>();
+    match name.len() {
+        1 => Path::new("1").join(&name),
+        2 => Path::new("2").join(&name),
+        3 => Path::new("3").join(&name[..1]).join(&name),
+        _ => Path::new(&name[0..2]).join(&name[2..4]).join(&name),
+    }
+}
+
+fn parse_line(line: &str) -> CargoResult<Crate> {
+    serde_json::from_str::<Crate>(line)
+        .map_err(|_| internal(&format_args!("couldn't decode: `{}`", line)))
+}
@@ -0,0 +1,141 @@
+//! Serves the git index over git's smart HTTP protocol, so that a
+//! self-hosted registry doesn't need a separate git server.
+//!
+//! The index is only served for fetching: pushes are rejected before they
+//! reach `git http-backend`, since the index must only change through the
+//! registry itself.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+
+use base64;
+use conduit::{Handler, Request, Response};
+use conduit_git_http_backend;
+use git2;
+
+use db::RequestTransaction;
+use user::RequestUser;
+use util::errors::std_error;
+use util::CargoResult;
+use User;
+
+/// Serves the git repository of the index, see `Config::serve_git_index`.
+// The git backend doesn't implement debug.
+#[allow(missing_debug_implementations)]
+pub struct Serve {
+    backend: conduit_git_http_backend::Serve,
+    require_token: bool,
+}
+
+impl Serve {
+    /// Serves the repository at `path`, requiring an API token to fetch it if
+    /// `require_token` is set.
+    pub fn new(path: &Path, require_token: bool) -> CargoResult<Serve> {
+        // `git http-backend` only serves repositories marked as exported.
+        let repo = git2::Repository::open(path)?;
+        File::create(repo.path().join("git-daemon-export-ok"))?;
+
+        Ok(Serve {
+            backend: conduit_git_http_backend::Serve(path.to_path_buf()),
+            require_token: require_token,
+        })
+    }
+}
+
+impl Handler for Serve {
+    fn call(&self, req: &mut Request) -> Result<Response, Box<Error + Send>> {
+        if is_push(req) {
+            return Ok(text_response(
+                (403, "Forbidden"),
+                "The index is read-only, crates are published with `cargo publish`",
+            ));
+        }
+        if self.require_token && !has_api_token(req).map_err(std_error)? {
+            return Ok(unauthorized());
+        }
+
+        let mut response = self.backend.call(req)?;
+        // `git http-backend` already asks not to cache its responses, but
+        // shared caches mustn't keep those of a private index either way.
+        let cache_control = if self.require_token {
+            "private, no-cache, max-age=0, must-revalidate"
+        } else {
+            "no-cache, max-age=0, must-revalidate"
+        };
+        response
+            .headers
+            .insert("Cache-Control".to_string(), vec![cache_control.to_string()]);
+        if self.require_token {
+            response
+                .headers
+                .insert("Vary".to_string(), vec!["Authorization".to_string()]);
+        }
+        Ok(response)
+    }
+}
+
+/// Whether the request is part of a `git push`, either the advertisement of
+/// the refs to push to or the push itself.
+fn is_push(req: &Request) -> bool {
+    let advertisement = req.query_string()
+        .map(|query| query.split('&').any(|param| param == "service=git-receive-pack"))
+        .unwrap_or(false);
+    advertisement || req.path().ends_with("git-receive-pack")
+}
+
+/// Whether the request is made with an API token, either as is in the
+/// `Authorization` header like cargo does, or as the password of HTTP basic
+/// authentication like git does.
+pub fn has_api_token(req: &Request) -> CargoResult<bool> {
+    if req.user().is_ok() {
+        return Ok(true);
+    }
+    let token = match req.headers().find("Authorization") {
+        Some(values) => basic_auth_password(values[0]),
+        None => None,
+    };
+    match token {
+        Some(token) => {
+            let conn = req.db_conn()?;
+            Ok(User::find_by_api_token(&conn, &token).is_ok())
+        }
+        None => Ok(false),
+    }
+}
+
+fn basic_auth_password(header: &str) -> Option<String> {
+    if !header.starts_with("Basic ") {
+        return None;
+    }
+    base64::decode(header["Basic ".len()..].trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| decoded.find(':').map(|i| decoded[i + 1..].to_string()))
+}
+
+/// The response to a request for a private index made without an API token.
+pub fn unauthorized() -> Response {
+    let mut response = text_response((401, "Unauthorized"), "An API token is required");
+    response.headers.insert(
+        "WWW-Authenticate".to_string(),
+        vec!["Basic realm=\"registry index\"".to_string()],
+    );
+    response
+}
+
+fn text_response(status: (u32, &'static str), body: &str) -> Response {
+    let mut headers = HashMap::new();
+    headers.insert(
+        "Content-Type".to_string(),
+        vec!["text/plain; charset=utf-8".to_string()],
+    );
+    headers.insert("Content-Length".to_string(), vec![body.len().to_string()]);
+    Response {
+        status: status,
+        headers: headers,
+        body: Box::new(Cursor::new(body.to_string().into_bytes())),
+    }
+}
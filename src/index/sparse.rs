@@ -0,0 +1,236 @@
+//! Serves the index over plain HTTP, one file per request, so that cargo can
+//! fetch the files of the crates it needs instead of cloning the whole git
+//! index.
+//!
+//! The files use the same layout as the git index (see `file_path`), and are
+//! generated from the database rather than read from an `IndexBackend`, so
+//! they are up to date as soon as a publish or yank is committed.
+//!
+//! Like the git index, the files are only served to requests made with an API
+//! token if `Config::private_index` is set.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use conduit::{Host, Request, Response};
+use conduit_router::RequestParams;
+use diesel::dsl::max;
+use diesel::prelude::*;
+use hex::ToHex;
+use serde_json;
+
+use app::RequestApp;
+use db::RequestTransaction;
+use dependency::Dependency;
+use git;
+use publish_job::PublishJobStatus;
+use schema::*;
+use util::errors::NotFound;
+use util::{hash, CargoResult, RequestUtils};
+use {Crate, Version};
+
+use super::smart_http::{has_api_token, unauthorized};
+use super::{file_path, RegistryConfig};
+
+/// Handles the `GET /index/config.json` route.
+///
+/// Points cargo at this server both for downloads and the API, at
+/// `Config::domain_name` if it is set or the host of the request otherwise.
+pub fn config(req: &mut Request) -> CargoResult<Response> {
+    if let Some(response) = require_token(req)? {
+        return Ok(response);
+    }
+    let config = match req.app().config.registry_config() {
+        Some(config) => config,
+        None => {
+            let host = match req.host() {
+                Host::Name(name) => name.to_string(),
+                Host::Socket(addr) => addr.to_string(),
+            };
+            let mut config =
+                RegistryConfig::new(format!("{}://{}", req.app().config.api_protocol, host));
+            config.auth_required = req.app().config.private_index;
+            config
+        }
+    };
+    let response = req.json(&config);
+    Ok(private_headers(req, response))
+}
+
+/// Handles the `GET /index/*path` route.
+///
+/// Responds with a strong `ETag` (the SHA256 of the file) and a
+/// `Last-Modified` date (the last time a version was published or yanked),
+/// which `ConditionalGet` turns into `304 Not Modified` responses.
+pub fn file(req: &mut Request) -> CargoResult<Response> {
+    if let Some(response) = require_token(req)? {
+        return Ok(response);
+    }
+    let path = req.params()["path"].to_string();
+    let name = path.rsplit('/').next().unwrap();
+    if file_path(name) != Path::new(&path) {
+        return Err(Box::new(NotFound));
+    }
+
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(name).first::<Crate>(&*conn)?;
+    if krate.name.to_lowercase() != name {
+        return Err(Box::new(NotFound));
+    }
+    let contents = render(&conn, &krate)?;
+    if contents.is_empty() {
+        return Err(Box::new(NotFound));
+    }
+
+    let published_at = versions::table
+        .filter(versions::crate_id.eq(krate.id))
+        .select(max(versions::created_at))
+        .first::<Option<NaiveDateTime>>(&*conn)?;
+    let yanked_at = version_yank_events::table
+        .inner_join(versions::table)
+        .filter(versions::crate_id.eq(krate.id))
+        .select(max(version_yank_events::created_at))
+        .first::<Option<NaiveDateTime>>(&*conn)?;
+    let last_modified = published_at.into_iter().chain(yanked_at).max().unwrap();
+
+    let etag = format!("\"{}\"", hash(contents.as_bytes()).to_hex());
+    let mut headers = HashMap::new();
+    headers.insert("ETag".to_string(), vec![etag]);
+    headers.insert(
+        "Last-Modified".to_string(),
+        vec![
+            last_modified
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        ],
+    );
+    headers.insert(
+        "Content-Type".to_string(),
+        vec!["text/plain; charset=utf-8".to_string()],
+    );
+    headers.insert(
+        "Content-Length".to_string(),
+        vec![contents.len().to_string()],
+    );
+    let response = Response {
+        status: (200, "OK"),
+        headers: headers,
+        body: Box::new(Cursor::new(contents.into_bytes())),
+    };
+    Ok(private_headers(req, response))
+}
+
+/// Returns the response to send instead of the index if it is private and
+/// the request has no API token.
+fn require_token(req: &Request) -> CargoResult<Option<Response>> {
+    if req.app().config.private_index && !has_api_token(req)? {
+        Ok(Some(unauthorized()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Keeps shared caches from storing the files of a private index.
+fn private_headers(req: &Request, mut response: Response) -> Response {
+    if req.app().config.private_index {
+        response
+            .headers
+            .insert("Cache-Control".to_string(), vec!["private".to_string()]);
+        response
+            .headers
+            .insert("Vary".to_string(), vec!["Authorization".to_string()]);
+    }
+    response
+}
+
+/// Renders the index file of a crate, one line per version in the order
+/// they were published.
+///
+/// Versions without a recorded checksum are left out, since cargo can't
+/// verify their downloads without one. The checksums of versions published
+/// before they were recorded are copied from the git index by the
+/// `backfill-version-checksums` binary.
+pub fn render(conn: &PgConnection, krate: &Crate) -> CargoResult<String> {
+    let mut contents = String::new();
+    for entry in entries(conn, krate)? {
+        if !entry.cksum.is_empty() {
+            contents.push_str(&serde_json::to_string(&entry).unwrap());
+            contents.push('\n');
+        }
+    }
+    Ok(contents)
+}
+
+/// Returns the index entries of every version of a crate according to the
+/// database, in the order they were published.
+///
+/// `cksum` is empty for versions published before checksums were recorded.
+/// Versions held for review are left out until they are approved.
+pub fn entries(conn: &PgConnection, krate: &Crate) -> CargoResult<Vec<git::Crate>> {
+    let mut versions = Version::belonging_to(krate)
+        .order(versions::id)
+        .load::<Version>(conn)?;
+    let ids = versions.iter().map(|v| v.id).collect::<Vec<_>>();
+    let held = publish_jobs::table
+        .filter(publish_jobs::version_id.eq_any(&ids))
+        .filter(publish_jobs::status.eq(PublishJobStatus::Held as i32))
+        .select(publish_jobs::version_id)
+        .load::<i32>(conn)?;
+    versions.retain(|v| !held.contains(&v.id));
+    let deps = Dependency::belonging_to(&versions)
+        .order(dependencies::id)
+        .load::<Dependency>(conn)?
+        .grouped_by(&versions);
+
+    let crate_ids = deps.iter()
+        .flat_map(|deps| deps.iter().filter_map(|dep| dep.crate_id))
+        .collect::<Vec<_>>();
+    let crate_names = crates::table
+        .filter(crates::id.eq_any(&crate_ids))
+        .select((crates::id, crates::name))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let mut entries = Vec::new();
+    for (version, deps) in versions.into_iter().zip(deps) {
+        let deps = deps.into_iter()
+            .map(|dep| {
+                let crate_name = match (dep.crate_id, dep.registry_crate_name) {
+                    (Some(id), _) => crate_names[&id].clone(),
+                    (None, Some(name)) => name,
+                    (None, None) => unreachable!("dependency without a crate"),
+                };
+                // Like cargo, the index uses the name the dependency was
+                // renamed to as its name, and the name of the crate as
+                // `package`.
+                let (name, package) = match dep.explicit_name {
+                    Some(explicit_name) => (explicit_name, Some(crate_name)),
+                    None => (crate_name, None),
+                };
+                git::Dependency {
+                    name: name,
+                    req: dep.req.to_string(),
+                    features: dep.features,
+                    optional: dep.optional,
+                    default_features: dep.default_features,
+                    target: dep.target,
+                    kind: Some(dep.kind),
+                    registry: dep.registry,
+                    package: package,
+                }
+            })
+            .collect();
+        entries.push(git::Crate {
+            name: krate.name.clone(),
+            vers: version.num.to_string(),
+            deps: deps,
+            cksum: version.checksum.unwrap_or_default(),
+            features: version.features,
+            yanked: Some(version.yanked),
+        });
+    }
+    Ok(entries)
+}
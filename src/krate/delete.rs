@@ -0,0 +1,171 @@
+//! Deletion of a crate by its owners, shortly after it was first published.
+//!
+//! Crates can otherwise only be yanked, since deleting them would break the
+//! builds of anyone depending on them. A crate which was published by mistake
+//! (an internal crate, say) is unlikely to have been depended on yet, so its
+//! owners can delete it themselves for a little while after publishing it, as
+//! long as no other crate depends on it.
+//!
+//! The database rows are deleted right away. Removing the crate from the
+//! index and its files from storage is left to a `PurgeDeletedCrate` job,
+//! which is retried until it succeeds. The name can't be used for a new crate
+//! until `NAME_COOLDOWN_DAYS` have passed, so that anyone who already
+//! downloaded the crate doesn't get something else under the same name.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::types::Integer;
+
+use app::{App, RequestApp};
+use background_job::Job;
+use db::RequestTransaction;
+use owner::{rights, Rights};
+use schema::*;
+use user::RequestUser;
+use util::errors::CargoError;
+use util::{human, internal, CargoResult, ChainError, RequestUtils};
+
+use super::{canon_crate_name, Crate};
+
+/// How long after a crate is first published its owners can delete it.
+pub const DELETION_WINDOW_HOURS: i64 = 72;
+
+/// How long the name of a deleted crate is kept from being used again.
+pub const NAME_COOLDOWN_DAYS: i64 = 30;
+
+#[derive(Insertable, Debug)]
+#[table_name = "deleted_crates"]
+struct NewDeletedCrate<'a> {
+    name: &'a str,
+    deleted_by: i32,
+    available_at: NaiveDateTime,
+}
+
+#[derive(QueryableByName, Debug)]
+#[table_name = "crates"]
+struct CrateCreatedAt {
+    created_at: NaiveDateTime,
+}
+
+/// Handles the `DELETE /crates/:crate_id` route.
+pub fn delete(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = &req.params()["crate_id"];
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
+
+    let now = Utc::now().naive_utc();
+    conn.transaction::<_, Box<CargoError>, _>(|| {
+        // Publishing a crate which depends on this one has to lock its row
+        // to insert the dependency, so holding the lock until the crate is
+        // deleted keeps a new dependent from showing up after the check.
+        let locked = sql_query("SELECT created_at FROM crates WHERE id = $1 FOR UPDATE")
+            .bind::<Integer, _>(krate.id)
+            .load::<CrateCreatedAt>(&*conn)?
+            .pop()
+            .ok_or_else(|| human("crate not found"))?;
+
+        let owners = krate.owners(&conn)?;
+        if rights(req.app(), &owners, user)? < Rights::Full {
+            return Err(human("only owners have permission to delete crates"));
+        }
+
+        if now.signed_duration_since(locked.created_at) > Duration::hours(DELETION_WINDOW_HOURS) {
+            return Err(human(&format_args!(
+                "crates can only be deleted within {} hours of being published, \
+                 please contact help@crates.io",
+                DELETION_WINDOW_HOURS
+            )));
+        }
+
+        let dependents = dependencies::table
+            .inner_join(versions::table)
+            .filter(dependencies::crate_id.eq(krate.id))
+            .filter(versions::crate_id.ne(krate.id))
+            .count()
+            .get_result::<i64>(&*conn)?;
+        if dependents > 0 {
+            return Err(human(
+                "crates which other crates depend on can't be deleted, \
+                 consider yanking its versions instead",
+            ));
+        }
+
+        let versions = versions::table
+            .filter(versions::crate_id.eq(krate.id))
+            .select(versions::num)
+            .load::<String>(&*conn)?;
+
+        diesel::delete(crates::table.find(krate.id)).execute(&*conn)?;
+        diesel::insert_into(deleted_crates::table)
+            .values(&NewDeletedCrate {
+                name: &krate.name,
+                deleted_by: user.id,
+                available_at: now + Duration::days(NAME_COOLDOWN_DAYS),
+            })
+            .execute(&*conn)?;
+        PurgeDeletedCrate {
+            name: krate.name.clone(),
+            versions: versions,
+        }.enqueue(&conn)?;
+        Ok(())
+    })?;
+
+    #[derive(Serialize)]
+    struct R {
+        ok: bool,
+    }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Returns an error if a crate named `name` was deleted too recently for the
+/// name to be used again.
+pub fn ensure_name_available(conn: &PgConnection, name: &str) -> CargoResult<()> {
+    let available_at = deleted_crates::table
+        .filter(canon_crate_name(deleted_crates::name).eq(canon_crate_name(name)))
+        .filter(deleted_crates::available_at.gt(Utc::now().naive_utc()))
+        .select(deleted_crates::available_at)
+        .order(deleted_crates::available_at.desc())
+        .first::<NaiveDateTime>(conn)
+        .optional()?;
+    match available_at {
+        Some(available_at) => Err(human(&format_args!(
+            "a crate named `{}` was recently deleted, the name can be used again after {}",
+            name,
+            available_at.format("%Y-%m-%d %H:%M UTC")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Removes a deleted crate from the index, then deletes the files of each
+/// of its versions from storage.
+///
+/// Both steps can be repeated safely, so a failed attempt is simply retried
+/// from the start.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PurgeDeletedCrate {
+    pub name: String,
+    pub versions: Vec<String>,
+}
+
+impl Job for PurgeDeletedCrate {
+    const JOB_TYPE: &'static str = "purge_deleted_crate";
+
+    fn perform(self, app: &App, _conn: &PgConnection) -> CargoResult<()> {
+        app.index.remove_crate(&self.name).chain_error(|| {
+            internal(&format_args!(
+                "could not delete crate `{}` from the index",
+                self.name
+            ))
+        })?;
+        for version in &self.versions {
+            app.storage.delete_version(&self.name, version)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Checks that the `[features]` of a crate being published only refer to
+//! things which exist.
+//!
+//! Each feature can enable:
+//!
+//! - another feature
+//! - an optional dependency, by its name in `Cargo.toml`
+//! - a feature of a dependency, written `dep/feature`
+//!
+//! Cargo refuses to resolve crates whose features refer to anything else, or
+//! which enable themselves, but only once something depends on them. These
+//! are rejected when publishing instead, so they never make it to the index.
+
+use std::collections::HashMap;
+
+use dependency::Kind;
+use upload;
+use util::{human, CargoResult};
+
+/// Returns an error describing the first feature which refers to a missing
+/// feature or dependency, or which enables itself.
+///
+/// Otherwise returns the `dep/feature` entries whose dependency isn't
+/// optional. Cargo accepts these, but they enable the dependency's feature
+/// unconditionally, which is rarely what was meant, so they are warned about.
+pub fn check(new_crate: &upload::NewCrate) -> CargoResult<Vec<&str>> {
+    // Dev-dependencies can't be enabled by features
+    let deps = new_crate
+        .deps
+        .iter()
+        .filter(|dep| match dep.kind {
+            Some(Kind::Dev) => false,
+            _ => true,
+        })
+        .map(|dep| {
+            let name = dep.explicit_name_in_toml.as_ref().unwrap_or(&dep.name);
+            (&**name, dep.optional)
+        })
+        .collect::<HashMap<_, _>>();
+    let features = new_crate
+        .features
+        .iter()
+        .map(|(name, values)| (&**name, values.iter().map(|v| &**v).collect()))
+        .collect::<HashMap<_, Vec<_>>>();
+
+    let mut names = features.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    let mut non_optional = Vec::new();
+    for &name in &names {
+        for &value in &features[name] {
+            let mut parts = value.splitn(2, '/');
+            let dep = parts.next().unwrap_or(value);
+            let is_dep_feature = parts.next().is_some();
+
+            match (deps.get(dep), is_dep_feature) {
+                (Some(&false), true) => non_optional.push(value),
+                (Some(_), true) | (Some(&true), false) => {}
+                (Some(&false), false) => {
+                    return Err(human(&format_args!(
+                        "feature `{}` enables `{}`, which is not an optional dependency",
+                        name,
+                        value
+                    )))
+                }
+                (None, true) => {
+                    return Err(human(&format_args!(
+                        "feature `{}` enables `{}`, but `{}` is not a dependency",
+                        name,
+                        value,
+                        dep
+                    )))
+                }
+                (None, false) => if !features.contains_key(value) {
+                    return Err(human(&format_args!(
+                        "feature `{}` enables `{}`, which is neither a feature \
+                         nor an optional dependency",
+                        name,
+                        value
+                    )));
+                },
+            }
+        }
+    }
+
+    let mut visited = Vec::new();
+    for &name in &names {
+        let mut path = Vec::new();
+        if let Some(cycle) = find_cycle(&features, name, &mut path, &mut visited) {
+            return Err(human(&format_args!(
+                "feature `{}` enables itself: {}",
+                cycle[0],
+                cycle.join(" -> ")
+            )));
+        }
+    }
+
+    non_optional.sort();
+    non_optional.dedup();
+    Ok(non_optional)
+}
+
+/// Walks the features enabled by `name` depth first, returning the features
+/// making up the first cycle found, starting and ending with the same one.
+///
+/// `path` holds the features leading to `name`, and `visited` those already
+/// known not to be part of a cycle.
+fn find_cycle<'a>(
+    features: &HashMap<&'a str, Vec<&'a str>>,
+    name: &'a str,
+    path: &mut Vec<&'a str>,
+    visited: &mut Vec<&'a str>,
+) -> Option<Vec<&'a str>> {
+    if let Some(start) = path.iter().position(|&n| n == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        return Some(cycle);
+    }
+    if visited.contains(&name) {
+        return None;
+    }
+
+    path.push(name);
+    for &value in &features[name] {
+        if features.contains_key(value) {
+            if let Some(cycle) = find_cycle(features, value, path, visited) {
+                return Some(cycle);
+            }
+        }
+    }
+    path.pop();
+    visited.push(name);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use serde_json;
+    use upload;
+
+    fn new_crate(features: ::serde_json::Value) -> upload::NewCrate {
+        serde_json::from_value(json!({
+            "name": "foo",
+            "vers": "1.0.0",
+            "deps": [
+                {
+                    "name": "bar",
+                    "version_req": "^1",
+                    "features": [],
+                    "optional": true,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "normal"
+                },
+                {
+                    "name": "baz",
+                    "version_req": "^1",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "normal"
+                },
+                {
+                    "name": "quux",
+                    "version_req": "^1",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "dev"
+                }
+            ],
+            "features": features,
+            "authors": ["foo"],
+            "description": "description",
+            "license": "MIT",
+        })).unwrap()
+    }
+
+    #[test]
+    fn valid_features() {
+        let krate = new_crate(json!({
+            "default": ["std", "bar"],
+            "std": ["bar/std"],
+            "nightly": ["std"],
+        }));
+        assert_eq!(check(&krate).unwrap(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn non_optional_dependency_features_are_warned_about() {
+        let krate = new_crate(json!({
+            "default": ["baz/std", "bar"],
+            "std": ["baz/std"],
+        }));
+        assert_eq!(check(&krate).unwrap(), vec!["baz/std"]);
+    }
+
+    #[test]
+    fn missing_features_and_dependencies() {
+        let err = check(&new_crate(json!({ "default": ["std"] }))).unwrap_err();
+        assert!(err.to_string().contains("neither a feature"), "{}", err);
+
+        let err = check(&new_crate(json!({ "default": ["baz"] }))).unwrap_err();
+        assert!(err.to_string().contains("not an optional dependency"), "{}", err);
+
+        let err = check(&new_crate(json!({ "default": ["missing/std"] }))).unwrap_err();
+        assert!(err.to_string().contains("`missing` is not a dependency"), "{}", err);
+
+        let err = check(&new_crate(json!({ "default": ["quux/std"] }))).unwrap_err();
+        assert!(err.to_string().contains("`quux` is not a dependency"), "{}", err);
+    }
+
+    #[test]
+    fn cycles() {
+        let err = check(&new_crate(json!({ "a": ["a"] }))).unwrap_err();
+        assert_eq!(err.to_string(), "feature `a` enables itself: a -> a");
+
+        let krate = new_crate(json!({
+            "a": ["b"],
+            "b": ["bar", "c"],
+            "c": ["a"],
+            "d": ["a"],
+        }));
+        let err = check(&krate).unwrap_err();
+        assert_eq!(err.to_string(), "feature `a` enables itself: a -> b -> c -> a");
+    }
+}
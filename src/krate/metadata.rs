@@ -177,8 +177,7 @@ pub fn readme(req: &mut Request) -> CargoResult<Response> {
     let version = &req.params()["version"];
 
     let redirect_url = req.app()
-        .config
-        .uploader
+        .storage
         .readme_location(crate_name, version)
         .ok_or_else(|| human("crate readme not found"))?;
 
@@ -13,7 +13,8 @@ use app::App;
 use badge::EncodableBadge;
 use crate_owner_invitation::NewCrateOwnerInvitation;
 use dependency::ReverseDependency;
-use owner::{CrateOwner, Owner, OwnerKind};
+use owner::{CrateOwner, Owner, OwnerKind, Team};
+use reserved_prefix::ReservedPrefix;
 use schema::*;
 use util::{human, CargoResult};
 use with_count::*;
@@ -25,6 +26,9 @@ pub mod owners;
 pub mod follow;
 pub mod downloads;
 pub mod metadata;
+pub mod typosquat;
+pub mod features;
+pub mod delete;
 
 /// Hosts in this blacklist are known to not be hosting documentation,
 /// and are possibly of malicious intent e.g. ad tracking networks, etc.
@@ -144,12 +148,20 @@ pub struct NewCrate<'a> {
 }
 
 impl<'a> NewCrate<'a> {
-    pub fn create_or_update(
+    /// Creates the crate, or updates it if it already exists.
+    ///
+    /// `is_team_member` is called to ask whether the uploader is a member of a
+    /// team when the name of a new crate starts with a prefix reserved for it.
+    pub fn create_or_update<F>(
         mut self,
         conn: &PgConnection,
         license_file: Option<&'a str>,
         uploader: i32,
-    ) -> CargoResult<Crate> {
+        is_team_member: F,
+    ) -> CargoResult<Crate>
+    where
+        F: FnOnce(&Team) -> CargoResult<bool>,
+    {
         use diesel::update;
 
         self.validate(license_file)?;
@@ -159,6 +171,9 @@ impl<'a> NewCrate<'a> {
             // To avoid race conditions, we try to insert
             // first so we know whether to add an owner
             if let Some(krate) = self.save_new_crate(conn, uploader)? {
+                // Either of these rolls back the insert
+                self.ensure_prefix_not_reserved(conn, is_team_member)?;
+                delete::ensure_name_available(conn, &krate.name)?;
                 return Ok(krate);
             }
 
@@ -245,6 +260,30 @@ impl<'a> NewCrate<'a> {
         }
     }
 
+    fn ensure_prefix_not_reserved<F>(
+        &self,
+        conn: &PgConnection,
+        is_team_member: F,
+    ) -> CargoResult<()>
+    where
+        F: FnOnce(&Team) -> CargoResult<bool>,
+    {
+        let (reserved, team) = match ReservedPrefix::matching(conn, self.name)? {
+            Some(matching) => matching,
+            None => return Ok(()),
+        };
+        if is_team_member(&team)? {
+            Ok(())
+        } else {
+            Err(human(&format_args!(
+                "the prefix `{}` is reserved for crates owned by `{}`, \
+                 only members of that team can publish new crates starting with it",
+                reserved.prefix,
+                team.login
+            )))
+        }
+    }
+
     fn save_new_crate(&self, conn: &PgConnection, user_id: i32) -> QueryResult<Option<Crate>> {
         use schema::crates::dsl::*;
 
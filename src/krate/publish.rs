@@ -6,31 +6,45 @@ use std::sync::Arc;
 
 use conduit::{Request, Response};
 use diesel::prelude::*;
+use diesel::result::Error::RollbackTransaction;
 use hex::ToHex;
 use serde_json;
 
-use app::RequestApp;
+use app::{App, RequestApp};
 use db::RequestTransaction;
 use dependency;
 use git;
 use owner::{rights, Rights};
+use publish_job::{EncodablePublishJob, PublishJob};
+use publish_rate_limit::{self, PublishAction};
 use render;
+use storage;
+use tarball;
 use upload;
 use user::RequestUser;
 use util::{read_fill, read_le_u32};
-use util::{human, internal, CargoResult, ChainError, RequestUtils};
+use util::{human, CargoError, CargoResult, ChainError, RequestUtils};
 use version::NewVersion;
-use {Badge, Category, Keyword, User};
+use version::files::VersionFile;
+use {Badge, Category, Crate, Keyword, User};
 
-use super::{EncodableCrate, NewCrate};
+use super::{features as feature_check, typosquat, EncodableCrate, NewCrate};
+
+#[derive(Serialize)]
+struct Warnings<'a> {
+    invalid_categories: Vec<&'a str>,
+    invalid_badges: Vec<&'a str>,
+    non_optional_dependency_features: Vec<&'a str>,
+}
 
 /// Handles the `PUT /crates/new` route.
 /// Used by `cargo publish` to publish a new crate or to publish a new version of an
 /// existing crate.
 ///
-/// Currently blocks the HTTP thread, perhaps some function calls can spawn new
-/// threads and return completion or error through other methods  a `cargo publish
-/// --status` command, via crates.io's front end, or email.
+/// The version is recorded and uploaded before responding, but adding it to the
+/// git index is left to a `PublishJob`. The id of that job is returned so its
+/// progress can be followed through the `GET /publish_jobs/:id` route, or so
+/// that the publisher can see it is held for review.
 pub fn publish(req: &mut Request) -> CargoResult<Response> {
     let app = Arc::clone(req.app());
     let (new_crate, user) = parse_new_headers(req)?;
@@ -38,70 +52,39 @@ pub fn publish(req: &mut Request) -> CargoResult<Response> {
     let name = &*new_crate.name;
     let vers = &*new_crate.vers;
     let repo = new_crate.repository.as_ref().map(|s| &**s);
-    let features = new_crate
-        .features
-        .iter()
-        .map(|(k, v)| {
-            (
-                k[..].to_string(),
-                v.iter().map(|v| v[..].to_string()).collect(),
-            )
-        })
-        .collect::<HashMap<String, Vec<String>>>();
-    let keywords = new_crate
-        .keywords
-        .as_ref()
-        .map(|kws| kws.iter().map(|kw| &**kw).collect())
-        .unwrap_or_else(Vec::new);
-
-    let categories = new_crate.categories.as_ref().map(|s| &s[..]).unwrap_or(&[]);
-    let categories: Vec<_> = categories.iter().map(|k| &**k).collect();
+    let features = features(&new_crate);
+    let keywords = keywords(&new_crate);
+    let categories = categories(&new_crate);
+    let non_optional_dependency_features = feature_check::check(&new_crate)?;
+    let ip = publish_rate_limit::client_ip(req);
 
     let conn = req.db_conn()?;
     // Create a transaction on the database, if there are no errors,
     // commit the transactions to record a new or updated crate.
     conn.transaction(|| {
-        // Persist the new crate, if it doesn't already exist
-        let persist = NewCrate {
-            name: name,
-            description: new_crate.description.as_ref().map(|s| &**s),
-            homepage: new_crate.homepage.as_ref().map(|s| &**s),
-            documentation: new_crate.documentation.as_ref().map(|s| &**s),
-            readme: new_crate.readme.as_ref().map(|s| &**s),
-            readme_file: new_crate.readme_file.as_ref().map(|s| &**s),
-            repository: repo,
-            license: new_crate.license.as_ref().map(|s| &**s),
-            max_upload_size: None,
+        let action = if Crate::by_name(name).count().get_result::<i64>(&*conn)? == 0 {
+            PublishAction::NewCrate
+        } else {
+            PublishAction::NewVersion
         };
+        app.config.publish_rate_limit.check(&conn, action, &user, ip)?;
 
+        // Persist the new crate, if it doesn't already exist
+        let persist = persisted_crate(&new_crate);
         let license_file = new_crate.license_file.as_ref().map(|s| &**s);
-        let krate = persist.create_or_update(&conn, license_file, user.id)?;
-
-        let owners = krate.owners(&conn)?;
-        if rights(req.app(), &owners, &user)? < Rights::Publish {
-            return Err(human(
-                "this crate exists but you don't seem to be an owner. \
-                 If you believe this is a mistake, perhaps you need \
-                 to accept an invitation to be an owner before \
-                 publishing.",
-            ));
-        }
+        let krate = persist.create_or_update(&conn, license_file, user.id, |team| {
+            team.contains_user(&app, &user)
+        })?;
+        let lookalike = if action == PublishAction::NewCrate {
+            typosquat::check(&conn, &krate.name, user.id)?
+        } else {
+            None
+        };
 
-        if krate.name != name {
-            return Err(human(
-                &format_args!("crate was previously named `{}`", krate.name),
-            ));
-        }
+        ensure_can_publish(&app, &conn, &krate, &user, name)?;
 
-        let length = req.content_length()
-            .chain_error(|| human("missing header: Content-Length"))?;
-        let max = krate
-            .max_upload_size
-            .map(|m| m as u64)
-            .unwrap_or(app.config.max_upload_size);
-        if length > max {
-            return Err(human(&format_args!("max upload size is: {}", max)));
-        }
+        let max = max_upload_size(&app, &krate);
+        verify_content_length(req, max)?;
 
         // This is only redundant for now. Eventually the duplication will be removed.
         let license = new_crate.license.clone();
@@ -111,7 +94,12 @@ pub fn publish(req: &mut Request) -> CargoResult<Response> {
             .save(&conn, &new_crate.authors)?;
 
         // Link this new version to all dependencies
-        let git_deps = dependency::add_dependencies(&conn, &new_crate.deps, version.id)?;
+        let git_deps = dependency::add_dependencies(
+            &conn,
+            &new_crate.deps,
+            version.id,
+            &app.config.allowed_registries,
+        )?;
 
         // Update all keywords for this crate
         Keyword::update_crate(&conn, &krate, &keywords)?;
@@ -139,60 +127,347 @@ pub fn publish(req: &mut Request) -> CargoResult<Response> {
         // If the git commands fail below, we shouldn't keep the crate on the
         // server.
         let max_unpack = cmp::max(app.config.max_unpack_size, max);
-        let (cksum, mut crate_bomb, mut readme_bomb) =
-            app.config
-                .uploader
-                .upload_crate(req, &krate, readme, max, max_unpack, vers)?;
+        let (uploaded, mut crate_bomb, mut readme_bomb) =
+            storage::upload_crate(req, &krate, &new_crate, readme, max, max_unpack)?;
         version.record_readme_rendering(&conn)?;
+        version.record_sizes(&conn, uploaded.crate_size, uploaded.unpacked_size)?;
+        version.record_checksum(&conn, &uploaded.checksum.to_hex())?;
+        VersionFile::insert_all(&conn, version.id, &uploaded.files)?;
 
-        // Register this crate in our local git repo.
+        // Queue the registration of this crate in our local git repo, it is
+        // pushed once this transaction is committed. New crates which look
+        // like popular ones wait for an administrator to approve them.
         let git_crate = git::Crate {
             name: name.to_string(),
             vers: vers.to_string(),
-            cksum: cksum.to_hex(),
+            cksum: uploaded.checksum.to_hex(),
             features: features,
             deps: git_deps,
             yanked: Some(false),
         };
-        git::add_crate(&**req.app(), &git_crate).chain_error(|| {
-            internal(&format_args!(
-                "could not add crate `{}` to the git repo",
-                name
-            ))
-        })?;
+        let publish_job = match lookalike {
+            Some(popular) => {
+                let reason = format!(
+                    "held for review: the name `{}` is similar to the popular crate `{}`",
+                    name,
+                    popular
+                );
+                PublishJob::hold(&conn, version.id, &git_crate, &reason)?
+            }
+            None => PublishJob::enqueue(&conn, version.id, &git_crate)?,
+        };
 
         // Now that we've come this far, we're committed!
         crate_bomb.path = None;
         readme_bomb.path = None;
 
-        #[derive(Serialize)]
-        struct Warnings<'a> {
-            invalid_categories: Vec<&'a str>,
-            invalid_badges: Vec<&'a str>,
-        }
         let warnings = Warnings {
             invalid_categories: ignored_invalid_categories,
             invalid_badges: ignored_invalid_badges,
+            non_optional_dependency_features: non_optional_dependency_features,
         };
 
         #[derive(Serialize)]
         struct R<'a> {
             #[serde(rename = "crate")] krate: EncodableCrate,
             warnings: Warnings<'a>,
+            publish_job: EncodablePublishJob,
         }
         Ok(req.json(&R {
+            publish_job: publish_job.encodable(name, &vers.to_string()),
             krate: krate.minimal_encodable(&max_version, None, false, None),
             warnings: warnings,
         }))
     })
 }
 
+/// Handles the `PUT /crates/validate` route.
+///
+/// Takes the same body as `PUT /crates/new` and runs the checks a publish
+/// would, inside a transaction which is always rolled back. Nothing is
+/// uploaded and the index isn't touched. Rather than stopping at the first
+/// problem, every failed check is listed in `errors`, along with the
+/// warnings a real publish would produce.
+pub fn validate(req: &mut Request) -> CargoResult<Response> {
+    let app = Arc::clone(req.app());
+    let new_crate = read_new_crate(req)?;
+    let user = req.user()?.clone();
+
+    let name = &*new_crate.name;
+    let vers = &*new_crate.vers;
+    let repo = new_crate.repository.as_ref().map(|s| &**s);
+    let features = features(&new_crate);
+    let keywords = keywords(&new_crate);
+    let categories = categories(&new_crate);
+
+    let conn = req.db_conn()?;
+    let mut outcome = None;
+    let rolled_back = conn.transaction::<(), _, _>(|| {
+        outcome = Some((|| -> CargoResult<_> {
+            let mut errors = Vec::new();
+            let mut warnings = Warnings {
+                invalid_categories: Vec::new(),
+                invalid_badges: Vec::new(),
+                non_optional_dependency_features: Vec::new(),
+            };
+
+            check(&conn, &mut errors, || check_metadata(&new_crate))?;
+            let non_optional_features =
+                check(&conn, &mut errors, || feature_check::check(&new_crate))?;
+            if let Some(non_optional_features) = non_optional_features {
+                warnings.non_optional_dependency_features = non_optional_features;
+            }
+
+            // `create_or_update` stops at the first problem with the crate, the
+            // license is checked on its own so that it is reported either way.
+            let license_file = new_crate.license_file.as_ref().map(|s| &**s);
+            check(&conn, &mut errors, || {
+                persisted_crate(&new_crate).validate_license(license_file)
+            })?;
+            let krate = check(&conn, &mut errors, || {
+                persisted_crate(&new_crate).create_or_update(
+                    &conn,
+                    license_file,
+                    user.id,
+                    |team| team.contains_user(&app, &user),
+                )
+            })?;
+
+            let max = match krate {
+                Some(ref krate) => {
+                    check(&conn, &mut errors, || {
+                        ensure_can_publish(&app, &conn, krate, &user, name)
+                    })?;
+                    max_upload_size(&app, krate)
+                }
+                None => app.config.max_upload_size,
+            };
+            check(&conn, &mut errors, || verify_content_length(req, max))?;
+
+            // A crate which couldn't be created still gets the checks which
+            // don't need it, so that their problems are reported too.
+            let version = match krate {
+                Some(ref krate) => {
+                    let license = new_crate.license.clone();
+                    check(&conn, &mut errors, || {
+                        NewVersion::new(krate.id, vers, &features, license, license_file)?
+                            .save(&conn, &new_crate.authors)
+                    })?
+                }
+                None => None,
+            };
+            match version {
+                Some(version) => {
+                    check(&conn, &mut errors, || {
+                        dependency::add_dependencies(
+                            &conn,
+                            &new_crate.deps,
+                            version.id,
+                            &app.config.allowed_registries,
+                        )
+                    })?;
+                }
+                None => {
+                    check(&conn, &mut errors, || {
+                        dependency::check_dependencies(
+                            &conn,
+                            &new_crate.deps,
+                            &app.config.allowed_registries,
+                        )
+                    })?;
+                }
+            }
+
+            let invalid_categories = match krate {
+                Some(ref krate) => {
+                    check(&conn, &mut errors, || {
+                        Ok(Keyword::update_crate(&conn, krate, &keywords)?)
+                    })?;
+                    let invalid_badges = check(&conn, &mut errors, || {
+                        Ok(Badge::update_crate(&conn, krate, new_crate.badges.as_ref())?)
+                    })?;
+                    if let Some(invalid_badges) = invalid_badges {
+                        warnings.invalid_badges = invalid_badges;
+                    }
+                    check(&conn, &mut errors, || {
+                        Ok(Category::update_crate(&conn, krate, &categories)?)
+                    })?
+                }
+                None => {
+                    check(&conn, &mut errors, || {
+                        Ok(Keyword::find_or_create_all(&conn, &keywords)?)
+                    })?;
+                    warnings.invalid_badges = Badge::invalid_badges(new_crate.badges.as_ref());
+                    check(&conn, &mut errors, || {
+                        Ok(Category::invalid_slugs(&conn, &categories)?)
+                    })?
+                }
+            };
+            if let Some(invalid_categories) = invalid_categories {
+                warnings.invalid_categories = invalid_categories;
+            }
+
+            if let Some(ref readme) = new_crate.readme {
+                let readme_file = new_crate.readme_file.as_ref().map_or("README.md", |s| &**s);
+                check(&conn, &mut errors, || {
+                    render::readme_to_html(readme, readme_file, repo)
+                })?;
+            }
+
+            let max_unpack = cmp::max(app.config.max_unpack_size, max);
+            check(&conn, &mut errors, || {
+                tarball::verify_crate(req, &new_crate, max, max_unpack)
+            })?;
+
+            Ok((errors, warnings))
+        })());
+        Err(RollbackTransaction)
+    });
+    match rolled_back {
+        Err(RollbackTransaction) => {}
+        other => other?,
+    }
+    let (errors, warnings) = outcome.expect("validation ran in the transaction")?;
+
+    #[derive(Serialize)]
+    struct Error {
+        detail: String,
+    }
+    #[derive(Serialize)]
+    struct R<'a> {
+        ok: bool,
+        errors: Vec<Error>,
+        warnings: Warnings<'a>,
+    }
+    Ok(req.json(&R {
+        ok: errors.is_empty(),
+        errors: errors
+            .into_iter()
+            .map(|detail| Error { detail: detail })
+            .collect(),
+        warnings: warnings,
+    }))
+}
+
+/// Runs one of the checks made by `validate` in a savepoint, so that a failed
+/// query doesn't prevent the following checks from running.
+///
+/// Errors meant for the user are added to `errors`, unless an earlier check
+/// already found the same one. Anything else is returned.
+fn check<T, F>(conn: &PgConnection, errors: &mut Vec<String>, f: F) -> CargoResult<Option<T>>
+where
+    F: FnOnce() -> CargoResult<T>,
+{
+    match conn.transaction(f) {
+        Ok(t) => Ok(Some(t)),
+        Err(ref e) if e.human() => {
+            let error = e.description().to_string();
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn persisted_crate(new_crate: &upload::NewCrate) -> NewCrate {
+    NewCrate {
+        name: &*new_crate.name,
+        description: new_crate.description.as_ref().map(|s| &**s),
+        homepage: new_crate.homepage.as_ref().map(|s| &**s),
+        documentation: new_crate.documentation.as_ref().map(|s| &**s),
+        readme: new_crate.readme.as_ref().map(|s| &**s),
+        readme_file: new_crate.readme_file.as_ref().map(|s| &**s),
+        repository: new_crate.repository.as_ref().map(|s| &**s),
+        license: new_crate.license.as_ref().map(|s| &**s),
+        max_upload_size: None,
+    }
+}
+
+fn features(new_crate: &upload::NewCrate) -> HashMap<String, Vec<String>> {
+    new_crate
+        .features
+        .iter()
+        .map(|(k, v)| {
+            (
+                k[..].to_string(),
+                v.iter().map(|v| v[..].to_string()).collect(),
+            )
+        })
+        .collect()
+}
+
+fn keywords(new_crate: &upload::NewCrate) -> Vec<&str> {
+    new_crate
+        .keywords
+        .as_ref()
+        .map(|kws| kws.iter().map(|kw| &**kw).collect())
+        .unwrap_or_else(Vec::new)
+}
+
+fn categories(new_crate: &upload::NewCrate) -> Vec<&str> {
+    let categories = new_crate.categories.as_ref().map(|s| &s[..]).unwrap_or(&[]);
+    categories.iter().map(|k| &**k).collect()
+}
+
+/// Checks that `user` may publish new versions of `krate`, and that `name`
+/// matches the crate's existing name exactly.
+fn ensure_can_publish(
+    app: &App,
+    conn: &PgConnection,
+    krate: &Crate,
+    user: &User,
+    name: &str,
+) -> CargoResult<()> {
+    let owners = krate.owners(conn)?;
+    if rights(app, &owners, user)? < Rights::Publish {
+        return Err(human(
+            "this crate exists but you don't seem to be an owner. \
+             If you believe this is a mistake, perhaps you need \
+             to accept an invitation to be an owner before \
+             publishing.",
+        ));
+    }
+
+    if krate.name != name {
+        return Err(human(
+            &format_args!("crate was previously named `{}`", krate.name),
+        ));
+    }
+    Ok(())
+}
+
+fn max_upload_size(app: &App, krate: &Crate) -> u64 {
+    krate
+        .max_upload_size
+        .map(|m| m as u64)
+        .unwrap_or(app.config.max_upload_size)
+}
+
+fn verify_content_length(req: &Request, max: u64) -> CargoResult<()> {
+    let length = req.content_length()
+        .chain_error(|| human("missing header: Content-Length"))?;
+    if length > max {
+        return Err(human(&format_args!("max upload size is: {}", max)));
+    }
+    Ok(())
+}
+
 /// Used by the `krate::new` function.
 ///
 /// This function parses the JSON headers to interpret the data and validates
 /// the data during and after the parsing. Returns crate metadata and user
 /// information.
 fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User)> {
+    let new = read_new_crate(req)?;
+    check_metadata(&new)?;
+    let user = req.user()?;
+    Ok((new, user.clone()))
+}
+
+/// Reads the JSON metadata at the start of a publish's body.
+fn read_new_crate(req: &mut Request) -> CargoResult<upload::NewCrate> {
     // Read the json upload request
     let amt = u64::from(read_le_u32(req.body())?);
     let max = req.app().config.max_upload_size;
@@ -202,10 +477,12 @@ fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User)>
     let mut json = vec![0; amt as usize];
     read_fill(req.body(), &mut json)?;
     let json = String::from_utf8(json).map_err(|_| human("json body was not valid utf-8"))?;
-    let new: upload::NewCrate = serde_json::from_str(&json)
-        .map_err(|e| human(&format_args!("invalid upload request: {}", e)))?;
+    serde_json::from_str(&json)
+        .map_err(|e| human(&format_args!("invalid upload request: {}", e)))
+}
 
-    // Make sure required fields are provided
+/// Makes sure the metadata fields crates.io requires are provided.
+fn check_metadata(new: &upload::NewCrate) -> CargoResult<()> {
     fn empty(s: Option<&String>) -> bool {
         s.map_or(true, |s| s.is_empty())
     }
@@ -228,7 +505,5 @@ fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User)>
             missing.join(", ")
         )));
     }
-
-    let user = req.user()?;
-    Ok((new, user.clone()))
+    Ok(())
 }
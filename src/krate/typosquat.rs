@@ -0,0 +1,207 @@
+//! Holds new crates whose names look like the names of popular crates for
+//! review, see `PublishJobStatus::Held`.
+//!
+//! A name is considered a lookalike of a popular crate when, after lowercasing
+//! it, it:
+//!
+//! - is the same name with separators (`-` and `_`) added or removed
+//! - is the same name with a common prefix or suffix added (`rust-`, `-rs`, ...)
+//! - only differs by characters which are easily confused (`0` and `o`, `rn`
+//!   and `m`, ...)
+//! - is one edit (insertion, deletion, substitution or swap of two adjacent
+//!   characters) away from it, for names long enough that this is unlikely to
+//!   be a coincidence
+//!
+//! Hyphen and underscore swaps of the full name are already rejected by the
+//! unique index on `canon_crate_name(name)`.
+//!
+//! Legitimate names are caught too (`future` looks like `futures`), which is
+//! why matches are only held for an administrator to look at rather than
+//! rejected.
+
+use std::cmp;
+use std::collections::HashSet;
+
+use diesel::prelude::*;
+
+use owner::OwnerKind;
+use schema::{crate_owners, crates};
+use util::CargoResult;
+
+/// How many of the most downloaded crates new names are compared against.
+const POPULAR_CRATES: i64 = 1000;
+
+/// Crates with fewer downloads than this are never considered popular, no
+/// matter how they rank.
+const MIN_DOWNLOADS: i32 = 10_000;
+
+/// Names shorter than this are only compared for exact lookalikes, since
+/// many short names are a single edit away from each other.
+const MIN_EDIT_DISTANCE_LEN: usize = 5;
+
+const PREFIXES: &[&str] = &["rust-", "rs-", "lib-", "lib"];
+const SUFFIXES: &[&str] = &["-rust", "-rs", "-lib"];
+
+/// Characters, or sequences of characters, which look alike in most fonts,
+/// each replaced by the one it is mistaken for.
+const CONFUSABLES: &[(&str, &str)] = &[
+    ("rn", "m"),
+    ("vv", "w"),
+    ("cl", "d"),
+    ("0", "o"),
+    ("1", "l"),
+    ("i", "l"),
+    ("5", "s"),
+];
+
+/// Returns the name of the popular crate `name` looks like, if any.
+///
+/// The crates `uploader` owns are left out, so that their owners can publish
+/// related crates such as `foo-derive` without being held.
+pub fn check(conn: &PgConnection, name: &str, uploader: i32) -> CargoResult<Option<String>> {
+    let owned = crate_owners::table
+        .filter(crate_owners::owner_id.eq(uploader))
+        .filter(crate_owners::owner_kind.eq(OwnerKind::User as i32))
+        .filter(crate_owners::deleted.eq(false))
+        .select(crate_owners::crate_id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let popular = crates::table
+        .select((crates::id, crates::name))
+        .filter(crates::downloads.ge(MIN_DOWNLOADS))
+        .order(crates::downloads.desc())
+        .limit(POPULAR_CRATES)
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .filter(|&(id, _)| !owned.contains(&id))
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+
+    Ok(lookalike_of(name, &popular).map(String::from))
+}
+
+/// Returns the first name in `popular` that `name` is a lookalike of.
+fn lookalike_of<'a>(name: &str, popular: &'a [String]) -> Option<&'a str> {
+    let canonical = canonicalize(name);
+    let unaffixed = strip_affixes(&canonical)
+        .into_iter()
+        .map(without_separators)
+        .collect::<Vec<_>>();
+    let name = without_separators(&canonical);
+    let skeleton = skeleton(&name);
+
+    popular.iter().map(|s| &**s).find(|popular_name| {
+        let popular_canonical = canonicalize(popular_name);
+        if popular_canonical == canonical {
+            return false;
+        }
+        let popular_name = without_separators(&popular_canonical);
+        popular_name == name || unaffixed.contains(&popular_name)
+            || skeleton == self::skeleton(&popular_name)
+            || (popular_name.len() >= MIN_EDIT_DISTANCE_LEN
+                && edit_distance(&name, &popular_name) <= 1)
+    })
+}
+
+/// Lowercases `name` and uses `-` for every separator, the same way
+/// `canon_crate_name` treats names as equal.
+fn canonicalize(name: &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == '_' { '-' } else { c })
+        .collect()
+}
+
+fn without_separators(name: &str) -> String {
+    name.chars().filter(|&c| c != '-').collect()
+}
+
+/// Returns what is left of `name` after removing each of the common prefixes
+/// and suffixes it has.
+fn strip_affixes(name: &str) -> Vec<&str> {
+    let prefixed = PREFIXES
+        .iter()
+        .filter(|prefix| name.starts_with(*prefix))
+        .map(|prefix| &name[prefix.len()..]);
+    let suffixed = SUFFIXES
+        .iter()
+        .filter(|suffix| name.ends_with(*suffix))
+        .map(|suffix| &name[..name.len() - suffix.len()]);
+    prefixed.chain(suffixed).filter(|n| !n.is_empty()).collect()
+}
+
+fn skeleton(name: &str) -> String {
+    CONFUSABLES
+        .iter()
+        .fold(name.to_string(), |name, &(from, to)| name.replace(from, to))
+}
+
+/// The number of insertions, deletions, substitutions and swaps of adjacent
+/// characters needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..a.len() + 1 {
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = cmp::min(
+                cmp::min(d[i - 1][j] + 1, d[i][j - 1] + 1),
+                d[i - 1][j - 1] + cost,
+            );
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = cmp::min(d[i][j], d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, lookalike_of};
+
+    fn popular() -> Vec<String> {
+        vec!["serde".into(), "serde_json".into(), "log".into(), "rand".into()]
+    }
+
+    #[test]
+    fn edit_distance_counts_swaps_once() {
+        assert_eq!(edit_distance("serde", "serde"), 0);
+        assert_eq!(edit_distance("serde", "sedre"), 1);
+        assert_eq!(edit_distance("serde", "serd"), 1);
+        assert_eq!(edit_distance("serde", "sserde"), 1);
+        assert_eq!(edit_distance("serde", "surdo"), 2);
+    }
+
+    #[test]
+    fn lookalikes_are_found() {
+        let popular = popular();
+        assert_eq!(lookalike_of("serde-rs", &popular), Some("serde"));
+        assert_eq!(lookalike_of("rust-serde", &popular), Some("serde"));
+        assert_eq!(lookalike_of("serdejson", &popular), Some("serde_json"));
+        assert_eq!(lookalike_of("serde-jsno", &popular), Some("serde_json"));
+        assert_eq!(lookalike_of("sedre", &popular), Some("serde"));
+        assert_eq!(lookalike_of("rn0g", &[String::from("mog")]), Some("mog"));
+        assert_eq!(lookalike_of("1og", &popular), Some("log"));
+        assert_eq!(lookalike_of("librand", &popular), Some("rand"));
+    }
+
+    #[test]
+    fn unrelated_names_are_allowed() {
+        let popular = popular();
+        assert_eq!(lookalike_of("serde", &popular), None);
+        assert_eq!(lookalike_of("Serde_JSON", &popular), None);
+        assert_eq!(lookalike_of("serde_yaml", &popular), None);
+        assert_eq!(lookalike_of("hog", &popular), None);
+        assert_eq!(lookalike_of("band", &popular), None);
+        assert_eq!(lookalike_of("logos", &popular), None);
+    }
+}
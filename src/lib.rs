@@ -8,6 +8,7 @@
 #![recursion_limit = "128"]
 
 extern crate ammonia;
+extern crate base64;
 extern crate chrono;
 extern crate comrak;
 extern crate curl;
@@ -60,7 +61,7 @@ pub use self::keyword::Keyword;
 pub use self::krate::Crate;
 pub use self::user::User;
 pub use self::version::Version;
-pub use self::uploaders::{Bomb, Uploader};
+pub use self::storage::{Bomb, Storage, StorageConfig};
 
 use std::sync::Arc;
 use std::error::Error;
@@ -71,6 +72,7 @@ use conduit_middleware::MiddlewareBuilder;
 use util::{R404, C, R};
 
 pub mod app;
+pub mod background_job;
 pub mod badge;
 pub mod boot;
 pub mod category;
@@ -83,14 +85,20 @@ pub mod download;
 pub mod git;
 pub mod github;
 pub mod http;
+pub mod index;
 pub mod keyword;
 pub mod krate;
+pub mod manifest;
 pub mod owner;
+pub mod publish_job;
+pub mod publish_rate_limit;
 pub mod render;
+pub mod reserved_prefix;
 pub mod schema;
+pub mod storage;
+pub mod tarball;
 pub mod token;
 pub mod upload;
-pub mod uploaders;
 pub mod user;
 pub mod util;
 pub mod version;
@@ -142,9 +150,14 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
 
     // Routes used by `cargo`
     api_router.put("/crates/new", C(krate::publish::publish));
+    api_router.put("/crates/validate", C(krate::publish::validate));
+    api_router.get("/publish_jobs/:id", C(publish_job::show));
     api_router.get("/crates/:crate_id/owners", C(krate::owners::owners));
     api_router.put("/crates/:crate_id/owners", C(krate::owners::add_owners));
     api_router.delete("/crates/:crate_id/owners", C(krate::owners::remove_owners));
+    api_router.delete("/crates/:crate_id", C(krate::delete::delete));
+    api_router.delete("/crates/:crate_id/yank", C(version::yank::yank_range));
+    api_router.put("/crates/:crate_id/unyank", C(version::yank::unyank_range));
     api_router.delete("/crates/:crate_id/:version/yank", C(version::yank::yank));
     api_router.put(
         "/crates/:crate_id/:version/unyank",
@@ -178,6 +191,15 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
         "/crates/:crate_id/:version/authors",
         C(version::metadata::authors),
     );
+    api_router.get(
+        "/crates/:crate_id/:version/yank_history",
+        C(version::yank::history),
+    );
+    api_router.get("/crates/:crate_id/:version/files", C(version::files::files));
+    api_router.get(
+        "/crates/:crate_id/:version/files/*path",
+        C(version::files::file),
+    );
     api_router.get(
         "/crates/:crate_id/downloads",
         C(krate::downloads::downloads),
@@ -218,6 +240,24 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
     api_router.put("/confirm/:email_token", C(user::confirm_user_email));
     api_router.put("/users/:user_id/resend", C(user::regenerate_token_and_send));
     api_router.get("/site_metadata", C(site_metadata::show_deployed_sha));
+
+    // Routes used by crates.io administrators
+    api_router.get("/admin/background_jobs", C(background_job::list));
+    api_router.get("/admin/publish_jobs", C(publish_job::list_held));
+    api_router.put(
+        "/admin/publish_jobs/:id/approve",
+        C(publish_job::approve),
+    );
+    api_router.put(
+        "/admin/users/:user_id/publish_rate_override",
+        C(publish_rate_limit::update_override),
+    );
+    api_router.get("/admin/reserved_prefixes", C(reserved_prefix::list));
+    api_router.put("/admin/reserved_prefixes", C(reserved_prefix::add));
+    api_router.delete(
+        "/admin/reserved_prefixes/:prefix",
+        C(reserved_prefix::remove),
+    );
     let api_router = Arc::new(R404(api_router));
 
     let mut router = RouteBuilder::new();
@@ -230,19 +270,29 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
     router.head("/api/v1/*path", R(Arc::clone(&api_router)));
     router.delete("/api/v1/*path", R(api_router));
 
+    router.get("/index/config.json", C(index::sparse::config));
+    router.get("/index/*path", C(index::sparse::file));
+
     router.get("/authorize_url", C(user::github_authorize));
     router.get("/authorize", C(user::github_access_token));
     router.delete("/logout", C(user::logout));
 
-    // Only serve the local checkout of the git index in development mode.
-    // In production, for crates.io, cargo gets the index from
-    // https://github.com/rust-lang/crates.io-index directly.
+    // Serve the local checkout of the git index in development mode, and in
+    // production only if asked to, for self-hosted registries. crates.io's
+    // index is served by https://github.com/rust-lang/crates.io-index.
     let env = app.config.env;
-    if env == Env::Development {
-        let s = conduit_git_http_backend::Serve(app.git_repo_checkout.clone());
-        let s = Arc::new(s);
-        router.get("/git/index/*path", R(Arc::clone(&s)));
-        router.post("/git/index/*path", R(s));
+    if let Some(path) = app.config.index.repo_path() {
+        if env == Env::Development {
+            let s = conduit_git_http_backend::Serve(path.to_path_buf());
+            let s = Arc::new(s);
+            router.get("/git/index/*path", R(Arc::clone(&s)));
+            router.post("/git/index/*path", R(s));
+        } else if app.config.serve_git_index {
+            let s = index::smart_http::Serve::new(path, app.config.private_index).unwrap();
+            let s = Arc::new(s);
+            router.get("/git/index/*path", R(Arc::clone(&s)));
+            router.post("/git/index/*path", R(s));
+        }
     }
 
     let mut m = MiddlewareBuilder::new(R404(router));
@@ -250,7 +300,11 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
     if env == Env::Development {
         // DebugMiddleware is defined below to print logs for each request.
         m.add(DebugMiddleware);
-        m.around(local_upload::Middleware::default());
+    }
+
+    // Serve the crate files stored on the local filesystem, if they are.
+    if let Some(root) = app.config.storage.local_root() {
+        m.around(local_upload::Middleware::new(root.to_path_buf()));
     }
 
     if env != Env::Test {
@@ -266,7 +320,7 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
         env == Env::Production,
     ));
     if env == Env::Production {
-        m.add(http::SecurityHeadersMiddleware::new(&app.config.uploader));
+        m.add(http::SecurityHeadersMiddleware::new(&app.config.storage));
     }
     m.add(app::AppMiddleware::new(app));
 
@@ -1,26 +1,99 @@
-//! This module implements middleware to serve crates and readmes
-//! from the `local_uploads/` directory. This is only used in
-//! development environments.
+//! This module implements middleware to serve crates and readmes stored on
+//! the local filesystem by `FilesystemStorage`.
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-use conduit::{Handler, Request, Response};
-use conduit_static::Static;
+use chrono::NaiveDateTime;
+use conduit::{Handler, Method, Request, Response};
 use conduit_middleware::AroundMiddleware;
 
-// Can't derive debug because of Handler and Static.
+// Can't derive debug because of Handler.
 #[allow(missing_debug_implementations)]
 pub struct Middleware {
     handler: Option<Box<Handler>>,
-    local_uploads: Static,
+    root: PathBuf,
 }
 
-impl Default for Middleware {
-    fn default() -> Middleware {
+impl Middleware {
+    /// Serves the files under `root`, see `StorageConfig::local_root`.
+    pub fn new(root: PathBuf) -> Middleware {
         Middleware {
             handler: None,
-            local_uploads: Static::new("local_uploads"),
+            root: root,
         }
     }
+
+    /// Returns the response for a stored file, or `None` if the request isn't
+    /// for one.
+    fn serve(&self, req: &Request) -> io::Result<Option<Response>> {
+        match req.method() {
+            Method::Get | Method::Head => {}
+            _ => return Ok(None),
+        }
+        let path = Path::new(req.path().trim_left_matches('/'));
+        let content_type = if path.starts_with("crates") {
+            "application/x-tar"
+        } else if path.starts_with("readmes") {
+            "text/html; charset=utf-8"
+        } else {
+            return Ok(None);
+        };
+        let is_normal = |c: Component| match c {
+            Component::Normal(..) => true,
+            _ => false,
+        };
+        if !path.components().all(is_normal) {
+            return Ok(None);
+        }
+
+        let file = match File::open(self.root.join(path)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Files are replaced rather than modified, so their size and
+        // modification time are enough to tell versions apart.
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            vec![content_type.to_string()],
+        );
+        headers.insert(
+            "Content-Length".to_string(),
+            vec![metadata.len().to_string()],
+        );
+        headers.insert(
+            "ETag".to_string(),
+            vec![format!("\"{:x}-{:x}\"", metadata.len(), modified)],
+        );
+        headers.insert(
+            "Last-Modified".to_string(),
+            vec![
+                NaiveDateTime::from_timestamp(modified as i64, 0)
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            ],
+        );
+        Ok(Some(Response {
+            status: (200, "OK"),
+            headers: headers,
+            body: Box::new(file),
+        }))
+    }
 }
 
 impl AroundMiddleware for Middleware {
@@ -31,9 +104,10 @@ impl AroundMiddleware for Middleware {
 
 impl Handler for Middleware {
     fn call(&self, req: &mut Request) -> Result<Response, Box<Error + Send>> {
-        match self.local_uploads.call(req) {
-            Ok(ref resp) if resp.status.0 == 404 => {}
-            ret => return ret,
+        match self.serve(req) {
+            Ok(Some(response)) => return Ok(response),
+            Ok(None) => {}
+            Err(e) => return Err(Box::new(e)),
         }
 
         self.handler.as_ref().unwrap().call(req)
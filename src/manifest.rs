@@ -0,0 +1,400 @@
+//! Checks that the `Cargo.toml` packaged in an uploaded crate agrees with the
+//! metadata `cargo publish` sends alongside it.
+//!
+//! The index entry and the database rows are generated from the JSON
+//! metadata, so without this check a modified client could publish index
+//! metadata that doesn't describe the source which is actually downloaded.
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+use semver;
+use toml;
+
+use dependency::Kind;
+use upload;
+use util::{human, CargoError, CargoResult};
+
+#[derive(Deserialize, Debug)]
+struct TomlManifest {
+    package: Option<TomlPackage>,
+    project: Option<TomlPackage>,
+    #[serde(default)] dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "dev-dependencies")]
+    dev_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "dev_dependencies")]
+    dev_dependencies2: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "build_dependencies")]
+    build_dependencies2: BTreeMap<String, TomlDependency>,
+    #[serde(default)] target: BTreeMap<String, TomlPlatform>,
+    #[serde(default)] features: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TomlPackage {
+    name: String,
+    version: String,
+    license: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TomlPlatform {
+    #[serde(default)] dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "dev-dependencies")]
+    dev_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "dev_dependencies")]
+    dev_dependencies2: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, rename = "build_dependencies")]
+    build_dependencies2: BTreeMap<String, TomlDependency>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum TomlDependency {
+    Simple(String),
+    Detailed(DetailedTomlDependency),
+}
+
+#[derive(Deserialize, Debug)]
+struct DetailedTomlDependency {
+    version: Option<String>,
+    #[serde(default)] optional: bool,
+    #[serde(rename = "default-features")] default_features: Option<bool>,
+    #[serde(rename = "default_features")] default_features2: Option<bool>,
+    #[serde(default)] features: Vec<String>,
+    package: Option<String>,
+    #[serde(rename = "registry-index")] registry_index: Option<String>,
+}
+
+/// The parts of a dependency which end up in the index, in a form which can
+/// be compared between the manifest and the JSON metadata.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Dependency {
+    kind: i32,
+    target: Option<String>,
+    name: String,
+    req: String,
+    optional: bool,
+    default_features: bool,
+    features: Vec<String>,
+    explicit_name: Option<String>,
+    registry: Option<String>,
+}
+
+/// Parses `manifest` and returns an error describing the first field which
+/// disagrees with `new_crate`.
+pub fn verify(manifest: &str, new_crate: &upload::NewCrate) -> CargoResult<()> {
+    let manifest: TomlManifest = toml::from_str(manifest)
+        .map_err(|e| human(&format_args!("uploaded `Cargo.toml` is invalid: {}", e)))?;
+
+    {
+        let package = manifest
+            .package
+            .as_ref()
+            .or_else(|| manifest.project.as_ref())
+            .ok_or_else(|| human("uploaded `Cargo.toml` has no `[package]` section"))?;
+
+        if new_crate.name != package.name {
+            return Err(mismatch("the package name"));
+        }
+        let version = semver::Version::parse(&package.version).map_err(|_| {
+            human(&format_args!(
+                "uploaded `Cargo.toml` has an invalid version: `{}`",
+                package.version
+            ))
+        })?;
+        if version != *new_crate.vers {
+            return Err(mismatch("the version"));
+        }
+        if new_crate.license != package.license {
+            return Err(mismatch("the license"));
+        }
+    }
+
+    let features = new_crate
+        .features
+        .iter()
+        .map(|(name, values)| {
+            let mut values = values.iter().map(|v| v.0.clone()).collect::<Vec<_>>();
+            values.sort();
+            (name.0.clone(), values)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let manifest_features = manifest
+        .features
+        .iter()
+        .map(|(name, values)| {
+            let mut values = values.clone();
+            values.sort();
+            (name.clone(), values)
+        })
+        .collect::<BTreeMap<_, _>>();
+    if features != manifest_features {
+        return Err(mismatch("the `[features]` section"));
+    }
+
+    let mut deps = new_crate
+        .deps
+        .iter()
+        .map(|dep| {
+            let mut features = dep.features.iter().map(|f| f.0.clone()).collect::<Vec<_>>();
+            features.sort();
+            Dependency {
+                kind: dep.kind.unwrap_or(Kind::Normal) as i32,
+                target: dep.target.clone(),
+                name: dep.name.0.clone(),
+                req: dep.version_req.0.to_string(),
+                optional: dep.optional,
+                default_features: dep.default_features,
+                features: features,
+                explicit_name: dep.explicit_name_in_toml.as_ref().map(|n| n.0.clone()),
+                registry: dep.registry.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    deps.sort();
+    let mut manifest_deps = manifest_dependencies(&manifest)?;
+    manifest_deps.sort();
+
+    for (dep, manifest_dep) in deps.iter().zip(&manifest_deps) {
+        if dep != manifest_dep {
+            let name = cmp::min(&dep.name, &manifest_dep.name);
+            return Err(mismatch(&format!("dependency `{}`", name)));
+        }
+    }
+    if deps.len() != manifest_deps.len() {
+        let extra = if deps.len() > manifest_deps.len() {
+            &deps[manifest_deps.len()]
+        } else {
+            &manifest_deps[deps.len()]
+        };
+        return Err(mismatch(&format!("dependency `{}`", extra.name)));
+    }
+
+    Ok(())
+}
+
+fn manifest_dependencies(manifest: &TomlManifest) -> CargoResult<Vec<Dependency>> {
+    let mut deps = Vec::new();
+    let tables = vec![
+        (None, Kind::Normal, &manifest.dependencies),
+        (None, Kind::Dev, &manifest.dev_dependencies),
+        (None, Kind::Dev, &manifest.dev_dependencies2),
+        (None, Kind::Build, &manifest.build_dependencies),
+        (None, Kind::Build, &manifest.build_dependencies2),
+    ];
+    let platform_tables = manifest.target.iter().flat_map(|(target, platform)| {
+        vec![
+            (Some(target), Kind::Normal, &platform.dependencies),
+            (Some(target), Kind::Dev, &platform.dev_dependencies),
+            (Some(target), Kind::Dev, &platform.dev_dependencies2),
+            (Some(target), Kind::Build, &platform.build_dependencies),
+            (Some(target), Kind::Build, &platform.build_dependencies2),
+        ]
+    });
+
+    for (target, kind, table) in tables.into_iter().chain(platform_tables) {
+        for (name, dep) in table {
+            let (req, optional, default_features, mut features, package, registry) = match *dep {
+                TomlDependency::Simple(ref req) => (Some(req), false, true, Vec::new(), None, None),
+                TomlDependency::Detailed(ref details) => (
+                    details.version.as_ref(),
+                    details.optional,
+                    details
+                        .default_features
+                        .or(details.default_features2)
+                        .unwrap_or(true),
+                    details.features.clone(),
+                    details.package.clone(),
+                    details.registry_index.clone(),
+                ),
+            };
+            // Like cargo, a dependency without a version requirement matches
+            // any version.
+            let req = match req {
+                Some(req) => semver::VersionReq::parse(req).map_err(|_| {
+                    human(&format_args!(
+                        "uploaded `Cargo.toml` has an invalid version requirement \
+                         for dependency `{}`: `{}`",
+                        name,
+                        req
+                    ))
+                })?,
+                None => semver::VersionReq::any(),
+            };
+            features.sort();
+            // A renamed dependency's key is the name it's renamed to
+            let (name, explicit_name) = match package {
+                Some(package) => (package, Some(name.clone())),
+                None => (name.clone(), None),
+            };
+            deps.push(Dependency {
+                kind: kind as i32,
+                target: target.cloned(),
+                name: name,
+                req: req.to_string(),
+                optional: optional,
+                default_features: default_features,
+                features: features,
+                explicit_name: explicit_name,
+                registry: registry,
+            });
+        }
+    }
+    Ok(deps)
+}
+
+fn mismatch(what: &str) -> Box<CargoError> {
+    human(&format_args!(
+        "{} in the uploaded `Cargo.toml` does not match the metadata sent by cargo",
+        what
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use serde_json;
+    use upload;
+
+    fn new_crate() -> upload::NewCrate {
+        serde_json::from_value(json!({
+            "name": "foo",
+            "vers": "1.2.0",
+            "deps": [
+                {
+                    "name": "bar",
+                    "version_req": "^0.3",
+                    "features": ["baz"],
+                    "optional": true,
+                    "default_features": false,
+                    "target": null,
+                    "kind": "normal"
+                },
+                {
+                    "name": "winapi",
+                    "version_req": "^0.2",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": "cfg(windows)",
+                    "kind": "normal"
+                },
+                {
+                    "name": "quickcheck",
+                    "version_req": "*",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "dev"
+                }
+            ],
+            "features": { "default": ["bar"] },
+            "authors": ["foo"],
+            "description": "description",
+            "license": "MIT/Apache-2.0",
+        })).unwrap()
+    }
+
+    const MANIFEST: &'static str = r#"
+[package]
+name = "foo"
+version = "1.2.0"
+license = "MIT/Apache-2.0"
+
+[dependencies]
+bar = { version = "0.3", optional = true, default-features = false, features = ["baz"] }
+
+[target.'cfg(windows)'.dependencies]
+winapi = "0.2"
+
+[dev-dependencies.quickcheck]
+path = "../quickcheck"
+
+[features]
+default = ["bar"]
+"#;
+
+    #[test]
+    fn matching_manifest() {
+        verify(MANIFEST, &new_crate()).unwrap();
+    }
+
+    #[test]
+    fn mismatched_package() {
+        let manifest = MANIFEST.replace("name = \"foo\"", "name = \"foo2\"");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("the package name"), "{}", err);
+
+        let manifest = MANIFEST.replace("1.2.0", "1.2.1");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("the version"), "{}", err);
+
+        let manifest = MANIFEST.replace("MIT/Apache-2.0", "MIT");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("the license"), "{}", err);
+    }
+
+    #[test]
+    fn mismatched_features() {
+        let manifest = MANIFEST.replace("default = [\"bar\"]", "default = []");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("the `[features]`"), "{}", err);
+    }
+
+    #[test]
+    fn mismatched_dependencies() {
+        let manifest = MANIFEST.replace("optional = true, ", "");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `bar`"), "{}", err);
+
+        let manifest = MANIFEST.replace("winapi = \"0.2\"", "winapi = \"0.3\"");
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `winapi`"), "{}", err);
+
+        let manifest = MANIFEST.replace(
+            "[dev-dependencies.quickcheck]",
+            "[dev-dependencies.other]",
+        );
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `other`"), "{}", err);
+
+        let manifest = format!("{}\n[build-dependencies]\ncc = \"1.0\"\n", MANIFEST);
+        let err = verify(&manifest, &new_crate()).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `cc`"), "{}", err);
+    }
+
+    #[test]
+    fn renamed_dependency_from_another_registry() {
+        let mut krate = new_crate();
+        krate.deps[0].explicit_name_in_toml = Some(upload::CrateName("bar2".into()));
+        krate.deps[0].registry = Some("https://example.com/index".into());
+        let manifest = MANIFEST.replace(
+            "bar = { version",
+            "bar2 = { package = \"bar\", registry-index = \"https://example.com/index\", version",
+        );
+        verify(&manifest, &krate).unwrap();
+
+        let manifest = MANIFEST.replace("bar = { version", "bar2 = { package = \"bar\", version");
+        let err = verify(&manifest, &krate).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `bar`"), "{}", err);
+
+        krate.deps[0].registry = None;
+        let err = verify(MANIFEST, &krate).unwrap_err();
+        assert!(err.to_string().starts_with("dependency `bar`"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let err = verify("[package", &new_crate()).unwrap_err();
+        assert!(err.to_string().contains("is invalid"), "{}", err);
+
+        let err = verify("[dependencies]\n", &new_crate()).unwrap_err();
+        assert!(err.to_string().contains("no `[package]`"), "{}", err);
+    }
+}
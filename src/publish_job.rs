@@ -0,0 +1,361 @@
+//! Deferred index updates for newly published versions.
+//!
+//! `cargo publish` only waits for the version to be recorded in the database
+//! and uploaded; adding it to the git index is done by an `AddCrateToIndex`
+//! background job. Each publish records a `PublishJob` that can be polled
+//! through the `GET /publish_jobs/:id` route to find out whether the index
+//! update went through.
+//!
+//! New crates whose names look like popular crates (see `krate::typosquat`)
+//! are held instead: their job isn't queued until an administrator approves
+//! it through the `PUT /admin/publish_jobs/:id/approve` route. Held crates
+//! are rejected by deleting them with the `delete-crate` binary.
+
+use chrono::NaiveDateTime;
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::types::Integer;
+use serde_json;
+
+use app::App;
+use background_job::Job;
+use db::RequestTransaction;
+use git;
+use index::Update;
+use schema::*;
+use user::RequestUser;
+use util::errors::CargoError;
+use util::{human, internal, CargoResult, ChainError, RequestUtils};
+use version::Version;
+
+#[derive(Identifiable, Associations, Debug)]
+#[belongs_to(Version)]
+#[table_name = "publish_jobs"]
+pub struct PublishJob {
+    pub id: i32,
+    pub version_id: i32,
+    pub status: PublishJobStatus,
+    pub index_entry: serde_json::Value,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+#[repr(u32)]
+pub enum PublishJobStatus {
+    Pending = 0,
+    Succeeded = 1,
+    Failed = 2,
+    /// Waiting for an administrator to approve the publish, with the reason
+    /// in `error`.
+    Held = 3,
+    // if you add a status here, be sure to update `build` below.
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "publish_jobs"]
+struct NewPublishJob {
+    version_id: i32,
+    status: i32,
+    index_entry: serde_json::Value,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodablePublishJob {
+    pub id: i32,
+    #[serde(rename = "crate")] pub krate: String,
+    pub num: String,
+    pub status: PublishJobStatus,
+    pub error: Option<String>,
+    #[serde(with = "::util::rfc3339")] pub created_at: NaiveDateTime,
+    #[serde(with = "::util::rfc3339")] pub updated_at: NaiveDateTime,
+}
+
+impl PublishJob {
+    /// Records that `krate` needs to be added to the index once the
+    /// surrounding transaction commits.
+    pub fn enqueue(conn: &PgConnection, version_id: i32, krate: &git::Crate) -> CargoResult<Self> {
+        let new_job = NewPublishJob {
+            version_id: version_id,
+            status: PublishJobStatus::Pending as i32,
+            index_entry: serde_json::to_value(krate)?,
+            error: None,
+        };
+
+        let publish_job = diesel::insert_into(publish_jobs::table)
+            .values(&new_job)
+            .get_result::<PublishJob>(conn)?;
+        AddCrateToIndex {
+            publish_job_id: publish_job.id,
+        }.enqueue(conn)?;
+        Ok(publish_job)
+    }
+
+    /// Records that `krate` needs to be added to the index, but only once an
+    /// administrator has approved it, see `approve`.
+    pub fn hold(
+        conn: &PgConnection,
+        version_id: i32,
+        krate: &git::Crate,
+        reason: &str,
+    ) -> CargoResult<Self> {
+        let new_job = NewPublishJob {
+            version_id: version_id,
+            status: PublishJobStatus::Held as i32,
+            index_entry: serde_json::to_value(krate)?,
+            error: Some(reason.to_string()),
+        };
+
+        diesel::insert_into(publish_jobs::table)
+            .values(&new_job)
+            .get_result::<PublishJob>(conn)
+            .map_err(Into::into)
+    }
+
+    pub fn encodable(self, crate_name: &str, num: &str) -> EncodablePublishJob {
+        EncodablePublishJob {
+            id: self.id,
+            krate: crate_name.to_string(),
+            num: num.to_string(),
+            status: self.status,
+            error: self.error,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+#[derive(QueryableByName, Debug)]
+#[table_name = "versions"]
+struct VersionYanked {
+    yanked: bool,
+}
+
+/// Adds the index entry of a `PublishJob` to the index.
+///
+/// Failed pushes are retried by the job runner; the publish job is only
+/// marked as failed once the runner gives up.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AddCrateToIndex {
+    pub publish_job_id: i32,
+}
+
+impl AddCrateToIndex {
+    /// Loads the publish job and the entry to add to the index.
+    ///
+    /// Returns `None` if the publish job is gone, which happens when its
+    /// crate was deleted before the job ran. There's nothing left to add.
+    fn load(&self, conn: &PgConnection) -> CargoResult<Option<(PublishJob, git::Crate)>> {
+        let job = match publish_jobs::table
+            .find(self.publish_job_id)
+            .first::<PublishJob>(conn)
+            .optional()?
+        {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        let mut krate = serde_json::from_value::<git::Crate>(job.index_entry.clone())?;
+
+        // The version may have been yanked since it was published. Locking
+        // it until the entry is in the index makes a concurrent yank either
+        // happen first and be picked up here, or wait for the entry to be
+        // there to update it.
+        let version = sql_query("SELECT yanked FROM versions WHERE id = $1 FOR UPDATE")
+            .bind::<Integer, _>(job.version_id)
+            .load::<VersionYanked>(conn)?
+            .pop()
+            .ok_or_else(|| internal("the version of the publish job was deleted"))?;
+        krate.yanked = Some(version.yanked);
+        Ok(Some((job, krate)))
+    }
+}
+
+impl Job for AddCrateToIndex {
+    const JOB_TYPE: &'static str = "add_crate_to_index";
+
+    /// The versions published since the last poll are added to the index as
+    /// a single change.
+    const BATCH_SIZE: usize = 50;
+
+    fn perform(self, app: &App, conn: &PgConnection) -> CargoResult<()> {
+        Self::perform_all(vec![self], app, conn).pop().unwrap()
+    }
+
+    fn perform_all(jobs: Vec<Self>, app: &App, conn: &PgConnection) -> Vec<CargoResult<()>> {
+        // A job which can't be loaded only fails itself. Each one is loaded
+        // in its own transaction so that its errors don't abort the others.
+        let loaded = jobs.iter()
+            .map(|job| conn.transaction::<_, Box<CargoError>, _>(|| job.load(conn)))
+            .collect::<Vec<_>>();
+        let updates = loaded
+            .iter()
+            .filter_map(|loaded| match *loaded {
+                Ok(Some((_, ref krate))) => Some(Update::AddVersion(krate.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut added = app.index.update_all(&updates).into_iter();
+
+        loaded
+            .into_iter()
+            .map(|loaded| -> CargoResult<()> {
+                let (job, krate) = match loaded? {
+                    Some(loaded) => loaded,
+                    None => return Ok(()),
+                };
+                added.next().unwrap().chain_error(|| {
+                    internal(&format_args!(
+                        "could not add crate `{}` to the index",
+                        krate.name
+                    ))
+                })?;
+                diesel::update(&job)
+                    .set((
+                        publish_jobs::status.eq(PublishJobStatus::Succeeded as i32),
+                        publish_jobs::error.eq(None::<String>),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            })
+            .collect()
+    }
+
+    fn dead(self, _app: &App, conn: &PgConnection, error: &str) -> CargoResult<()> {
+        diesel::update(publish_jobs::table.find(self.publish_job_id))
+            .set((
+                publish_jobs::status.eq(PublishJobStatus::Failed as i32),
+                publish_jobs::error.eq(error),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+/// Handles the `GET /publish_jobs/:id` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let id = publish_job_id(req)?;
+    let conn = req.db_conn()?;
+
+    let (job, num, crate_name) = publish_jobs::table
+        .inner_join(versions::table.inner_join(crates::table))
+        .filter(publish_jobs::id.eq(id))
+        .select((publish_jobs::all_columns, versions::num, crates::name))
+        .first::<(PublishJob, String, String)>(&*conn)?;
+
+    #[derive(Serialize)]
+    struct R {
+        publish_job: EncodablePublishJob,
+    }
+    Ok(req.json(&R {
+        publish_job: job.encodable(&crate_name, &num),
+    }))
+}
+
+/// Handles the `GET /admin/publish_jobs` route.
+///
+/// Lists the publishes which are held for review, oldest first.
+pub fn list_held(req: &mut Request) -> CargoResult<Response> {
+    req.user()?.require_admin()?;
+    let conn = req.db_conn()?;
+
+    let jobs = publish_jobs::table
+        .inner_join(versions::table.inner_join(crates::table))
+        .filter(publish_jobs::status.eq(PublishJobStatus::Held as i32))
+        .order(publish_jobs::id)
+        .select((publish_jobs::all_columns, versions::num, crates::name))
+        .load::<(PublishJob, String, String)>(&*conn)?
+        .into_iter()
+        .map(|(job, num, crate_name)| job.encodable(&crate_name, &num))
+        .collect();
+
+    #[derive(Serialize)]
+    struct R {
+        publish_jobs: Vec<EncodablePublishJob>,
+    }
+    Ok(req.json(&R { publish_jobs: jobs }))
+}
+
+/// Handles the `PUT /admin/publish_jobs/:id/approve` route.
+///
+/// Queues the index update of a held publish.
+pub fn approve(req: &mut Request) -> CargoResult<Response> {
+    req.user()?.require_admin()?;
+    let id = publish_job_id(req)?;
+    let conn = req.db_conn()?;
+
+    let job = conn.transaction::<_, Box<CargoError>, _>(|| {
+        let held = publish_jobs::table
+            .filter(publish_jobs::id.eq(id))
+            .filter(publish_jobs::status.eq(PublishJobStatus::Held as i32));
+        let job = diesel::update(held)
+            .set((
+                publish_jobs::status.eq(PublishJobStatus::Pending as i32),
+                publish_jobs::error.eq(None::<String>),
+            ))
+            .get_result::<PublishJob>(&*conn)
+            .optional()?
+            .ok_or_else(|| human("this publish isn't held for review"))?;
+        AddCrateToIndex {
+            publish_job_id: job.id,
+        }.enqueue(&*conn)?;
+        Ok(job)
+    })?;
+
+    let (num, crate_name) = versions::table
+        .inner_join(crates::table)
+        .filter(versions::id.eq(job.version_id))
+        .select((versions::num, crates::name))
+        .first::<(String, String)>(&*conn)?;
+
+    #[derive(Serialize)]
+    struct R {
+        publish_job: EncodablePublishJob,
+    }
+    Ok(req.json(&R {
+        publish_job: job.encodable(&crate_name, &num),
+    }))
+}
+
+fn publish_job_id(req: &mut Request) -> CargoResult<i32> {
+    req.params()["id"]
+        .parse::<i32>()
+        .map_err(|_| human("invalid publish job id"))
+}
+
+impl Queryable<publish_jobs::SqlType, Pg> for PublishJob {
+    #[cfg_attr(feature = "cargo-clippy", allow(type_complexity))]
+    type Row = (
+        i32,
+        i32,
+        i32,
+        serde_json::Value,
+        Option<String>,
+        NaiveDateTime,
+        NaiveDateTime,
+    );
+
+    fn build(row: Self::Row) -> Self {
+        PublishJob {
+            id: row.0,
+            version_id: row.1,
+            status: match row.2 {
+                0 => PublishJobStatus::Pending,
+                1 => PublishJobStatus::Succeeded,
+                2 => PublishJobStatus::Failed,
+                3 => PublishJobStatus::Held,
+                n => panic!("unknown publish job status: {}", n),
+            },
+            index_entry: row.3,
+            error: row.4,
+            created_at: row.5,
+            updated_at: row.6,
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Throttling of `PUT /crates/new`.
+//!
+//! Each publish takes a token from two buckets: one for the user publishing
+//! and one for the IP address the request came from. Publishing a new crate
+//! and publishing a new version of an existing crate use separate budgets.
+//! Buckets are stored in the database so that every instance of the server
+//! sees the same counts.
+//!
+//! Admins can raise (or lower) the size of a user's buckets through the
+//! `PUT /admin/users/:user_id/publish_rate_override` route. Users with an
+//! override are trusted not to be shared between people, so the IP address
+//! they publish from isn't throttled.
+
+use std::cmp;
+use std::env;
+use std::io::Read;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{self, NaiveDateTime, Utc};
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use serde_json;
+
+use db::RequestTransaction;
+use schema::{publish_limit_buckets, publish_rate_overrides, users};
+use user::RequestUser;
+use util::errors::TooManyRequests;
+use util::{human, CargoResult, RequestUtils};
+use User;
+
+/// The two kinds of publishes, which are throttled separately.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum PublishAction {
+    NewCrate = 0,
+    NewVersion = 1,
+}
+
+/// The size of a bucket and how quickly it is refilled.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// How long it takes for one token to be added back to the bucket.
+    pub rate: Duration,
+    /// How many tokens a full bucket holds, i.e. how many publishes can be
+    /// made in a row.
+    pub burst: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PublishRateLimit {
+    pub new_crate: RateLimit,
+    pub new_version: RateLimit,
+}
+
+impl Default for PublishRateLimit {
+    /// Allows bursts of 5 new crates and one more every 10 minutes, and
+    /// bursts of 30 new versions and one more every minute.
+    fn default() -> Self {
+        PublishRateLimit {
+            new_crate: RateLimit {
+                rate: Duration::from_secs(10 * 60),
+                burst: 5,
+            },
+            new_version: RateLimit {
+                rate: Duration::from_secs(60),
+                burst: 30,
+            },
+        }
+    }
+}
+
+impl RateLimit {
+    fn from_env(action: &str, default: RateLimit) -> RateLimit {
+        RateLimit {
+            rate: parse_env(&format!("PUBLISH_RATE_{}_SECONDS", action))
+                .map(Duration::from_secs)
+                .unwrap_or(default.rate),
+            burst: parse_env(&format!("PUBLISH_BURST_{}", action)).unwrap_or(default.burst),
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name)
+        .ok()
+        .map(|value| match value.parse() {
+            Ok(value) => value,
+            Err(..) => panic!("couldn't parse {}", name),
+        })
+}
+
+#[derive(QueryableByName, Debug)]
+#[table_name = "publish_limit_buckets"]
+struct Bucket {
+    tokens: i32,
+    last_refill: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize, Deserialize, Clone, Copy,
+         Debug)]
+#[primary_key(user_id)]
+#[table_name = "publish_rate_overrides"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct PublishRateOverride {
+    pub user_id: i32,
+    pub new_crate_burst: Option<i32>,
+    pub new_version_burst: Option<i32>,
+}
+
+impl PublishRateLimit {
+    /// The default limits, with any of them overridden by the
+    /// `PUBLISH_RATE_*` and `PUBLISH_BURST_*` variables, see
+    /// `Config::default`.
+    pub fn from_env() -> Self {
+        let default = PublishRateLimit::default();
+        PublishRateLimit {
+            new_crate: RateLimit::from_env("NEW_CRATE", default.new_crate),
+            new_version: RateLimit::from_env("NEW_VERSION", default.new_version),
+        }
+    }
+
+    fn limit(&self, action: PublishAction) -> RateLimit {
+        match action {
+            PublishAction::NewCrate => self.new_crate,
+            PublishAction::NewVersion => self.new_version,
+        }
+    }
+
+    /// Takes a token from the buckets of `user` and `ip` for `action`, or
+    /// returns a `TooManyRequests` error if either of them is empty.
+    ///
+    /// This should be called in the transaction of the publish, so that the
+    /// token is given back if the publish fails.
+    pub fn check(
+        &self,
+        conn: &PgConnection,
+        action: PublishAction,
+        user: &User,
+        ip: IpAddr,
+    ) -> CargoResult<()> {
+        let mut limit = self.limit(action);
+        let now = Utc::now().naive_utc();
+
+        let user_override = publish_rate_overrides::table
+            .find(user.id)
+            .first::<PublishRateOverride>(conn)
+            .optional()?;
+        match user_override {
+            Some(user_override) => {
+                let burst = match action {
+                    PublishAction::NewCrate => user_override.new_crate_burst,
+                    PublishAction::NewVersion => user_override.new_version_burst,
+                };
+                limit.burst = burst.unwrap_or(limit.burst);
+            }
+            None => take_token(conn, &format!("ip:{}", ip), action, limit, now)?,
+        }
+        take_token(conn, &format!("user:{}", user.id), action, limit, now)
+    }
+}
+
+fn take_token(
+    conn: &PgConnection,
+    bucket: &str,
+    action: PublishAction,
+    limit: RateLimit,
+    now: NaiveDateTime,
+) -> CargoResult<()> {
+    use diesel::sql_query;
+    use diesel::types::{Integer, Text};
+
+    diesel::insert_into(publish_limit_buckets::table)
+        .values((
+            publish_limit_buckets::bucket.eq(bucket),
+            publish_limit_buckets::action.eq(action as i32),
+            publish_limit_buckets::tokens.eq(limit.burst),
+            publish_limit_buckets::last_refill.eq(now),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    let row = sql_query(
+        "SELECT tokens, last_refill FROM publish_limit_buckets \
+         WHERE bucket = $1 AND action = $2 FOR UPDATE",
+    ).bind::<Text, _>(bucket)
+        .bind::<Integer, _>(action as i32)
+        .load::<Bucket>(conn)?
+        .pop()
+        .expect("the bucket was just inserted");
+
+    let rate = chrono::Duration::from_std(limit.rate).expect("rate limit is too long");
+    let elapsed = now.signed_duration_since(row.last_refill);
+    let refilled = cmp::max(
+        elapsed.num_milliseconds() / cmp::max(rate.num_milliseconds(), 1),
+        0,
+    );
+    let tokens = cmp::min(i64::from(row.tokens) + refilled, i64::from(limit.burst)) as i32;
+    // Tokens are added at fixed intervals from the last refill, so only move
+    // it forward by the time that was turned into tokens.
+    let last_refill = if tokens == limit.burst {
+        now
+    } else {
+        row.last_refill + rate * refilled as i32
+    };
+
+    if tokens < 1 {
+        let retry_after = (last_refill + rate).signed_duration_since(now);
+        return Err(Box::new(TooManyRequests {
+            retry_after: cmp::max(retry_after.num_seconds(), 1) as u64,
+        }));
+    }
+
+    diesel::update(
+        publish_limit_buckets::table
+            .filter(publish_limit_buckets::bucket.eq(bucket))
+            .filter(publish_limit_buckets::action.eq(action as i32)),
+    ).set((
+            publish_limit_buckets::tokens.eq(tokens - 1),
+            publish_limit_buckets::last_refill.eq(last_refill),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Returns the address of the client which made the request.
+///
+/// Behind the Heroku router the connection comes from the router itself, which
+/// appends the address it saw to `X-Forwarded-For`.
+pub fn client_ip(req: &Request) -> IpAddr {
+    req.headers()
+        .find("X-Forwarded-For")
+        .and_then(|values| values.last().cloned())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or_else(|| req.remote_addr().ip())
+}
+
+/// Handles the `PUT /admin/users/:user_id/publish_rate_override` route.
+///
+/// Takes a JSON body with `new_crate_burst` and `new_version_burst`. A `null`
+/// burst uses the default for that action, and setting both to `null` removes
+/// the override.
+pub fn update_override(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    req.user()?.require_admin()?;
+
+    let user_id = req.params()["user_id"]
+        .parse::<i32>()
+        .map_err(|_| human("invalid user id"))?;
+
+    #[derive(Deserialize)]
+    struct Update {
+        new_crate_burst: Option<i32>,
+        new_version_burst: Option<i32>,
+    }
+    let update: Update = serde_json::from_str(&body).map_err(|_| human("invalid json request"))?;
+
+    let conn = req.db_conn()?;
+    let user = users::table.find(user_id).first::<User>(&*conn)?;
+    let new_override = PublishRateOverride {
+        user_id: user.id,
+        new_crate_burst: update.new_crate_burst,
+        new_version_burst: update.new_version_burst,
+    };
+
+    if new_override.new_crate_burst.is_none() && new_override.new_version_burst.is_none() {
+        diesel::delete(&new_override).execute(&*conn)?;
+    } else {
+        diesel::insert_into(publish_rate_overrides::table)
+            .values(&new_override)
+            .on_conflict(publish_rate_overrides::user_id)
+            .do_update()
+            .set(&new_override)
+            .execute(&*conn)?;
+    }
+
+    #[derive(Serialize)]
+    struct R {
+        publish_rate_override: PublishRateOverride,
+    }
+    Ok(req.json(&R {
+        publish_rate_override: new_override,
+    }))
+}
@@ -0,0 +1,178 @@
+//! Crate name prefixes reserved for a team.
+//!
+//! Only members of the team a prefix is reserved for can publish new crates
+//! whose name starts with it. Existing crates are unaffected, and can still be
+//! published to by their owners.
+//!
+//! Prefixes are compared the same way as crate names: ignoring case and
+//! treating `-` and `_` as the same character.
+
+use std::io::Read;
+
+use chrono::NaiveDateTime;
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use serde_json;
+
+use db::RequestTransaction;
+use owner::Team;
+use schema::{reserved_prefixes, teams};
+use user::RequestUser;
+use util::{human, CargoResult, RequestUtils};
+use Crate;
+
+#[derive(Queryable, Debug, Clone)]
+pub struct ReservedPrefix {
+    pub prefix: String,
+    pub team_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "reserved_prefixes"]
+struct NewReservedPrefix<'a> {
+    prefix: &'a str,
+    team_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodableReservedPrefix {
+    pub prefix: String,
+    pub team: String,
+    #[serde(with = "::util::rfc3339")] pub created_at: NaiveDateTime,
+}
+
+impl ReservedPrefix {
+    /// Returns the reserved prefix `name` starts with, along with the team it
+    /// is reserved for.
+    pub fn matching(
+        conn: &PgConnection,
+        name: &str,
+    ) -> QueryResult<Option<(ReservedPrefix, Team)>> {
+        let name = canonicalize(name);
+        let prefixes = reserved_prefixes::table
+            .inner_join(teams::table)
+            .load::<(ReservedPrefix, Team)>(conn)?;
+        Ok(prefixes
+            .into_iter()
+            .find(|&(ref reserved, _)| name.starts_with(&canonicalize(&reserved.prefix))))
+    }
+
+    fn encodable(self, team: Team) -> EncodableReservedPrefix {
+        EncodableReservedPrefix {
+            prefix: self.prefix,
+            team: team.login,
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// Mirrors `canon_crate_name`.
+fn canonicalize(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// Handles the `GET /admin/reserved_prefixes` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    req.user()?.require_admin()?;
+
+    let conn = req.db_conn()?;
+    let prefixes = reserved_prefixes::table
+        .inner_join(teams::table)
+        .order(reserved_prefixes::prefix)
+        .load::<(ReservedPrefix, Team)>(&*conn)?
+        .into_iter()
+        .map(|(prefix, team)| prefix.encodable(team))
+        .collect();
+
+    #[derive(Serialize)]
+    struct R {
+        reserved_prefixes: Vec<EncodableReservedPrefix>,
+    }
+    Ok(req.json(&R {
+        reserved_prefixes: prefixes,
+    }))
+}
+
+/// Handles the `PUT /admin/reserved_prefixes` route.
+///
+/// Takes a JSON body with the `prefix` to reserve and the login of the `team`
+/// to reserve it for, e.g. `github:org:team`. The team must already be known,
+/// which happens when it is first added as an owner of a crate.
+pub fn add(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    req.user()?.require_admin()?;
+
+    #[derive(Deserialize)]
+    struct NewPrefix {
+        prefix: String,
+        team: String,
+    }
+    let new_prefix: NewPrefix =
+        serde_json::from_str(&body).map_err(|_| human("invalid json request"))?;
+    if !Crate::valid_name(&new_prefix.prefix) {
+        return Err(human(&format_args!(
+            "invalid prefix `{}`, prefixes must be the start of a valid crate name",
+            new_prefix.prefix
+        )));
+    }
+
+    let conn = req.db_conn()?;
+    let team = teams::table
+        .filter(teams::login.eq(&new_prefix.team))
+        .first::<Team>(&*conn)
+        .optional()?
+        .ok_or_else(|| {
+            human(&format_args!(
+                "could not find the team `{}`, it must be added as an owner of a crate first",
+                new_prefix.team
+            ))
+        })?;
+
+    let reserved = diesel::insert_into(reserved_prefixes::table)
+        .values(&NewReservedPrefix {
+            prefix: &new_prefix.prefix,
+            team_id: team.id,
+        })
+        .on_conflict_do_nothing()
+        .get_result::<ReservedPrefix>(&*conn)
+        .optional()?
+        .ok_or_else(|| {
+            human(&format_args!(
+                "the prefix `{}` is already reserved",
+                new_prefix.prefix
+            ))
+        })?;
+
+    #[derive(Serialize)]
+    struct R {
+        reserved_prefix: EncodableReservedPrefix,
+    }
+    Ok(req.json(&R {
+        reserved_prefix: reserved.encodable(team),
+    }))
+}
+
+/// Handles the `DELETE /admin/reserved_prefixes/:prefix` route.
+pub fn remove(req: &mut Request) -> CargoResult<Response> {
+    req.user()?.require_admin()?;
+
+    let prefix = req.params()["prefix"].clone();
+    let conn = req.db_conn()?;
+    let deleted = diesel::delete(reserved_prefixes::table.find(&prefix)).execute(&*conn)?;
+    if deleted == 0 {
+        return Err(human(&format_args!(
+            "the prefix `{}` is not reserved",
+            prefix
+        )));
+    }
+
+    #[derive(Serialize)]
+    struct R {
+        ok: bool,
+    }
+    Ok(req.json(&R { ok: true }))
+}
@@ -1,18 +1,29 @@
 #![deny(warnings)]
 
-extern crate base64;
 extern crate chrono;
 extern crate curl;
 extern crate openssl;
 
-use std::io::prelude::*;
+use std::error;
+use std::fmt;
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
 
 use chrono::prelude::Utc;
-use curl::easy::{Easy, List, ReadError, Transfer};
-use openssl::hash::MessageDigest;
-use openssl::sign::Signer;
-use openssl::pkey::PKey;
-use base64::encode;
+use curl::easy::{Easy, List, ReadError};
+
+mod signature;
+mod xml;
+
+/// The size of the parts of multipart uploads, unless changed with
+/// `Bucket::with_part_size`. Objects no larger than this are uploaded in a
+/// single request.
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// How many times a request answered with a server error is retried, unless
+/// changed with `Bucket::with_max_retries`.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
 
 #[derive(Clone, Debug)]
 pub struct Bucket {
@@ -21,6 +32,34 @@ pub struct Bucket {
     access_key: String,
     secret_key: String,
     proto: String,
+    endpoint: Option<String>,
+    part_size: usize,
+    max_retries: u32,
+}
+
+/// What `Bucket::head` learned about an object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    pub size: u64,
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Curl(curl::Error),
+    Io(io::Error),
+    /// S3 answered with an unexpected status, or an error in the body of a
+    /// successful response.
+    Status { status: u32, body: String },
+    /// S3 answered with something which couldn't be understood.
+    InvalidResponse(String),
+}
+
+struct Response {
+    status: u32,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
 }
 
 impl Bucket {
@@ -37,105 +76,469 @@ impl Bucket {
             access_key: access_key,
             secret_key: secret_key,
             proto: proto.to_string(),
+            endpoint: None,
+            part_size: DEFAULT_PART_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
         }
     }
 
-    pub fn put<'a, 'b>(
+    /// Sends requests to `endpoint` (e.g. `http://localhost:9000`) rather
+    /// than AWS, for S3-compatible stores such as MinIO or Ceph. The bucket is
+    /// then addressed in the path of requests rather than in their host.
+    ///
+    /// The protocol of the endpoint, if given, replaces the one the bucket
+    /// was created with.
+    pub fn with_endpoint(mut self, endpoint: String) -> Bucket {
+        let host = match endpoint.find("://") {
+            Some(i) => {
+                self.proto = endpoint[..i].to_string();
+                &endpoint[i + 3..]
+            }
+            None => &endpoint[..],
+        };
+        self.endpoint = Some(host.trim_right_matches('/').to_string());
+        self
+    }
+
+    /// Changes the size of the parts of multipart uploads. S3 requires them
+    /// to be at least 5MiB.
+    pub fn with_part_size(mut self, part_size: usize) -> Bucket {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Changes how many times a request answered with a server error is
+    /// retried.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Bucket {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Uploads `content` to `path`, in parts if it's larger than the part
+    /// size.
+    pub fn put(
         &self,
-        easy: &'a mut Easy,
+        easy: &mut Easy,
         path: &str,
-        mut content: &'b [u8],
+        mut content: &[u8],
         content_type: &str,
-        content_length: u64,
-    ) -> Transfer<'a, 'b> {
-        let path = if path.starts_with("/") {
-            &path[1..]
+    ) -> Result<(), Error> {
+        self.put_reader(easy, path, &mut content, content_type)
+    }
+
+    /// Uploads what `content` reads to `path`. At most two parts are kept in
+    /// memory at once, so this can upload objects which don't fit in memory.
+    pub fn put_reader(
+        &self,
+        easy: &mut Easy,
+        path: &str,
+        content: &mut Read,
+        content_type: &str,
+    ) -> Result<(), Error> {
+        let first = read_part(content, self.part_size)?;
+        let second = if first.len() < self.part_size {
+            Vec::new()
         } else {
-            path
+            read_part(content, self.part_size)?
         };
-        let host = self.host();
-        let date = Utc::now().to_rfc2822().to_string();
-        let auth = self.auth("PUT", &date, path, "", content_type);
-        let url = format!("{}://{}/{}", self.proto, host, path);
+        if second.is_empty() {
+            let response = self.send(easy, "PUT", path, &[], Some(content_type), &first)?;
+            return expect_success(response).map(|_| ());
+        }
 
-        let mut headers = List::new();
-        headers.append(&format!("Host: {}", host)).unwrap();
-        headers.append(&format!("Date: {}", date)).unwrap();
-        headers.append(&format!("Authorization: {}", auth)).unwrap();
-        headers
-            .append(&format!("Content-Type: {}", content_type))
-            .unwrap();
+        let upload_id = self.create_multipart_upload(easy, path, content_type)?;
+        let res = self.upload_parts(easy, path, &upload_id, first, second, content);
+        if res.is_err() {
+            // Otherwise the parts already uploaded are kept, and billed, until
+            // the bucket's lifecycle rules clean them up.
+            let _ = self.send(easy, "DELETE", path, &[("uploadId", &*upload_id)], None, &[]);
+        }
+        res
+    }
 
-        // Disable the `Expect: 100-continue` header for now, this cause
-        // problems with the test harness currently and the purpose is
-        // not yet clear. Would probably be good to reenable at some point.
-        headers.append("Expect:").unwrap();
+    /// Returns the contents of the object at `path`, or `None` if there is
+    /// none.
+    pub fn get(&self, easy: &mut Easy, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let response = self.send(easy, "GET", path, &[], None, &[])?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        expect_success(response).map(|response| Some(response.body))
+    }
+
+    /// Returns what is known about the object at `path` without downloading
+    /// it, or `None` if there is none.
+    pub fn head(&self, easy: &mut Easy, path: &str) -> Result<Option<Object>, Error> {
+        let response = self.send(easy, "HEAD", path, &[], None, &[])?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        let response = expect_success(response)?;
+        let size = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| Error::InvalidResponse("missing Content-Length".to_string()))?;
+        Ok(Some(Object {
+            size: size,
+            etag: response.header("ETag").map(str::to_string),
+            content_type: response.header("Content-Type").map(str::to_string),
+        }))
+    }
+
+    /// Deletes the object at `path`. Deleting an object which doesn't exist
+    /// isn't an error.
+    pub fn delete(&self, easy: &mut Easy, path: &str) -> Result<(), Error> {
+        let response = self.send(easy, "DELETE", path, &[], None, &[])?;
+        expect_success(response).map(|_| ())
+    }
+
+    /// Returns the paths of the objects starting with `prefix`, sorted.
+    pub fn list(&self, easy: &mut Easy, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut paths = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let body = {
+                let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+                if let Some(ref token) = token {
+                    query.push(("continuation-token", &**token));
+                }
+                let response = expect_success(self.send(easy, "GET", "", &query, None, &[])?)?;
+                String::from_utf8_lossy(&response.body).into_owned()
+            };
+            paths.extend(xml::elements(&body, "Key"));
+            token = match xml::element(&body, "IsTruncated") {
+                Some(ref truncated) if truncated == "true" => {
+                    Some(xml::element(&body, "NextContinuationToken").ok_or_else(|| {
+                        Error::InvalidResponse("missing NextContinuationToken".to_string())
+                    })?)
+                }
+                _ => None,
+            };
+            if token.is_none() {
+                break;
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
 
-        easy.url(&url).unwrap();
-        easy.put(true).unwrap();
-        easy.http_headers(headers).unwrap();
-        easy.upload(true).unwrap();
-        easy.in_filesize(content_length).unwrap();
+    pub fn host(&self) -> String {
+        if let Some(ref endpoint) = self.endpoint {
+            return endpoint.clone();
+        }
+        format!(
+            "{}.s3{}.amazonaws.com",
+            self.name,
+            match self.region {
+                Some(ref r) if r != "" => format!("-{}", r),
+                Some(_) => String::new(),
+                None => String::new(),
+            }
+        )
+    }
 
-        let mut transfer = easy.transfer();
-        transfer
-            .read_function(move |data| content.read(data).map_err(|_| ReadError::Abort))
-            .unwrap();
+    /// Returns the URL the object at `path` is publicly downloaded from.
+    pub fn url(&self, path: &str) -> String {
+        match self.endpoint {
+            Some(ref endpoint) => format!("{}://{}/{}/{}", self.proto, endpoint, self.name, path),
+            None => format!("https://{}/{}", self.host(), path),
+        }
+    }
 
-        return transfer;
+    fn create_multipart_upload(
+        &self,
+        easy: &mut Easy,
+        path: &str,
+        content_type: &str,
+    ) -> Result<String, Error> {
+        let response = self.send(easy, "POST", path, &[("uploads", "")], Some(content_type), &[])?;
+        let response = expect_success(response)?;
+        xml::element(&String::from_utf8_lossy(&response.body), "UploadId")
+            .ok_or_else(|| Error::InvalidResponse("missing UploadId".to_string()))
     }
 
-    pub fn delete<'a, 'b>(&self, easy: &'a mut Easy, path: &str) -> Transfer<'a, 'b> {
-        let path = if path.starts_with("/") {
+    /// Uploads `first`, `second` and the rest of `content` as the parts of
+    /// `upload_id`, then completes it.
+    fn upload_parts(
+        &self,
+        easy: &mut Easy,
+        path: &str,
+        upload_id: &str,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        content: &mut Read,
+    ) -> Result<(), Error> {
+        let mut etags = Vec::new();
+        let mut part = first;
+        let mut next = second;
+        loop {
+            let number = (etags.len() + 1).to_string();
+            let query = [("partNumber", &*number), ("uploadId", upload_id)];
+            let response = expect_success(self.send(easy, "PUT", path, &query, None, &part)?)?;
+            let etag = response
+                .header("ETag")
+                .ok_or_else(|| Error::InvalidResponse("missing ETag".to_string()))?;
+            etags.push(etag.to_string());
+
+            if next.is_empty() {
+                break;
+            }
+            part = next;
+            next = if part.len() < self.part_size {
+                Vec::new()
+            } else {
+                read_part(content, self.part_size)?
+            };
+        }
+
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                xml::escape(etag)
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let query = [("uploadId", upload_id)];
+        let response = self.send(easy, "POST", path, &query, None, body.as_bytes())?;
+        let response = expect_success(response)?;
+        // Completing an upload can fail after S3 started answering, in which
+        // case the error is in the body of a successful response.
+        if String::from_utf8_lossy(&response.body).contains("<Error>") {
+            return Err(Error::Status {
+                status: response.status,
+                body: String::from_utf8_lossy(&response.body).into_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends a request, retrying it with an exponential backoff as long as
+    /// it is answered with a server error.
+    fn send(
+        &self,
+        easy: &mut Easy,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let mut retries = 0;
+        loop {
+            let response = self.send_once(easy, method, path, query, content_type, body)?;
+            if response.status < 500 || retries >= self.max_retries {
+                return Ok(response);
+            }
+            thread::sleep(Duration::from_millis(100 << retries));
+            retries += 1;
+        }
+    }
+
+    fn send_once(
+        &self,
+        easy: &mut Easy,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let path = if path.starts_with('/') {
             &path[1..]
         } else {
             path
         };
+        let uri = match self.endpoint {
+            Some(_) => signature::canonical_uri(&format!("/{}/{}", self.name, path)),
+            None => signature::canonical_uri(&format!("/{}", path)),
+        };
+        let query = signature::canonical_query(query);
         let host = self.host();
-        let date = Utc::now().to_rfc2822().to_string();
-        let auth = self.auth("DELETE", &date, path, "", "");
-        let url = format!("{}://{}/{}", self.proto, host, path);
+        let time = Utc::now();
+        let date = signature::amz_date(&time);
+        let payload_hash = signature::sha256_hex(body);
+        let region = match self.region {
+            Some(ref r) if r != "" => &**r,
+            _ => "us-east-1",
+        };
+        let auth = signature::authorization(
+            &signature::Credentials {
+                access_key: &self.access_key,
+                secret_key: &self.secret_key,
+                region: region,
+            },
+            &signature::Request {
+                method: method,
+                uri: &uri,
+                query: &query,
+                headers: &[
+                    ("host", &*host),
+                    ("x-amz-content-sha256", &*payload_hash),
+                    ("x-amz-date", &*date),
+                ],
+                payload_hash: &payload_hash,
+            },
+            &time,
+        );
+
+        let mut url = format!("{}://{}{}", self.proto, host, uri);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
 
         let mut headers = List::new();
-        headers.append(&format!("Host: {}", host)).unwrap();
-        headers.append(&format!("Date: {}", date)).unwrap();
-        headers.append(&format!("Authorization: {}", auth)).unwrap();
+        headers.append(&format!("Host: {}", host))?;
+        headers.append(&format!("x-amz-content-sha256: {}", payload_hash))?;
+        headers.append(&format!("x-amz-date: {}", date))?;
+        headers.append(&format!("Authorization: {}", auth))?;
+        if let Some(content_type) = content_type {
+            headers.append(&format!("Content-Type: {}", content_type))?;
+        }
+
+        // Disable the `Expect: 100-continue` header for now, this cause
+        // problems with the test harness currently and the purpose is
+        // not yet clear. Would probably be good to reenable at some point.
+        headers.append("Expect:")?;
 
-        easy.custom_request("DELETE").unwrap();
-        easy.url(&url).unwrap();
-        easy.http_headers(headers).unwrap();
+        // The handle may have been used for another kind of request before,
+        // which `get` resets.
+        easy.get(true)?;
+        easy.custom_request(method)?;
+        match method {
+            "PUT" | "POST" => {
+                easy.upload(true)?;
+                easy.in_filesize(body.len() as u64)?;
+            }
+            "HEAD" => easy.nobody(true)?,
+            _ => {}
+        }
+        easy.url(&url)?;
+        easy.http_headers(headers)?;
+
+        let mut response_headers = Vec::new();
+        let mut response_body = Vec::new();
+        {
+            let mut body = body;
+            let mut transfer = easy.transfer();
+            transfer.read_function(move |data| body.read(data).map_err(|_| ReadError::Abort))?;
+            transfer.header_function(|line| {
+                let line = String::from_utf8_lossy(line);
+                if line.starts_with("HTTP/") {
+                    // The headers of a previous response, e.g. of a redirect.
+                    response_headers.clear();
+                } else if let Some(i) = line.find(':') {
+                    response_headers.push((
+                        line[..i].trim().to_string(),
+                        line[i + 1..].trim().to_string(),
+                    ));
+                }
+                true
+            })?;
+            transfer.write_function(|data| {
+                response_body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
 
-        return easy.transfer();
+        Ok(Response {
+            status: easy.response_code()?,
+            headers: response_headers,
+            body: response_body,
+        })
     }
+}
 
-    pub fn host(&self) -> String {
-        format!(
-            "{}.s3{}.amazonaws.com",
-            self.name,
-            match self.region {
-                Some(ref r) if r != "" => format!("-{}", r),
-                Some(_) => String::new(),
-                None => String::new(),
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref n, _)| n.to_lowercase() == name.to_lowercase())
+            .map(|&(_, ref value)| &**value)
+    }
+}
+
+fn expect_success(response: Response) -> Result<Response, Error> {
+    if response.status >= 200 && response.status < 300 {
+        Ok(response)
+    } else {
+        Err(Error::Status {
+            status: response.status,
+            body: String::from_utf8_lossy(&response.body).into_owned(),
+        })
+    }
+}
+
+/// Reads up to `size` bytes, less only if `content` ends first.
+fn read_part(content: &mut Read, size: usize) -> Result<Vec<u8>, Error> {
+    let mut part = Vec::new();
+    Read::take(&mut *content, size as u64).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Curl(ref e) => e.fmt(f),
+            Error::Io(ref e) => e.fmt(f),
+            Error::Status { status, ref body } => {
+                write!(f, "S3 responded with status {}: {}", status, body)
             }
-        )
+            Error::InvalidResponse(ref what) => write!(f, "invalid response from S3: {}", what),
+        }
     }
+}
 
-    fn auth(&self, verb: &str, date: &str, path: &str, md5: &str, content_type: &str) -> String {
-        let string = format!(
-            "{verb}\n{md5}\n{ty}\n{date}\n{headers}{resource}",
-            verb = verb,
-            md5 = md5,
-            ty = content_type,
-            date = date,
-            headers = "",
-            resource = format!("/{}/{}", self.name, path)
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Curl(ref e) => error::Error::description(e),
+            Error::Io(ref e) => error::Error::description(e),
+            Error::Status { .. } => "S3 responded with an error",
+            Error::InvalidResponse(..) => "invalid response from S3",
+        }
+    }
+}
+
+impl From<curl::Error> for Error {
+    fn from(e: curl::Error) -> Error {
+        Error::Curl(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bucket;
+
+    #[test]
+    fn endpoints_address_the_bucket_in_the_path() {
+        let bucket = Bucket::new(
+            "crates".to_string(),
+            None,
+            String::new(),
+            String::new(),
+            "https",
+        );
+        assert_eq!(bucket.host(), "crates.s3.amazonaws.com");
+        assert_eq!(
+            bucket.url("crates/foo/foo-1.0.0.crate"),
+            "https://crates.s3.amazonaws.com/crates/foo/foo-1.0.0.crate"
+        );
+
+        let bucket = bucket.with_endpoint("http://localhost:9000/".to_string());
+        assert_eq!(bucket.host(), "localhost:9000");
+        assert_eq!(
+            bucket.url("crates/foo/foo-1.0.0.crate"),
+            "http://localhost:9000/crates/crates/foo/foo-1.0.0.crate"
         );
-        let signature = {
-            let key = PKey::hmac(self.secret_key.as_bytes()).unwrap();
-            let mut signer = Signer::new(MessageDigest::sha1(), &key).unwrap();
-            signer.update(string.as_bytes()).unwrap();
-            encode(&signer.finish().unwrap()[..])
-        };
-        format!("AWS {}:{}", self.access_key, signature)
     }
 }
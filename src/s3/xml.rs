@@ -0,0 +1,104 @@
+//! Just enough XML for the responses of S3, which are flat lists of
+//! elements holding text. This isn't a general XML parser.
+
+/// Returns the text of every `<tag>` element in `xml`, in order.
+pub fn elements(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut texts = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = match rest.find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        texts.push(unescape(&rest[..end]));
+        rest = &rest[end + close.len()..];
+    }
+    texts
+}
+
+/// Returns the text of the first `<tag>` element in `xml`.
+pub fn element(xml: &str, tag: &str) -> Option<String> {
+    elements(xml, tag).into_iter().next()
+}
+
+/// Escapes `text` to be put in an element.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..]
+                .parse()
+                .ok()
+                .and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_list_responses() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                   <ListBucketResult>\
+                   <Contents><Key>crates/a&amp;b/1.crate</Key><Size>3</Size></Contents>\
+                   <Contents><Key>crates/&#x63;/2.crate</Key><Size>3</Size></Contents>\
+                   <IsTruncated>false</IsTruncated>\
+                   </ListBucketResult>";
+        assert_eq!(
+            elements(xml, "Key"),
+            ["crates/a&b/1.crate", "crates/c/2.crate"]
+        );
+        assert_eq!(element(xml, "IsTruncated"), Some("false".to_string()));
+        assert_eq!(element(xml, "NextContinuationToken"), None);
+        assert_eq!(unescape(&escape("\"a\" & <b>")), "\"a\" & <b>");
+    }
+}
@@ -42,6 +42,63 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `background_jobs` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    background_jobs (id) {
+        /// The `id` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `job_type` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        job_type -> Varchar,
+        /// The `data` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        data -> Jsonb,
+        /// The `retries` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        retries -> Int4,
+        /// The `last_retry` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_retry -> Timestamp,
+        /// The `last_error` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_error -> Nullable<Varchar>,
+        /// The `dead` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        dead -> Bool,
+        /// The `created_at` column of the `background_jobs` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamp,
+    }
+}
+
+
 table! {
     /// Representation of the `badges` table.
     ///
@@ -355,6 +412,44 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `deleted_crates` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    deleted_crates (id) {
+        /// The `id` column of the `deleted_crates` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `name` column of the `deleted_crates` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        name -> Varchar,
+        /// The `deleted_by` column of the `deleted_crates` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        deleted_by -> Nullable<Int4>,
+        /// The `deleted_at` column of the `deleted_crates` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        deleted_at -> Timestamp,
+        /// The `available_at` column of the `deleted_crates` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        available_at -> Timestamp,
+    }
+}
+
 table! {
     /// Representation of the `dependencies` table.
     ///
@@ -374,10 +469,10 @@ table! {
         version_id -> Int4,
         /// The `crate_id` column of the `dependencies` table.
         ///
-        /// Its SQL type is `Int4`.
+        /// Its SQL type is `Nullable<Int4>`.
         ///
         /// (Automatically generated by Diesel.)
-        crate_id -> Int4,
+        crate_id -> Nullable<Int4>,
         /// The `req` column of the `dependencies` table.
         ///
         /// Its SQL type is `Varchar`.
@@ -414,6 +509,24 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         kind -> Int4,
+        /// The `explicit_name` column of the `dependencies` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        explicit_name -> Nullable<Varchar>,
+        /// The `registry` column of the `dependencies` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        registry -> Nullable<Varchar>,
+        /// The `registry_crate_name` column of the `dependencies` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        registry_crate_name -> Nullable<Varchar>,
     }
 }
 
@@ -527,6 +640,117 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `publish_jobs` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    publish_jobs (id) {
+        /// The `id` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `version_id` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version_id -> Int4,
+        /// The `status` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        status -> Int4,
+        /// The `index_entry` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        index_entry -> Jsonb,
+        /// The `error` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        error -> Nullable<Varchar>,
+        /// The `created_at` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamp,
+        /// The `updated_at` column of the `publish_jobs` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        updated_at -> Timestamp,
+    }
+}
+
+
+table! {
+    /// Representation of the `publish_limit_buckets` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    publish_limit_buckets (bucket, action) {
+        /// The `bucket` column of the `publish_limit_buckets` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        bucket -> Varchar,
+        /// The `action` column of the `publish_limit_buckets` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        action -> Int4,
+        /// The `tokens` column of the `publish_limit_buckets` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        tokens -> Int4,
+        /// The `last_refill` column of the `publish_limit_buckets` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_refill -> Timestamp,
+    }
+}
+
+
+table! {
+    /// Representation of the `publish_rate_overrides` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    publish_rate_overrides (user_id) {
+        /// The `user_id` column of the `publish_rate_overrides` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `new_crate_burst` column of the `publish_rate_overrides` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        new_crate_burst -> Nullable<Int4>,
+        /// The `new_version_burst` column of the `publish_rate_overrides` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        new_version_burst -> Nullable<Int4>,
+    }
+}
+
+
 table! {
     /// Representation of the `reserved_crate_names` table.
     ///
@@ -541,6 +765,32 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `reserved_prefixes` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    reserved_prefixes (prefix) {
+        /// The `prefix` column of the `reserved_prefixes` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        prefix -> Varchar,
+        /// The `team_id` column of the `reserved_prefixes` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        team_id -> Int4,
+        /// The `created_at` column of the `reserved_prefixes` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     /// Representation of the `teams` table.
     ///
@@ -626,6 +876,12 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         gh_id -> Int4,
+        /// The `admin` column of the `users` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        admin -> Bool,
     }
 }
 
@@ -705,6 +961,88 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `version_files` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    version_files (id) {
+        /// The `id` column of the `version_files` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `version_id` column of the `version_files` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version_id -> Int4,
+        /// The `path` column of the `version_files` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        path -> Varchar,
+        /// The `size` column of the `version_files` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        size -> Int8,
+        /// The `mode` column of the `version_files` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        mode -> Nullable<Int4>,
+    }
+}
+
+table! {
+    /// Representation of the `version_yank_events` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    version_yank_events (id) {
+        /// The `id` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `version_id` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version_id -> Int4,
+        /// The `user_id` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `yanked` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        yanked -> Bool,
+        /// The `reason` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        reason -> Nullable<Varchar>,
+        /// The `created_at` column of the `version_yank_events` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     /// Representation of the `versions` table.
     ///
@@ -770,6 +1108,30 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         readme_rendered_at -> Nullable<Timestamp>,
+        /// The `crate_size` column of the `versions` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        crate_size -> Nullable<Int4>,
+        /// The `unpacked_size` column of the `versions` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        unpacked_size -> Nullable<Int4>,
+        /// The `yank_reason` column of the `versions` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        yank_reason -> Nullable<Varchar>,
+        /// The `checksum` column of the `versions` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        checksum -> Nullable<Varchar>,
     }
 }
 
@@ -783,18 +1145,26 @@ joinable!(crates_categories -> categories (category_id));
 joinable!(crates_categories -> crates (crate_id));
 joinable!(crates_keywords -> crates (crate_id));
 joinable!(crates_keywords -> keywords (keyword_id));
+joinable!(deleted_crates -> users (deleted_by));
 joinable!(dependencies -> crates (crate_id));
 joinable!(dependencies -> versions (version_id));
 joinable!(emails -> users (user_id));
 joinable!(follows -> crates (crate_id));
 joinable!(follows -> users (user_id));
+joinable!(publish_jobs -> versions (version_id));
+joinable!(publish_rate_overrides -> users (user_id));
+joinable!(reserved_prefixes -> teams (team_id));
 joinable!(version_authors -> users (user_id));
 joinable!(version_authors -> versions (version_id));
 joinable!(version_downloads -> versions (version_id));
+joinable!(version_files -> versions (version_id));
+joinable!(version_yank_events -> users (user_id));
+joinable!(version_yank_events -> versions (version_id));
 joinable!(versions -> crates (crate_id));
 
 allow_tables_to_appear_in_same_query!(
     api_tokens,
+    background_jobs,
     badges,
     categories,
     crate_downloads,
@@ -803,15 +1173,22 @@ allow_tables_to_appear_in_same_query!(
     crates,
     crates_categories,
     crates_keywords,
+    deleted_crates,
     dependencies,
     emails,
     follows,
     keywords,
     metadata,
+    publish_jobs,
+    publish_limit_buckets,
+    publish_rate_overrides,
     reserved_crate_names,
+    reserved_prefixes,
     teams,
     users,
     version_authors,
     version_downloads,
+    version_files,
+    version_yank_events,
     versions,
 );
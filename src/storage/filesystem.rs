@@ -0,0 +1,180 @@
+//! Stores files in a directory of the local filesystem.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use rand::{thread_rng, Rng};
+
+use util::CargoResult;
+
+use super::Storage;
+
+/// Stores files under a directory, see `StorageConfig::Filesystem`.
+///
+/// The files are served by `local_upload::Middleware`, so their URLs are
+/// relative to this server.
+#[derive(Clone, Debug)]
+pub struct FilesystemStorage {
+    root: PathBuf,
+}
+
+impl FilesystemStorage {
+    /// Stores files under `root`. It doesn't need to exist yet, it is created
+    /// along with the directories of the first file stored.
+    pub fn new(root: &Path) -> FilesystemStorage {
+        FilesystemStorage {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for FilesystemStorage {
+    fn put(&self, path: &str, body: &[u8], _content_type: &str) -> CargoResult<()> {
+        write_atomically(&self.root.join(path), body)
+    }
+
+    fn get(&self, path: &str) -> CargoResult<Option<Vec<u8>>> {
+        let mut file = match File::open(self.root.join(path)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut body = Vec::new();
+        file.read_to_end(&mut body)?;
+        Ok(Some(body))
+    }
+
+    fn reader(&self, path: &str) -> CargoResult<Option<Box<Read + Send>>> {
+        match File::open(self.root.join(path)) {
+            Ok(file) => Ok(Some(Box::new(file) as Box<Read + Send>)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn exists(&self, path: &str) -> CargoResult<bool> {
+        Ok(self.root.join(path).is_file())
+    }
+
+    fn delete(&self, path: &str) -> CargoResult<()> {
+        match fs::remove_file(self.root.join(path)) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> CargoResult<Vec<String>> {
+        // Only walk the directory the prefix ends in.
+        let dir = match prefix.rfind('/') {
+            Some(i) => &prefix[..i + 1],
+            None => "",
+        };
+        let mut paths = Vec::new();
+        list_dir(&self.root, dir, &mut paths)?;
+        paths.retain(|path| path.starts_with(prefix));
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn url(&self, path: &str) -> Option<String> {
+        Some(format!("/{}", path))
+    }
+}
+
+/// Adds the paths of the files under `dir`, relative to `root`, to `paths`.
+fn list_dir(root: &Path, dir: &str, paths: &mut Vec<String>) -> io::Result<()> {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(..) => continue,
+        };
+        // Skip the temporary files of `write_atomically`.
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", dir, name);
+        if entry.file_type()?.is_dir() {
+            list_dir(root, &format!("{}/", path), paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Writes `body` to `filename` so that it is never seen partially written, even if the server
+/// or the machine stops halfway through: it is written to a temporary file in the same
+/// directory first, flushed to disk, then renamed.
+fn write_atomically(filename: &Path, body: &[u8]) -> CargoResult<()> {
+    let dir = filename.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let suffix: String = thread_rng().gen_ascii_chars().take(8).collect();
+    let tmp = dir.join(format!(
+        ".{}.{}.tmp",
+        filename.file_name().unwrap().to_string_lossy(),
+        suffix
+    ));
+    let res = File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(body)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, filename));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    // Make the rename itself durable. Directories can't be opened like this on Windows.
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use rand::{thread_rng, Rng};
+
+    use storage::Storage;
+    use super::FilesystemStorage;
+
+    #[test]
+    fn files_are_listed_by_prefix() {
+        let suffix: String = thread_rng().gen_ascii_chars().take(8).collect();
+        let root = env::temp_dir().join(format!("filesystem-storage-{}", suffix));
+        let storage = FilesystemStorage::new(&root);
+        storage.put("crates/foo/foo-1.0.0.crate", b"foo", "application/x-tar").unwrap();
+        storage.put("crates/foo/foo-1.1.0.crate", b"foo", "application/x-tar").unwrap();
+        storage.put("crates/foobar/foobar-1.0.0.crate", b"bar", "application/x-tar").unwrap();
+        storage.put("readmes/foo/foo-1.0.0.html", b"<p>foo</p>", "text/html").unwrap();
+
+        assert_eq!(
+            storage.list("crates/foo/").unwrap(),
+            vec!["crates/foo/foo-1.0.0.crate", "crates/foo/foo-1.1.0.crate"]
+        );
+        assert_eq!(storage.list("crates/foo").unwrap().len(), 3);
+        assert_eq!(storage.list("").unwrap().len(), 4);
+        assert!(storage.list("missing/").unwrap().is_empty());
+
+        storage.delete("crates/foo/foo-1.0.0.crate").unwrap();
+        storage.delete("crates/foo/foo-1.0.0.crate").unwrap();
+        assert!(!storage.exists("crates/foo/foo-1.0.0.crate").unwrap());
+        assert_eq!(
+            storage.get("readmes/foo/foo-1.0.0.html").unwrap(),
+            Some(b"<p>foo</p>".to_vec())
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Stores files in memory.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use util::CargoResult;
+
+use super::Storage;
+
+/// Stores files in memory, see `StorageConfig::Memory`.
+///
+/// Clones share the same files, so that a test can keep one to inspect what
+/// the server stored.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<String, StoredFile>>>,
+}
+
+/// A file stored in a `MemoryStorage`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredFile {
+    pub body: Vec<u8>,
+    pub content_type: String,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// Returns the file stored at `path`, with its content type.
+    pub fn file(&self, path: &str) -> Option<StoredFile> {
+        self.files.lock().unwrap().get(path).cloned()
+    }
+
+    /// Returns the paths of all the stored files, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put(&self, path: &str, body: &[u8], content_type: &str) -> CargoResult<()> {
+        let file = StoredFile {
+            body: body.to_vec(),
+            content_type: content_type.to_string(),
+        };
+        self.files.lock().unwrap().insert(path.to_string(), file);
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> CargoResult<Option<Vec<u8>>> {
+        Ok(self.file(path).map(|file| file.body))
+    }
+
+    fn exists(&self, path: &str) -> CargoResult<bool> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+
+    fn delete(&self, path: &str) -> CargoResult<()> {
+        self.files.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> CargoResult<Vec<String>> {
+        Ok(self.paths()
+            .into_iter()
+            .filter(|path| path.starts_with(prefix))
+            .collect())
+    }
+
+    /// Files aren't served from memory, but the URLs are those the files
+    /// would have in a `FilesystemStorage`.
+    fn url(&self, path: &str) -> Option<String> {
+        Some(format!("/{}", path))
+    }
+}
@@ -0,0 +1,290 @@
+//! Storage for the files uploaded with crates: the `.crate` archives and
+//! their rendered readmes.
+//!
+//! Where those files live depends on the `Storage` picked through
+//! `Config::storage`:
+//!
+//! - `S3Storage`: an S3 bucket, optionally behind a CDN. This is what
+//!   crates.io runs.
+//! - `FilesystemStorage`: a directory of the local filesystem, served by the
+//!   server itself. Used in development and by registries which can't use S3.
+//! - `MemoryStorage`: kept in memory only, for tests.
+//! - `NoOpStorage`: stores nothing, for one-off scripts.
+//!
+//! Files are addressed by paths relative to the root of the storage, see
+//! `crate_path` and `readme_path`. Backends only need to store, fetch and
+//! delete files by path and tell where they are downloaded from, everything
+//! else is built on top of that.
+
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use conduit::Request;
+
+use app::{App, RequestApp};
+use krate::Crate;
+use tarball::{self, TarballFile};
+use upload;
+use util::{hash, internal, read_le_u32, CargoResult, LimitErrorReader};
+
+pub mod filesystem;
+pub mod memory;
+pub mod s3;
+
+pub use self::filesystem::FilesystemStorage;
+pub use self::memory::{MemoryStorage, StoredFile};
+pub use self::s3::S3Storage;
+
+/// Selects the `Storage` used by the server.
+#[derive(Clone, Debug)]
+pub enum StorageConfig {
+    /// Uploads to an S3 bucket, from which crates are downloaded. The test
+    /// of this backend goes through a proxy to record and replay the
+    /// requests made to S3.
+    S3 {
+        bucket: ::s3::Bucket,
+        cdn: Option<String>,
+        proxy: Option<String>,
+    },
+
+    /// Stores files in a directory of the local filesystem, and serves them
+    /// from there (see `local_upload::Middleware`).
+    Filesystem { root: PathBuf },
+
+    /// Stores files in memory. The storage is shared with the clones of the
+    /// `MemoryStorage`, so that tests can inspect what was uploaded.
+    Memory(MemoryStorage),
+
+    /// For one-off scripts where creating a Config is needed, but uploading
+    /// is not.
+    NoOp,
+}
+
+impl StorageConfig {
+    /// Opens the configured backend.
+    pub fn open(&self) -> Box<Storage> {
+        match *self {
+            StorageConfig::S3 {
+                ref bucket,
+                ref cdn,
+                ref proxy,
+            } => Box::new(S3Storage::new(bucket.clone(), cdn.clone(), proxy.clone())),
+            StorageConfig::Filesystem { ref root } => Box::new(FilesystemStorage::new(root)),
+            StorageConfig::Memory(ref storage) => Box::new(storage.clone()),
+            StorageConfig::NoOp => Box::new(NoOpStorage),
+        }
+    }
+
+    /// Returns the directory files are stored in, if they are stored on the
+    /// local filesystem.
+    pub fn local_root(&self) -> Option<&Path> {
+        match *self {
+            StorageConfig::Filesystem { ref root } => Some(root),
+            _ => None,
+        }
+    }
+
+    /// Returns the host files are downloaded from, if it isn't this server.
+    pub fn download_host(&self) -> Option<String> {
+        match *self {
+            StorageConfig::S3 {
+                ref bucket,
+                ref cdn,
+                ..
+            } => Some(cdn.clone().unwrap_or_else(|| bucket.host())),
+            _ => None,
+        }
+    }
+}
+
+/// Where the files uploaded with crates are stored.
+///
+/// Paths are relative to the root of the storage and use `/` as separator,
+/// e.g. `crates/foo/foo-1.0.0.crate`.
+pub trait Storage: Send + Sync {
+    /// Stores `body` at `path`, replacing any file already there.
+    fn put(&self, path: &str, body: &[u8], content_type: &str) -> CargoResult<()>;
+
+    /// Returns the contents of the file at `path`, or `None` if there is
+    /// none.
+    fn get(&self, path: &str) -> CargoResult<Option<Vec<u8>>>;
+
+    /// Deletes the file at `path`. Deleting a file which doesn't exist isn't
+    /// an error.
+    fn delete(&self, path: &str) -> CargoResult<()>;
+
+    /// Returns the paths of the stored files starting with `prefix`, sorted.
+    fn list(&self, prefix: &str) -> CargoResult<Vec<String>>;
+
+    /// Returns the URL the file at `path` is downloaded from, or `None` if
+    /// files aren't downloaded from this storage.
+    ///
+    /// The function doesn't check for the existence of the file.
+    fn url(&self, path: &str) -> Option<String>;
+
+    /// Returns a reader of the file at `path`, or `None` if there is none.
+    ///
+    /// Backends which can read a file without loading all of it should
+    /// override this, the default reads it through `get`.
+    fn reader(&self, path: &str) -> CargoResult<Option<Box<Read + Send>>> {
+        Ok(self.get(path)?
+            .map(|body| Box::new(Cursor::new(body)) as Box<Read + Send>))
+    }
+
+    /// Whether there is a file at `path`.
+    fn exists(&self, path: &str) -> CargoResult<bool> {
+        Ok(self.get(path)?.is_some())
+    }
+
+    /// Returns the URL of an uploaded crate's version archive.
+    fn crate_location(&self, crate_name: &str, version: &str) -> Option<String> {
+        self.url(&crate_path(crate_name, version))
+    }
+
+    /// Returns the URL of an uploaded crate's version readme.
+    fn readme_location(&self, crate_name: &str, version: &str) -> Option<String> {
+        self.url(&readme_path(crate_name, version))
+    }
+
+    /// Fetches the archive of an uploaded crate version.
+    fn download_crate(&self, crate_name: &str, version: &str) -> CargoResult<Vec<u8>> {
+        let path = crate_path(crate_name, version);
+        self.get(&path)?
+            .ok_or_else(|| internal(&format_args!("crate file `{}` is not stored", path)))
+    }
+
+    /// Opens the archive of an uploaded crate version for reading, without
+    /// loading it in memory if the storage allows it.
+    fn read_crate(&self, crate_name: &str, version: &str) -> CargoResult<Box<Read + Send>> {
+        let path = crate_path(crate_name, version);
+        self.reader(&path)?
+            .ok_or_else(|| internal(&format_args!("crate file `{}` is not stored", path)))
+    }
+
+    /// Deletes the archive and readme of an uploaded crate version.
+    ///
+    /// Deleting files which were never uploaded, such as the readme of a
+    /// version without one, isn't an error.
+    fn delete_version(&self, crate_name: &str, version: &str) -> CargoResult<()> {
+        self.delete(&crate_path(crate_name, version))?;
+        self.delete(&readme_path(crate_name, version))
+    }
+}
+
+/// Returns the path of an uploaded crate's version archive.
+pub fn crate_path(name: &str, version: &str) -> String {
+    format!("crates/{}/{}-{}.crate", name, name, version)
+}
+
+/// Returns the path of an uploaded crate's version readme.
+pub fn readme_path(name: &str, version: &str) -> String {
+    format!("readmes/{}/{}-{}.html", name, name, version)
+}
+
+/// Uploads a crate and its readme to `App::storage`. Returns what was
+/// learned about the uploaded crate file, and bombs for the uploaded crate
+/// and the uploaded readme.
+pub fn upload_crate(
+    req: &mut Request,
+    krate: &Crate,
+    new_crate: &upload::NewCrate,
+    readme: Option<String>,
+    max: u64,
+    max_unpack: u64,
+) -> CargoResult<(UploadedCrate, Bomb, Bomb)> {
+    let app = Arc::clone(req.app());
+    let vers = new_crate.vers.to_string();
+
+    read_le_u32(req.body())?;
+    let mut body = Vec::new();
+    LimitErrorReader::new(req.body(), max).read_to_end(&mut body)?;
+    let files = tarball::verify(new_crate, &body, max_unpack)?;
+    let path = crate_path(&krate.name, &vers);
+    app.storage.put(&path, &body, "application/x-tar")?;
+    let uploaded = UploadedCrate {
+        checksum: hash(&body),
+        crate_size: body.len() as i32,
+        unpacked_size: files.iter().map(|f| f.size).sum::<i64>() as i32,
+        files: files,
+    };
+
+    // We create the bomb for the crate file before uploading the readme so that if the
+    // readme upload fails, the uploaded crate file is automatically deleted.
+    let crate_bomb = Bomb {
+        app: Arc::clone(&app),
+        path: Some(path),
+    };
+    let readme_path = match readme {
+        Some(rendered) => {
+            let path = readme_path(&krate.name, &vers);
+            app.storage.put(&path, rendered.as_bytes(), "text/html")?;
+            Some(path)
+        }
+        None => None,
+    };
+    Ok((
+        uploaded,
+        crate_bomb,
+        Bomb {
+            app: app,
+            path: readme_path,
+        },
+    ))
+}
+
+/// What `upload_crate` learned about an uploaded crate file.
+#[derive(Debug)]
+pub struct UploadedCrate {
+    pub checksum: Vec<u8>,
+    /// The size of the `.crate` file itself.
+    pub crate_size: i32,
+    /// The total size of the files in the `.crate` file.
+    pub unpacked_size: i32,
+    pub files: Vec<TarballFile>,
+}
+
+/// Deletes an uploaded file from `App::storage` when dropped, unless its
+/// `path` was taken out.
+// Can't derive Debug because of App.
+#[allow(missing_debug_implementations)]
+pub struct Bomb {
+    app: Arc<App>,
+    pub path: Option<String>,
+}
+
+impl Drop for Bomb {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = self.app.storage.delete(path) {
+                println!("unable to delete {}, {:?}", path, e);
+            }
+        }
+    }
+}
+
+/// Stores nothing, see `StorageConfig::NoOp`.
+#[derive(Clone, Copy, Debug)]
+pub struct NoOpStorage;
+
+impl Storage for NoOpStorage {
+    fn put(&self, _path: &str, _body: &[u8], _content_type: &str) -> CargoResult<()> {
+        Ok(())
+    }
+
+    fn get(&self, _path: &str) -> CargoResult<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn delete(&self, _path: &str) -> CargoResult<()> {
+        Ok(())
+    }
+
+    fn list(&self, _prefix: &str) -> CargoResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn url(&self, _path: &str) -> Option<String> {
+        None
+    }
+}
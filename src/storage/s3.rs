@@ -0,0 +1,76 @@
+//! Stores files in an S3 bucket.
+
+use curl::easy::Easy;
+use s3;
+
+use util::{internal, CargoResult};
+
+use super::Storage;
+
+/// Stores files in an S3 bucket, see `StorageConfig::S3`.
+#[derive(Clone, Debug)]
+pub struct S3Storage {
+    bucket: s3::Bucket,
+    cdn: Option<String>,
+    proxy: Option<String>,
+}
+
+impl S3Storage {
+    /// Stores files in `bucket`. They are downloaded from `cdn` if set, and
+    /// from the bucket otherwise.
+    pub fn new(bucket: s3::Bucket, cdn: Option<String>, proxy: Option<String>) -> S3Storage {
+        S3Storage {
+            bucket: bucket,
+            cdn: cdn,
+            proxy: proxy,
+        }
+    }
+
+    fn handle(&self) -> Easy {
+        let mut handle = Easy::new();
+        if let Some(ref proxy) = self.proxy {
+            handle.proxy(proxy).unwrap();
+        }
+        handle
+    }
+}
+
+impl Storage for S3Storage {
+    fn put(&self, path: &str, body: &[u8], content_type: &str) -> CargoResult<()> {
+        self.bucket
+            .put(&mut self.handle(), path, body, content_type)
+            .map_err(|e| internal(&format_args!("failed to upload `{}` to S3: {}", path, e)))
+    }
+
+    fn get(&self, path: &str) -> CargoResult<Option<Vec<u8>>> {
+        self.bucket
+            .get(&mut self.handle(), path)
+            .map_err(|e| internal(&format_args!("failed to download `{}` from S3: {}", path, e)))
+    }
+
+    fn exists(&self, path: &str) -> CargoResult<bool> {
+        self.bucket
+            .head(&mut self.handle(), path)
+            .map(|object| object.is_some())
+            .map_err(|e| internal(&format_args!("failed to look up `{}` in S3: {}", path, e)))
+    }
+
+    fn delete(&self, path: &str) -> CargoResult<()> {
+        self.bucket
+            .delete(&mut self.handle(), path)
+            .map_err(|e| internal(&format_args!("failed to delete `{}` from S3: {}", path, e)))
+    }
+
+    fn list(&self, prefix: &str) -> CargoResult<Vec<String>> {
+        self.bucket
+            .list(&mut self.handle(), prefix)
+            .map_err(|e| internal(&format_args!("failed to list `{}` in S3: {}", prefix, e)))
+    }
+
+    fn url(&self, path: &str) -> Option<String> {
+        Some(match self.cdn {
+            Some(ref cdn) => format!("https://{}/{}", cdn, path),
+            None => self.bucket.url(path),
+        })
+    }
+}
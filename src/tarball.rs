@@ -0,0 +1,111 @@
+//! Checks on the crate files uploaded by `cargo publish`.
+
+use std::io::Read;
+use std::path::Path;
+
+use conduit::Request;
+use flate2::read::GzDecoder;
+use tar;
+
+use manifest;
+use upload;
+use util::{human, CargoResult, ChainError};
+use util::{read_le_u32, LimitErrorReader};
+
+/// Reads the crate file out of the body of a publish request and checks it
+/// the same way `storage::upload_crate` does, without uploading anything.
+pub fn verify_crate(
+    req: &mut Request,
+    new_crate: &upload::NewCrate,
+    max: u64,
+    max_unpack: u64,
+) -> CargoResult<()> {
+    read_le_u32(req.body())?;
+    let mut body = Vec::new();
+    LimitErrorReader::new(req.body(), max).read_to_end(&mut body)?;
+    verify(new_crate, &body, max_unpack).map(|_| ())
+}
+
+/// Returns the total size of the regular files in a crate file, as recorded
+/// in `versions.unpacked_size`.
+pub fn unpacked_size(tarball: &[u8]) -> CargoResult<i64> {
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball)?);
+    let mut size = 0;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            size += entry.header().size()? as i64;
+        }
+    }
+    Ok(size)
+}
+
+/// A regular file found in an uploaded crate, with its path relative to the
+/// `$name-$vers/` directory.
+#[derive(Debug)]
+pub struct TarballFile {
+    pub path: String,
+    pub size: i64,
+    pub mode: Option<i32>,
+}
+
+/// Checks that every entry of the tarball is inside the `$name-$vers/`
+/// directory, and that its `Cargo.toml` matches the metadata sent by cargo.
+/// Returns the regular files the tarball contains.
+pub fn verify(
+    new_crate: &upload::NewCrate,
+    tarball: &[u8],
+    max_unpack: u64,
+) -> CargoResult<Vec<TarballFile>> {
+    // All our data is currently encoded with gzip
+    let decoder = GzDecoder::new(tarball)?;
+
+    // Don't let gzip decompression go into the weeeds, apply a fixed cap after
+    // which point we say the decompressed source is "too large".
+    let decoder = LimitErrorReader::new(decoder, max_unpack);
+
+    // Use this I/O object now to take a peek inside
+    let mut archive = tar::Archive::new(decoder);
+    let prefix = format!("{}-{}", &*new_crate.name, &*new_crate.vers);
+    let manifest_path = Path::new(&prefix).join("Cargo.toml");
+    let mut manifest = None;
+    let mut files = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry.chain_error(|| {
+            human("uploaded tarball is malformed or too large when decompressed")
+        })?;
+
+        // Verify that all entries actually start with `$name-$vers/`.
+        // Historically Cargo didn't verify this on extraction so you could
+        // upload a tarball that contains both `foo-0.1.0/` source code as well
+        // as `bar-0.1.0/` source code, and this could overwrite other crates in
+        // the registry!
+        if !entry.path()?.starts_with(&prefix) {
+            return Err(human("invalid tarball uploaded"));
+        }
+
+        if entry.header().entry_type().is_file() {
+            let path = entry.path()?;
+            files.push(TarballFile {
+                path: path.strip_prefix(&prefix)
+                    .expect("checked above")
+                    .to_string_lossy()
+                    .into_owned(),
+                size: entry.header().size()? as i64,
+                mode: entry.header().mode().ok().map(|m| m as i32),
+            });
+        }
+
+        if *entry.path()? == *manifest_path {
+            let mut contents = String::new();
+            entry
+                .read_to_string(&mut contents)
+                .chain_error(|| human("uploaded `Cargo.toml` is not valid UTF-8"))?;
+            manifest = Some(contents);
+        }
+    }
+
+    let manifest = manifest.ok_or_else(|| human("uploaded tarball is missing `Cargo.toml`"))?;
+    manifest::verify(&manifest, new_crate)?;
+    Ok(files)
+}
@@ -1,5 +1,6 @@
 #![deny(warnings)]
 
+extern crate base64;
 extern crate cargo_registry;
 extern crate chrono;
 extern crate conduit;
@@ -21,7 +22,7 @@ extern crate tar;
 extern crate url;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::io::Read;
 use std::sync::Arc;
@@ -29,13 +30,17 @@ use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
 use cargo_registry::app::App;
 use cargo_registry::category::NewCategory;
-use cargo_registry::dependency::NewDependency;
+use cargo_registry::dependency::{Kind as DependencyKind, NewDependency};
 use cargo_registry::keyword::Keyword;
+use cargo_registry::index::IndexConfig;
+use cargo_registry::storage::{MemoryStorage, StorageConfig};
 use cargo_registry::krate::{CrateDownload, EncodableCrate, NewCrate};
 use cargo_registry::schema::*;
 use cargo_registry::upload as u;
 use cargo_registry::user::NewUser;
 use cargo_registry::owner::{CrateOwner, NewTeam, Team};
+use cargo_registry::background_job::Runner;
+use cargo_registry::publish_job::EncodablePublishJob;
 use cargo_registry::version::NewVersion;
 use cargo_registry::user::AuthenticationSource;
 use cargo_registry::{Crate, Dependency, Replica, User, Version};
@@ -84,13 +89,16 @@ struct Bad {
     errors: Vec<Error>,
 }
 
+mod background_job;
 mod badge;
 mod categories;
 mod category;
 mod git;
+mod index;
 mod keyword;
 mod krate;
 mod owners;
+mod publish_rate_limit;
 mod record;
 mod schema_details;
 mod team;
@@ -102,6 +110,7 @@ mod version;
 struct GoodCrate {
     #[serde(rename = "crate")] krate: EncodableCrate,
     warnings: Warnings,
+    publish_job: EncodablePublishJob,
 }
 #[derive(Deserialize)]
 struct CrateList {
@@ -112,6 +121,7 @@ struct CrateList {
 struct Warnings {
     invalid_categories: Vec<String>,
     invalid_badges: Vec<String>,
+    non_optional_dependency_features: Vec<String>,
 }
 #[derive(Deserialize)]
 struct CrateMeta {
@@ -123,6 +133,18 @@ fn app() -> (
     Arc<App>,
     conduit_middleware::MiddlewareBuilder,
 ) {
+    app_with_config(|_| {})
+}
+
+/// Like `app`, letting the test change the configuration first.
+fn app_with_config<F>(f: F) -> (
+    record::Bomb,
+    Arc<App>,
+    conduit_middleware::MiddlewareBuilder,
+)
+where
+    F: FnOnce(&mut cargo_registry::Config),
+{
     dotenv::dotenv().ok();
     git::init();
 
@@ -132,22 +154,11 @@ fn app() -> (
     // sniff/record it, but everywhere else we use https
     let api_protocol = String::from("http");
 
-    let uploader = cargo_registry::Uploader::S3 {
-        bucket: s3::Bucket::new(
-            String::from("alexcrichton-test"),
-            None,
-            std::env::var("S3_ACCESS_KEY").unwrap_or_default(),
-            std::env::var("S3_SECRET_KEY").unwrap_or_default(),
-            &api_protocol,
-        ),
-        proxy: Some(proxy),
-        cdn: None,
-    };
-
-    let config = cargo_registry::Config {
-        uploader: uploader,
+    let mut config = cargo_registry::Config {
+        storage: StorageConfig::Memory(MemoryStorage::new()),
         session_key: "test this has to be over 32 bytes long".to_string(),
-        git_repo_checkout: git::checkout(),
+        index: IndexConfig::Memory,
+        index_batch: None,
         gh_client_id: env::var("GH_CLIENT_ID").unwrap_or_default(),
         gh_client_secret: env::var("GH_CLIENT_SECRET").unwrap_or_default(),
         db_url: env("TEST_DATABASE_URL"),
@@ -156,7 +167,15 @@ fn app() -> (
         max_unpack_size: 2000,
         mirror: Replica::Primary,
         api_protocol: api_protocol,
+        domain_name: None,
+        repair_index_config: false,
+        serve_git_index: false,
+        private_index: false,
+        publish_rate_limit: Default::default(),
+        allowed_registries: vec![String::from("https://registry.example.com/index")],
+        http_proxy: Some(proxy),
     };
+    f(&mut config);
     let app = App::new(&config);
     t!(t!(app.diesel_database.get()).begin_test_transaction());
     let app = Arc::new(app);
@@ -164,6 +183,40 @@ fn app() -> (
     (bomb, app, middleware)
 }
 
+/// Returns the files the app stored, when it uses the default storage of
+/// `app_with_config`.
+fn storage(app: &App) -> MemoryStorage {
+    match app.config.storage {
+        StorageConfig::Memory(ref storage) => storage.clone(),
+        _ => panic!("the app doesn't store files in memory"),
+    }
+}
+
+/// Storage in the S3 bucket the recorded S3 requests were made to, going
+/// through the recording proxy of the app, or in a live S3-compatible store
+/// (see `record::live_s3`).
+fn s3_storage(config: &cargo_registry::Config) -> StorageConfig {
+    let bucket = s3::Bucket::new(
+        String::from("alexcrichton-test"),
+        None,
+        env::var("S3_ACCESS_KEY").unwrap_or_default(),
+        env::var("S3_SECRET_KEY").unwrap_or_default(),
+        &config.api_protocol,
+    );
+    match record::live_s3() {
+        Some(endpoint) => StorageConfig::S3 {
+            bucket: bucket.with_endpoint(endpoint),
+            proxy: None,
+            cdn: None,
+        },
+        None => StorageConfig::S3 {
+            bucket: bucket,
+            proxy: config.http_proxy.clone(),
+            cdn: None,
+        },
+    }
+}
+
 // Return the environment variable only if it has been defined
 fn env(s: &str) -> String {
     // Handles both the `None` and empty string cases e.g. VAR=
@@ -228,6 +281,7 @@ fn user(login: &str) -> User {
         name: None,
         gh_avatar: None,
         gh_access_token: "some random token".into(),
+        admin: false,
     }
 }
 
@@ -315,7 +369,7 @@ impl<'a> VersionBuilder<'a> {
                 NewDependency {
                     version_id: vers.id,
                     req: ">= 0".into(),
-                    crate_id,
+                    crate_id: Some(crate_id),
                     target,
                     ..Default::default()
                 }
@@ -408,7 +462,7 @@ impl<'a> CrateBuilder<'a> {
         use diesel::{insert_into, update};
 
         let mut krate = self.krate
-            .create_or_update(connection, None, self.owner_id)?;
+            .create_or_update(connection, None, self.owner_id, |_| Ok(false))?;
 
         // Since we are using `NewCrate`, we can't set all the
         // crate properties in a single DB call.
@@ -502,6 +556,21 @@ fn sign_in(req: &mut Request, app: &App) -> User {
     user
 }
 
+fn sign_in_as_admin(req: &mut Request, app: &App) -> User {
+    let conn = app.diesel_database.get().unwrap();
+    let user = ::new_user("admin").create_or_update(&conn).unwrap();
+    let user = diesel::update(&user)
+        .set(users::admin.eq(true))
+        .get_result::<User>(&*conn)
+        .unwrap();
+    sign_in_as(req, &user);
+    user
+}
+
+fn run_background_jobs(app: &Arc<App>) {
+    t!(Runner::new(Arc::clone(app)).run_pending());
+}
+
 fn new_dependency(conn: &PgConnection, version: &Version, krate: &Crate) -> Dependency {
     use diesel::insert_into;
     use cargo_registry::schema::dependencies;
@@ -509,7 +578,7 @@ fn new_dependency(conn: &PgConnection, version: &Version, krate: &Crate) -> Depe
     insert_into(dependencies::table)
         .values(&NewDependency {
             version_id: version.id,
-            crate_id: krate.id,
+            crate_id: Some(krate.id),
             req: ">= 0".into(),
             optional: false,
             ..Default::default()
@@ -677,10 +746,90 @@ fn new_crate_to_body(new_crate: &u::NewCrate, files: &[(&str, &[u8])]) -> Vec<u8
 fn new_crate_to_body_with_io(
     new_crate: &u::NewCrate,
     files: &mut [(&str, &mut Read, u64)],
+) -> Vec<u8> {
+    let manifest = new_crate_manifest(new_crate);
+    new_crate_to_body_with_manifest(new_crate, Some(&manifest), files)
+}
+
+/// Renders a `Cargo.toml` matching the metadata in `new_crate`, like the one
+/// `cargo package` would put in the tarball.
+fn new_crate_manifest(new_crate: &u::NewCrate) -> String {
+    fn list<T: AsRef<str>>(items: &[T]) -> String {
+        items
+            .iter()
+            .map(|item| format!("\"{}\"", item.as_ref()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    let mut manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"{}\"\n",
+        new_crate.name.0,
+        new_crate.vers.0
+    );
+    if let Some(ref license) = new_crate.license {
+        manifest.push_str(&format!("license = \"{}\"\n", license));
+    }
+    for dep in &new_crate.deps {
+        let table = match dep.kind {
+            Some(DependencyKind::Build) => "build-dependencies",
+            Some(DependencyKind::Dev) => "dev-dependencies",
+            Some(DependencyKind::Normal) | None => "dependencies",
+        };
+        let table = match dep.target {
+            Some(ref target) => format!("target.'{}'.{}", target, table),
+            None => table.to_string(),
+        };
+        let features = dep.features.iter().map(|f| &f.0).collect::<Vec<_>>();
+        let key = dep.explicit_name_in_toml.as_ref().unwrap_or(&dep.name);
+        manifest.push_str(&format!(
+            "\n[{}.{}]\nversion = \"{}\"\noptional = {}\ndefault-features = {}\nfeatures = [{}]\n",
+            table,
+            key.0,
+            dep.version_req.0,
+            dep.optional,
+            dep.default_features,
+            list(&features)
+        ));
+        if dep.explicit_name_in_toml.is_some() {
+            manifest.push_str(&format!("package = \"{}\"\n", dep.name.0));
+        }
+        if let Some(ref registry) = dep.registry {
+            manifest.push_str(&format!("registry-index = \"{}\"\n", registry));
+        }
+    }
+    if !new_crate.features.is_empty() {
+        manifest.push_str("\n[features]\n");
+        let features = new_crate
+            .features
+            .iter()
+            .map(|(name, values)| (&name.0, values.iter().map(|v| &v.0).collect::<Vec<_>>()))
+            .collect::<BTreeMap<_, _>>();
+        for (name, values) in features {
+            manifest.push_str(&format!("{} = [{}]\n", name, list(&values)));
+        }
+    }
+    manifest
+}
+
+/// Like `new_crate_to_body_with_io`, but with the given `Cargo.toml` (or none
+/// at all) instead of one generated from `new_crate`.
+fn new_crate_to_body_with_manifest(
+    new_crate: &u::NewCrate,
+    manifest: Option<&str>,
+    files: &mut [(&str, &mut Read, u64)],
 ) -> Vec<u8> {
     let mut tarball = Vec::new();
     {
         let mut ar = tar::Builder::new(GzEncoder::new(&mut tarball, Compression::Default));
+        if let Some(manifest) = manifest {
+            let path = format!("{}-{}/Cargo.toml", new_crate.name.0, new_crate.vers.0);
+            let mut header = tar::Header::new_gnu();
+            t!(header.set_path(&path));
+            header.set_size(manifest.len() as u64);
+            header.set_cksum();
+            t!(ar.append(&header, manifest.as_bytes()));
+        }
         for &mut (name, ref mut data, size) in files {
             let mut header = tar::Header::new_gnu();
             t!(header.set_path(name));
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use conduit::{Handler, Method};
+use diesel;
+use diesel::prelude::*;
+
+use cargo_registry::app::App;
+use cargo_registry::background_job::{BackgroundJob, EncodableBackgroundJob, Job, Runner,
+                                     MAX_RETRIES};
+use cargo_registry::schema::background_jobs;
+use cargo_registry::util::{human, CargoResult};
+
+#[derive(Deserialize)]
+struct BackgroundJobList {
+    background_jobs: Vec<EncodableBackgroundJob>,
+    meta: BackgroundJobMeta,
+}
+#[derive(Deserialize)]
+struct BackgroundJobMeta {
+    total: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AlwaysFails {
+    n: i32,
+}
+
+impl Job for AlwaysFails {
+    const JOB_TYPE: &'static str = "always_fails";
+
+    fn perform(self, _: &App, _: &PgConnection) -> CargoResult<()> {
+        Err(human(&format_args!("job {} failed", self.n)))
+    }
+}
+
+fn runner(app: &Arc<App>) -> Runner {
+    let mut runner = Runner::new(Arc::clone(app));
+    runner.register::<AlwaysFails>();
+    runner
+}
+
+#[test]
+fn failed_jobs_are_retried_then_dead() {
+    let (_b, app, _) = ::app();
+    let runner = runner(&app);
+    let conn = app.diesel_database.get().unwrap();
+    let job = t!(AlwaysFails { n: 1 }.enqueue(&conn));
+    drop(conn);
+
+    assert_eq!(t!(runner.run_pending()), 1);
+    let conn = app.diesel_database.get().unwrap();
+    let job = t!(background_jobs::table.find(job.id).first::<BackgroundJob>(&*conn));
+    assert_eq!(job.retries, 1);
+    assert_eq!(job.last_error, Some("job 1 failed".to_string()));
+    assert!(!job.dead);
+    drop(conn);
+
+    // The job isn't due again until its backoff has elapsed
+    assert_eq!(t!(runner.run_pending()), 0);
+
+    let conn = app.diesel_database.get().unwrap();
+    t!(
+        diesel::update(&job)
+            .set((
+                background_jobs::retries.eq(MAX_RETRIES - 1),
+                background_jobs::last_retry.eq(NaiveDate::from_ymd(2017, 1, 1).and_hms(0, 0, 0)),
+            ))
+            .execute(&*conn)
+    );
+    drop(conn);
+
+    assert_eq!(t!(runner.run_pending()), 1);
+    let conn = app.diesel_database.get().unwrap();
+    let job = t!(background_jobs::table.find(job.id).first::<BackgroundJob>(&*conn));
+    assert_eq!(job.retries, MAX_RETRIES);
+    assert!(job.dead);
+    drop(conn);
+
+    // Dead jobs are never picked up again
+    assert_eq!(t!(runner.run_pending()), 0);
+}
+
+#[test]
+fn unknown_job_types_fail() {
+    let (_b, app, _) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let job = t!(AlwaysFails { n: 1 }.enqueue(&conn));
+    drop(conn);
+
+    // `Runner::new` doesn't know about `AlwaysFails`
+    assert_eq!(t!(Runner::new(Arc::clone(&app)).run_pending()), 1);
+    let conn = app.diesel_database.get().unwrap();
+    let job = t!(background_jobs::table.find(job.id).first::<BackgroundJob>(&*conn));
+    assert_eq!(job.retries, 1);
+    assert_eq!(
+        job.last_error,
+        Some("unknown job type `always_fails`".to_string())
+    );
+}
+
+#[test]
+fn list_requires_admin() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        "/api/v1/admin/background_jobs",
+    );
+    ::sign_in(&mut req, &app);
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("must be an admin"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn list() {
+    let (_b, app, middle) = ::app();
+    let runner = runner(&app);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        t!(AlwaysFails { n: 1 }.enqueue(&conn));
+        let dead = t!(AlwaysFails { n: 2 }.enqueue(&conn));
+        t!(
+            diesel::update(&dead)
+                .set((
+                    background_jobs::retries.eq(MAX_RETRIES - 1),
+                    background_jobs::last_retry.eq(NaiveDate::from_ymd(2017, 1, 1).and_hms(0, 0, 0)),
+                ))
+                .execute(&*conn)
+        );
+    }
+    t!(runner.run_pending());
+
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        "/api/v1/admin/background_jobs",
+    );
+    ::sign_in_as_admin(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: BackgroundJobList = ::json(&mut response);
+    assert_eq!(json.meta.total, 2);
+    assert_eq!(json.background_jobs[0].data["n"], 2);
+    assert!(json.background_jobs[0].dead);
+    assert_eq!(json.background_jobs[1].data["n"], 1);
+    assert!(!json.background_jobs[1].dead);
+
+    let mut response = ok_resp!(middle.call(req.with_query("dead=true")));
+    let json: BackgroundJobList = ::json(&mut response);
+    assert_eq!(json.meta.total, 1);
+    assert_eq!(json.background_jobs[0].job_type, "always_fails");
+    assert_eq!(
+        json.background_jobs[0].last_error,
+        Some("job 2 failed".to_string())
+    );
+}
@@ -4,6 +4,8 @@ use std::thread;
 use std::path::PathBuf;
 use std::sync::{Once, ONCE_INIT};
 
+use cargo_registry::git::Credentials;
+use cargo_registry::index::IndexConfig;
 use git2;
 use url::Url;
 
@@ -21,6 +23,22 @@ pub fn bare() -> PathBuf {
     root().join("bare")
 }
 
+/// An index committing to the checkout without pushing it, for the tests
+/// which look at its commits or serve it over HTTP. The other tests use an
+/// index kept in memory.
+pub fn local_index() -> IndexConfig {
+    IndexConfig::LocalGit { path: checkout() }
+}
+
+/// An index pushing to the bare repository from the checkout, like
+/// crates.io's.
+pub fn remote_index() -> IndexConfig {
+    IndexConfig::RemoteGit {
+        checkout: checkout(),
+        credentials: Credentials::None,
+    }
+}
+
 pub fn init() {
     static INIT: Once = ONCE_INIT;
     let _ = fs::remove_dir_all(&checkout());
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use conduit::{Handler, Method};
+use base64;
+use diesel;
+use diesel::prelude::*;
+use git2;
+use serde_json;
+
+use cargo_registry::dependency::Kind;
+use cargo_registry::git::{self, Credentials};
+use cargo_registry::index::RemoteGitIndex;
+use cargo_registry::index::check::{check_crate, check_orphan, orphaned_files, repair, Problem};
+use cargo_registry::schema::versions;
+use cargo_registry::token::ApiToken;
+
+fn body(response: &mut ::conduit::Response) -> String {
+    let mut data = Vec::new();
+    response.body.write_body(&mut data).unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn sparse_index_file() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let u = ::new_user("foo").create_or_update(&conn).unwrap();
+        let dep = ::CrateBuilder::new("bar_sparse_dep", u.id).expect_build(&conn);
+        ::CrateBuilder::new("foo_sparse", u.id)
+            .version("1.0.0")
+            .version(::VersionBuilder::new("1.1.0").dependency(&dep, None))
+            .version("2.0.0")
+            .expect_build(&conn);
+
+        // Versions published before checksums were recorded are left out
+        diesel::update(versions::table.filter(versions::num.ne("2.0.0")))
+            .set(versions::checksum.eq("abc"))
+            .execute(&*conn)
+            .unwrap();
+        diesel::update(versions::table.filter(versions::num.eq("1.0.0")))
+            .set(versions::yanked.eq(true))
+            .execute(&*conn)
+            .unwrap();
+    }
+
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/index/fo/o_/foo_sparse");
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert_eq!(
+        response.headers["Content-Type"],
+        vec!["text/plain; charset=utf-8"]
+    );
+    assert_eq!(response.headers["Last-Modified"].len(), 1);
+    let etag = response.headers["ETag"][0].clone();
+    assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+
+    let entries = body(&mut response)
+        .lines()
+        .map(|line| serde_json::from_str::<git::Crate>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "foo_sparse");
+    assert_eq!(entries[0].vers, "1.0.0");
+    assert_eq!(entries[0].cksum, "abc");
+    assert_eq!(entries[0].yanked, Some(true));
+    assert!(entries[0].deps.is_empty());
+    assert_eq!(entries[1].vers, "1.1.0");
+    assert_eq!(entries[1].yanked, Some(false));
+    assert_eq!(entries[1].deps.len(), 1);
+    assert_eq!(entries[1].deps[0].name, "bar_sparse_dep");
+    assert!(match entries[1].deps[0].kind {
+        Some(Kind::Normal) => true,
+        _ => false,
+    });
+    assert_eq!(entries[1].deps[0].package, None);
+
+    // The ETag only changes along with the file
+    req.header("If-None-Match", &etag);
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 304);
+}
+
+#[test]
+fn sparse_index_file_not_found() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let u = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::CrateBuilder::new("Foo_Sparse_Case", u.id)
+            .version("1.0.0")
+            .expect_build(&conn);
+        diesel::update(versions::table)
+            .set(versions::checksum.eq("abc"))
+            .execute(&*conn)
+            .unwrap();
+    }
+
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        "/index/fo/o_/foo_sparse_case",
+    );
+    ok_resp!(middle.call(&mut req));
+
+    for path in &[
+        "/index/fo/o-/foo-sparse-case",
+        "/index/fo/oo/foo_sparse_case",
+        "/index/fo/o_/missing_sparse",
+    ] {
+        let mut req = ::req(Arc::clone(&app), Method::Get, path);
+        let response = t_resp!(middle.call(&mut req));
+        assert_eq!(response.status.0, 404, "{}", path);
+    }
+}
+
+#[test]
+fn sparse_index_config() {
+    #[derive(Deserialize)]
+    struct Config {
+        dl: String,
+        api: String,
+    }
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/index/config.json");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Config = ::json(&mut response);
+    assert!(json.api.starts_with("http://"), "{}", json.api);
+    assert_eq!(json.dl, format!("{}/api/v1/crates", json.api));
+}
+
+#[test]
+fn check_and_repair_index() {
+    let (_b, app, _middle) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let u = ::new_user("foo").create_or_update(&conn).unwrap();
+    let krate = ::CrateBuilder::new("foo_check", u.id)
+        .version("1.0.0")
+        .version("1.1.0")
+        .version("1.2.0")
+        .expect_build(&conn);
+    for &(num, checksum) in &[("1.0.0", "a"), ("1.1.0", "b")] {
+        diesel::update(versions::table.filter(versions::num.eq(num)))
+            .set(versions::checksum.eq(checksum))
+            .execute(&*conn)
+            .unwrap();
+    }
+    diesel::update(versions::table.filter(versions::num.eq("1.1.0")))
+        .set(versions::yanked.eq(true))
+        .execute(&*conn)
+        .unwrap();
+
+    for &(vers, cksum) in &[("0.9.0", "z"), ("1.0.0", "a"), ("1.1.0", "wrong")] {
+        app.index
+            .add_version(&git::Crate {
+                name: "foo_check".to_string(),
+                vers: vers.to_string(),
+                deps: Vec::new(),
+                cksum: cksum.to_string(),
+                features: HashMap::new(),
+                yanked: Some(false),
+            })
+            .unwrap();
+    }
+
+    let check = check_crate(&conn, &*app.index, &krate).unwrap();
+    let problems = check
+        .discrepancies
+        .iter()
+        .map(|d| (&*d.version, d.problem))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        problems,
+        vec![
+            ("1.1.0", Problem::YankedMismatch),
+            ("1.1.0", Problem::ChecksumMismatch),
+            ("1.2.0", Problem::MissingFromIndex),
+            ("0.9.0", Problem::MissingFromDatabase),
+        ]
+    );
+
+    // 1.2.0 has no checksum anywhere, so it can't be added back
+    let repaired = repair(&conn, &*app.index, &[krate.clone()], &[]).unwrap();
+    assert_eq!(repaired, vec!["foo_check"]);
+    let entries = app.index.entries("foo_check").unwrap();
+    let versions = entries
+        .iter()
+        .map(|e| (&*e.vers, &*e.cksum, e.yanked))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        versions,
+        vec![("1.0.0", "a", Some(false)), ("1.1.0", "b", Some(true))]
+    );
+
+    let check = check_crate(&conn, &*app.index, &krate).unwrap();
+    assert_eq!(check.discrepancies.len(), 1);
+    assert_eq!(check.discrepancies[0].problem, Problem::MissingFromIndex);
+}
+
+#[test]
+fn check_and_repair_orphaned_index_files() {
+    let (_b, app, _middle) = ::app_with_config(|config| config.index = ::git::local_index());
+    let conn = app.diesel_database.get().unwrap();
+    let u = ::new_user("foo").create_or_update(&conn).unwrap();
+    ::CrateBuilder::new("Foo_Kept", u.id).expect_build(&conn);
+    for name in &["Foo_Kept", "Foo_Orphan"] {
+        app.index
+            .add_version(&git::Crate {
+                name: name.to_string(),
+                vers: "1.0.0".to_string(),
+                deps: Vec::new(),
+                cksum: "a".to_string(),
+                features: HashMap::new(),
+                yanked: Some(false),
+            })
+            .unwrap();
+    }
+
+    let orphans = orphaned_files(&conn, &*app.index).unwrap();
+    assert_eq!(orphans, vec!["foo_orphan"]);
+    let discrepancies = check_orphan(&*app.index, &orphans[0]).unwrap();
+    assert_eq!(discrepancies.len(), 1);
+    assert_eq!(discrepancies[0].version, "1.0.0");
+    assert_eq!(discrepancies[0].problem, Problem::MissingFromDatabase);
+
+    let repaired = repair(&conn, &*app.index, &[], &orphans).unwrap();
+    assert_eq!(repaired, vec!["foo_orphan"]);
+    assert_eq!(app.index.read_file("foo_orphan").unwrap(), None);
+    assert!(app.index.read_file("foo_kept").unwrap().is_some());
+}
+
+#[test]
+fn git_index_is_not_served_by_default() {
+    let (_b, app, middle) = ::app_with_config(|config| config.index = ::git::local_index());
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/git/index/info/refs");
+    let response = t_resp!(middle.call(req.with_query("service=git-upload-pack")));
+    assert_eq!(response.status.0, 404);
+}
+
+#[test]
+fn git_index_over_smart_http() {
+    let (_b, app, middle) = ::app_with_config(|config| {
+        config.index = ::git::local_index();
+        config.serve_git_index = true;
+    });
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/git/index/info/refs");
+    let mut response = ok_resp!(middle.call(req.with_query("service=git-upload-pack")));
+    assert_eq!(
+        response.headers["Content-Type"],
+        vec!["application/x-git-upload-pack-advertisement"]
+    );
+    assert_eq!(
+        response.headers["Cache-Control"],
+        vec!["no-cache, max-age=0, must-revalidate"]
+    );
+    assert!(body(&mut response).contains("refs/heads/master"));
+}
+
+#[test]
+fn git_index_rejects_pushes() {
+    let (_b, app, middle) = ::app_with_config(|config| {
+        config.index = ::git::local_index();
+        config.serve_git_index = true;
+    });
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/git/index/info/refs");
+    let response = t_resp!(middle.call(req.with_query("service=git-receive-pack")));
+    assert_eq!(response.status.0, 403);
+
+    let mut req = ::req(Arc::clone(&app), Method::Post, "/git/index/git-receive-pack");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 403);
+}
+
+#[test]
+fn private_git_index_requires_a_token() {
+    let (_b, app, middle) = ::app_with_config(|config| {
+        config.index = ::git::local_index();
+        config.serve_git_index = true;
+        config.private_index = true;
+    });
+    let token = {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ApiToken::insert(&conn, user.id, "git").unwrap().token
+    };
+
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/git/index/info/refs");
+    req.with_query("service=git-upload-pack");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 401);
+    assert!(response.headers.contains_key("WWW-Authenticate"));
+
+    req.header("Authorization", "Basic Z2l0Ondyb25n");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 401);
+
+    let credentials = base64::encode(format!("git:{}", token).as_bytes());
+    req.header("Authorization", &format!("Basic {}", credentials));
+    let response = ok_resp!(middle.call(&mut req));
+    assert_eq!(
+        response.headers["Cache-Control"],
+        vec!["private, no-cache, max-age=0, must-revalidate"]
+    );
+    assert_eq!(response.headers["Vary"], vec!["Authorization"]);
+}
+
+#[test]
+fn private_sparse_index_requires_a_token() {
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(rename = "auth-required")]
+        auth_required: bool,
+    }
+    let (_b, app, middle) = ::app_with_config(|config| config.private_index = true);
+    let token = {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::CrateBuilder::new("foo_private", user.id).expect_build(&conn);
+        ApiToken::insert(&conn, user.id, "sparse").unwrap().token
+    };
+
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/index/config.json");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 401);
+    let response = t_resp!(middle.call(req.with_path("/index/fo/o_/foo_private")));
+    assert_eq!(response.status.0, 401);
+
+    req.header("Authorization", &token);
+    let mut response = ok_resp!(middle.call(req.with_path("/index/config.json")));
+    assert_eq!(response.headers["Cache-Control"], vec!["private"]);
+    assert!(::json::<Config>(&mut response).auth_required);
+}
+
+#[test]
+fn check_remote_index_needs_write_access() {
+    ::git::init();
+    let checkout = git2::Repository::open(&::git::checkout()).unwrap();
+    let index = RemoteGitIndex::open(&::git::checkout(), Credentials::None).unwrap();
+    index.check_remote().unwrap();
+
+    // The remote can still be fetched from, but not pushed to
+    checkout
+        .remote_set_pushurl("origin", Some("file:///does/not/exist"))
+        .unwrap();
+    let err = index.check_remote().unwrap_err();
+    assert!(
+        err.to_string().contains("failed to push the index"),
+        "{}",
+        err
+    );
+}
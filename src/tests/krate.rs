@@ -1,7 +1,8 @@
 extern crate diesel;
 
 use std::collections::HashMap;
-use std::fs::{self, File};
+use std::env;
+use std::fs;
 use std::io::prelude::*;
 use std::io;
 use std::sync::Arc;
@@ -19,13 +20,17 @@ use cargo_registry::download::EncodableVersionDownload;
 use cargo_registry::git;
 use cargo_registry::keyword::EncodableKeyword;
 use cargo_registry::krate::{Crate, EncodableCrate, MAX_NAME_LENGTH};
+use cargo_registry::publish_job::{EncodablePublishJob, PublishJobStatus};
 
 use cargo_registry::token::ApiToken;
-use cargo_registry::schema::{crates, metadata, versions};
+use cargo_registry::schema::{background_jobs, crates, metadata, versions};
 
 use cargo_registry::upload as u;
 use cargo_registry::version::EncodableVersion;
+use cargo_registry::version::yank::EncodableVersionYankEvent;
+use cargo_registry::version::files::EncodableVersionFile;
 use cargo_registry::category::{Category, EncodableCategory};
+use cargo_registry::storage::StorageConfig;
 
 use {CrateList, CrateMeta, GoodCrate};
 
@@ -50,6 +55,25 @@ struct RevDeps {
     meta: CrateMeta,
 }
 #[derive(Deserialize)]
+struct ValidateResponse {
+    ok: bool,
+    errors: Vec<::Error>,
+    warnings: ::Warnings,
+}
+#[derive(Deserialize)]
+struct FileList {
+    files: Vec<EncodableVersionFile>,
+}
+
+#[derive(Deserialize)]
+struct PublishJobResponse {
+    publish_job: EncodablePublishJob,
+}
+#[derive(Deserialize)]
+struct PublishJobList {
+    publish_jobs: Vec<EncodablePublishJob>,
+}
+#[derive(Deserialize)]
 struct Downloads {
     version_downloads: Vec<EncodableVersionDownload>,
 }
@@ -528,13 +552,125 @@ fn new_bd_names() {
 
 #[test]
 fn new_krate() {
-    let (_b, app, middle) = ::app();
+    // Uploads to S3, checking the recorded signed request
+    let (_b, app, middle) = ::app_with_config(|config| config.storage = ::s3_storage(config));
     let mut req = ::new_req(Arc::clone(&app), "foo_new", "1.0.0");
     ::sign_in(&mut req, &app);
     let mut response = ok_resp!(middle.call(&mut req));
     let json: GoodCrate = ::json(&mut response);
     assert_eq!(json.krate.name, "foo_new");
     assert_eq!(json.krate.max_version, "1.0.0");
+
+    // The tarball only contains the generated `Cargo.toml`
+    let conn = app.diesel_database.get().unwrap();
+    let sizes = versions::table
+        .select((versions::crate_size, versions::unpacked_size))
+        .first::<(Option<i32>, Option<i32>)>(&*conn)
+        .unwrap();
+    assert_eq!(sizes, (Some(140), Some(61)));
+}
+
+#[test]
+fn new_krate_with_filesystem_storage() {
+    let root = env::current_dir()
+        .unwrap()
+        .join("tmp")
+        .join("new_krate_with_filesystem_storage");
+    let _ = fs::remove_dir_all(&root);
+    let storage_root = root.clone();
+    let (_b, app, middle) = ::app_with_config(move |config| {
+        config.storage = StorageConfig::Filesystem { root: storage_root };
+    });
+    let mut req = ::new_req(Arc::clone(&app), "foo_fs", "1.0.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    assert!(root.join("crates/foo_fs/foo_fs-1.0.0.crate").exists());
+
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        "/api/v1/crates/foo_fs/1.0.0/download",
+    );
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 302);
+    assert_eq!(
+        response.headers["Location"],
+        vec!["/crates/foo_fs/foo_fs-1.0.0.crate"]
+    );
+
+    req.with_path("/crates/foo_fs/foo_fs-1.0.0.crate");
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert_eq!(response.headers["Content-Type"], vec!["application/x-tar"]);
+    assert_eq!(response.headers["Content-Length"], vec!["140"]);
+    let etag = response.headers["ETag"][0].clone();
+    let mut body = Vec::new();
+    response.body.write_body(&mut body).unwrap();
+    assert_eq!(body.len(), 140);
+
+    req.header("If-None-Match", &etag);
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 304);
+
+    req.with_path("/crates/foo_fs/../foo_fs/foo_fs-1.0.0.crate");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 404);
+
+    // Reading a file of the crate streams it from the stored file.
+    let mut response = ok_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_fs/1.0.0/files/Cargo.toml"),
+    ));
+    let mut contents = String::new();
+    response.body.read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("name = \"foo_fs\""), "{}", contents);
+}
+
+#[test]
+fn new_krate_with_memory_storage() {
+    let (_b, app, middle) = ::app();
+    let storage = ::storage(&app);
+    let mut krate = ::krate("foo_mem");
+    krate.readme = Some("# foo_mem".to_owned());
+    let mut req = ::new_req_full(Arc::clone(&app), krate, "1.0.0", vec![]);
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+
+    assert_eq!(
+        storage.paths(),
+        [
+            "crates/foo_mem/foo_mem-1.0.0.crate",
+            "readmes/foo_mem/foo_mem-1.0.0.html",
+        ]
+    );
+    let krate = storage.file("crates/foo_mem/foo_mem-1.0.0.crate").unwrap();
+    assert_eq!(krate.content_type, "application/x-tar");
+    let conn = app.diesel_database.get().unwrap();
+    let crate_size = versions::table
+        .select(versions::crate_size)
+        .first::<Option<i32>>(&*conn)
+        .unwrap();
+    assert_eq!(crate_size, Some(krate.body.len() as i32));
+    let readme = storage.file("readmes/foo_mem/foo_mem-1.0.0.html").unwrap();
+    assert_eq!(readme.content_type, "text/html");
+    assert!(String::from_utf8(readme.body).unwrap().contains("foo_mem</h1>"));
+
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        "/api/v1/crates/foo_mem/1.0.0/download",
+    );
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(
+        response.headers["Location"],
+        vec!["/crates/foo_mem/foo_mem-1.0.0.crate"]
+    );
+
+    // Reading a file of the crate fetches it back from the storage.
+    let mut response = ok_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_mem/1.0.0/files/Cargo.toml"),
+    ));
+    let mut contents = String::new();
+    response.body.read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("name = \"foo_mem\""), "{}", contents);
 }
 
 #[test]
@@ -555,6 +691,72 @@ fn new_krate_with_token() {
     assert_eq!(json.krate.max_version, "1.0.0");
 }
 
+#[test]
+fn new_krate_similar_to_popular_crate_is_held() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::CrateBuilder::new("foo_popular", owner.id)
+            .downloads(100_000)
+            .expect_build(&conn);
+    }
+    let mut req = ::new_req(Arc::clone(&app), "foo-popular-rs", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: GoodCrate = ::json(&mut response);
+    assert_eq!(json.publish_job.status, PublishJobStatus::Held);
+    assert!(
+        json.publish_job
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("similar to the popular crate `foo_popular`"),
+        "{:?}",
+        json.publish_job.error
+    );
+
+    // Nothing is added to the index until an admin approves it
+    ::run_background_jobs(&app);
+    assert_eq!(app.index.read_file("foo-popular-rs").unwrap(), None);
+
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/api/v1/admin/publish_jobs");
+    ::sign_in_as_admin(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let held: PublishJobList = ::json(&mut response);
+    assert_eq!(held.publish_jobs.len(), 1);
+    assert_eq!(held.publish_jobs[0].krate, "foo-popular-rs");
+
+    let url = format!(
+        "/api/v1/admin/publish_jobs/{}/approve",
+        json.publish_job.id
+    );
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put).with_path(&url)));
+    let approved: PublishJobResponse = ::json(&mut response);
+    assert_eq!(approved.publish_job.status, PublishJobStatus::Pending);
+    assert_eq!(approved.publish_job.error, None);
+    bad_resp!(middle.call(&mut req));
+
+    ::run_background_jobs(&app);
+    assert_eq!(app.index.entries("foo-popular-rs").unwrap().len(), 1);
+}
+
+#[test]
+fn new_krate_similar_to_own_popular_crate() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo-popular-rs", "1.0.0");
+    let user = ::sign_in(&mut req, &app);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        ::CrateBuilder::new("foo_popular", user.id)
+            .downloads(100_000)
+            .expect_build(&conn);
+    }
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: GoodCrate = ::json(&mut response);
+    assert_eq!(json.publish_job.status, PublishJobStatus::Pending);
+}
+
 #[test]
 fn new_krate_with_reserved_name() {
     fn test_bad_name(name: &str) {
@@ -598,6 +800,8 @@ fn new_krate_with_dependency() {
         version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
         target: None,
         kind: None,
+        explicit_name_in_toml: None,
+        registry: None,
     };
     let mut req = ::new_req_full(Arc::clone(&app), ::krate("new_dep"), "1.0.0", vec![dep]);
     {
@@ -609,15 +813,11 @@ fn new_krate_with_dependency() {
 
     let mut response = ok_resp!(middle.call(&mut req));
     ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
 
-    let path = ::git::checkout().join("ne/w_/new_dep");
-    assert!(path.exists());
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
-    let p: git::Crate = serde_json::from_str(&contents).unwrap();
+    let entries = app.index.entries("new_dep").unwrap();
+    assert_eq!(entries.len(), 1);
+    let p = &entries[0];
     assert_eq!(p.name, "new_dep");
     assert_eq!(p.vers, "1.0.0");
     assert_eq!(p.deps.len(), 1);
@@ -636,6 +836,8 @@ fn new_krate_non_canon_crate_name_dependencies() {
             version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
             target: None,
             kind: None,
+            explicit_name_in_toml: None,
+            registry: None,
         },
     ];
     let mut req = ::new_req_full(Arc::clone(&app), ::krate("new_dep"), "1.0.0", deps);
@@ -662,6 +864,8 @@ fn new_krate_with_wildcard_dependency() {
         version_req: u::CrateVersionReq(semver::VersionReq::parse("*").unwrap()),
         target: None,
         kind: None,
+        explicit_name_in_toml: None,
+        registry: None,
     };
     let mut req = ::new_req_full(Arc::clone(&app), ::krate("new_wild"), "1.0.0", vec![dep]);
     {
@@ -678,6 +882,96 @@ fn new_krate_with_wildcard_dependency() {
     );
 }
 
+#[test]
+fn new_krate_with_renamed_dependencies() {
+    let (_b, app, middle) = ::app();
+    let deps = vec![
+        u::CrateDependency {
+            name: u::CrateName("foo_dep".to_string()),
+            optional: false,
+            default_features: true,
+            features: Vec::new(),
+            version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+            target: None,
+            kind: None,
+            explicit_name_in_toml: Some(u::CrateName("bar_dep".to_string())),
+            registry: None,
+        },
+        u::CrateDependency {
+            name: u::CrateName("other_dep".to_string()),
+            optional: false,
+            default_features: true,
+            features: Vec::new(),
+            version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+            target: None,
+            kind: None,
+            explicit_name_in_toml: None,
+            registry: Some("https://registry.example.com/index".to_string()),
+        },
+    ];
+    let mut req = ::new_req_full(Arc::clone(&app), ::krate("new_renamed"), "1.0.0", deps);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::CrateBuilder::new("foo_dep", user.id).expect_build(&conn);
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
+
+    let entries = app.index.entries("new_renamed").unwrap();
+    let p = &entries[0];
+    assert_eq!(p.deps.len(), 2);
+    assert_eq!(p.deps[0].name, "bar_dep");
+    assert_eq!(p.deps[0].package, Some("foo_dep".to_string()));
+    assert_eq!(p.deps[0].registry, None);
+    assert_eq!(p.deps[1].name, "other_dep");
+    assert_eq!(p.deps[1].package, None);
+    assert_eq!(
+        p.deps[1].registry,
+        Some("https://registry.example.com/index".to_string())
+    );
+
+    req.with_method(Method::Get)
+        .with_path("/api/v1/crates/new_renamed/1.0.0/dependencies");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let deps = ::json::<Deps>(&mut response).dependencies;
+    assert_eq!(deps.len(), 2);
+    assert_eq!(deps[0].crate_id, "foo_dep");
+    assert_eq!(deps[0].explicit_name, Some("bar_dep".to_string()));
+    assert_eq!(deps[1].crate_id, "other_dep");
+    assert_eq!(
+        deps[1].registry,
+        Some("https://registry.example.com/index".to_string())
+    );
+}
+
+#[test]
+fn new_krate_with_dependency_from_disallowed_registry() {
+    let (_b, app, middle) = ::app();
+    let dep = u::CrateDependency {
+        name: u::CrateName("other_dep".to_string()),
+        optional: false,
+        default_features: true,
+        features: Vec::new(),
+        version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+        target: None,
+        kind: None,
+        explicit_name_in_toml: None,
+        registry: Some("https://evil.example.com/index".to_string()),
+    };
+    let mut req = ::new_req_full(Arc::clone(&app), ::krate("new_evil"), "1.0.0", vec![dep]);
+    ::sign_in(&mut req, &app);
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("not allowed to depend on"),
+        "{:?}",
+        json.errors
+    );
+}
+
 #[test]
 fn new_krate_twice() {
     let (_b, app, middle) = ::app();
@@ -802,6 +1096,233 @@ fn new_krate_wrong_files() {
     bad_resp!(middle.call(req.with_body(&body)));
 }
 
+#[test]
+fn new_krate_lists_files() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_files", "1.1.0");
+    ::sign_in(&mut req, &app);
+    let files = [
+        ("foo_files-1.1.0/src/lib.rs", b"pub fn foo() {}\n" as &[_]),
+        ("foo_files-1.1.0/README.md", b"# foo_files\n" as &[_]),
+    ];
+    let body = ::new_crate_to_body(&new_crate("foo_files"), &files);
+    ok_resp!(middle.call(req.with_body(&body)));
+
+    let mut response = ok_resp!(
+        middle.call(
+            req.with_method(Method::Get)
+                .with_path("/api/v1/crates/foo_files/1.1.0/files")
+        )
+    );
+    let json: FileList = ::json(&mut response);
+    let paths = json.files.iter().map(|f| &*f.path).collect::<Vec<_>>();
+    assert_eq!(paths, ["Cargo.toml", "README.md", "src/lib.rs"]);
+    assert_eq!(json.files[2].size, 16);
+
+    let mut response = ok_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_files/1.1.0/files/src/lib.rs"),
+    ));
+    let mut contents = String::new();
+    response.body.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "pub fn foo() {}\n");
+    assert_eq!(
+        response.headers["Content-Type"],
+        ["text/plain; charset=utf-8"]
+    );
+
+    let json = bad_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_files/1.1.0/files/src/main.rs"),
+    ));
+    assert!(
+        json.errors[0].detail.contains("has no file `src/main.rs`"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn new_krate_without_manifest() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo", "1.1.0");
+    ::sign_in(&mut req, &app);
+    let body = ::new_crate_to_body_with_manifest(&new_crate("foo"), None, &mut []);
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert_eq!(
+        json.errors[0].detail,
+        "uploaded tarball is missing `Cargo.toml`"
+    );
+}
+
+#[test]
+fn new_krate_manifest_mismatch() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo", "1.1.0");
+    ::sign_in(&mut req, &app);
+
+    let mut other = new_crate("foo");
+    other.deps.push(u::CrateDependency {
+        name: u::CrateName("bar".to_string()),
+        optional: false,
+        default_features: true,
+        features: Vec::new(),
+        version_req: u::CrateVersionReq(semver::VersionReq::parse("^1.0").unwrap()),
+        target: None,
+        kind: None,
+        explicit_name_in_toml: None,
+        registry: None,
+    });
+    let manifest = ::new_crate_manifest(&other);
+    let body = ::new_crate_to_body_with_manifest(&new_crate("foo"), Some(&manifest), &mut []);
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert_eq!(
+        json.errors[0].detail,
+        "dependency `bar` in the uploaded `Cargo.toml` does not match the metadata sent by cargo"
+    );
+
+    let mut other = new_crate("foo");
+    other.license = Some("Apache-2.0".to_string());
+    let manifest = ::new_crate_manifest(&other);
+    let body = ::new_crate_to_body_with_manifest(&new_crate("foo"), Some(&manifest), &mut []);
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert_eq!(
+        json.errors[0].detail,
+        "the license in the uploaded `Cargo.toml` does not match the metadata sent by cargo"
+    );
+}
+
+#[test]
+fn validate_new_krate() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_validate", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/validate")));
+    let json: ValidateResponse = ::json(&mut response);
+    assert!(json.ok, "{:?}", json.errors);
+    assert!(json.errors.is_empty());
+
+    // Nothing is persisted, uploaded or pushed to the index
+    let conn = app.diesel_database.get().unwrap();
+    let crates = crates::table.count().get_result::<i64>(&*conn).unwrap();
+    assert_eq!(crates, 0);
+    assert_eq!(app.index.read_file("foo_validate").unwrap(), None);
+}
+
+#[test]
+fn validate_reports_every_error() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_validate", "1.1.0");
+    ::sign_in(&mut req, &app);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::CrateBuilder::new("foo_validate", other.id).expect_build(&conn);
+    }
+
+    let mut new_crate = new_crate("foo_validate");
+    new_crate.categories = Some(u::CategoryList(vec![u::Category("bogus".to_string())]));
+    let data: &[u8] = &[1];
+    let files = [("foo_validate-1.1.0/a", data), ("bar-1.1.0/a", data)];
+    let body = ::new_crate_to_body(&new_crate, &files);
+    let mut response = ok_resp!(
+        middle.call(
+            req.with_path("/api/v1/crates/validate")
+                .with_body(&body)
+        )
+    );
+    let json: ValidateResponse = ::json(&mut response);
+    assert!(!json.ok);
+    assert_eq!(json.errors.len(), 2, "{:?}", json.errors);
+    assert!(
+        json.errors[0]
+            .detail
+            .contains("you don't seem to be an owner"),
+        "{:?}",
+        json.errors
+    );
+    assert_eq!(json.errors[1].detail, "invalid tarball uploaded");
+    assert_eq!(json.warnings.invalid_categories, vec!["bogus"]);
+
+    // The version the validation created was rolled back
+    let conn = app.diesel_database.get().unwrap();
+    let versions = versions::table.count().get_result::<i64>(&*conn).unwrap();
+    assert_eq!(versions, 1);
+}
+
+#[test]
+fn validate_reports_errors_of_crates_which_cant_be_created() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "std", "1.1.0");
+    ::sign_in(&mut req, &app);
+
+    let mut new_crate = new_crate("std");
+    new_crate.description = None;
+    new_crate.deps = vec![
+        u::CrateDependency {
+            name: u::CrateName("foo_validate_missing".to_string()),
+            optional: false,
+            default_features: true,
+            features: Vec::new(),
+            version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+            target: None,
+            kind: None,
+            explicit_name_in_toml: None,
+            registry: None,
+        },
+    ];
+    new_crate.categories = Some(u::CategoryList(vec![u::Category("bogus".to_string())]));
+    let data: &[u8] = &[1];
+    let body = ::new_crate_to_body(&new_crate, &[("std-1.1.0/a", data)]);
+    let mut response = ok_resp!(
+        middle.call(
+            req.with_path("/api/v1/crates/validate")
+                .with_body(&body)
+        )
+    );
+    let json: ValidateResponse = ::json(&mut response);
+    assert!(!json.ok);
+    let errors = json.errors.iter().map(|e| &*e.detail).collect::<Vec<_>>();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(
+        errors[0].starts_with("missing or empty metadata fields: description."),
+        "{:?}",
+        errors
+    );
+    assert_eq!(errors[1], "cannot upload a crate with a reserved name");
+    assert_eq!(errors[2], "no known crate named `foo_validate_missing`");
+    assert_eq!(json.warnings.invalid_categories, vec!["bogus"]);
+}
+
+#[test]
+fn validate_reports_license_along_with_other_crate_errors() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_validate_license", "1.0.0");
+    ::sign_in(&mut req, &app);
+
+    let mut new_crate = new_crate("foo_validate_license");
+    new_crate.license = Some("bogus-license".to_string());
+    new_crate.homepage = Some("ftp://example.com".to_string());
+    let data: &[u8] = &[1];
+    let body = ::new_crate_to_body(&new_crate, &[("foo_validate_license-1.0.0/a", data)]);
+    let mut response = ok_resp!(
+        middle.call(
+            req.with_path("/api/v1/crates/validate")
+                .with_body(&body)
+        )
+    );
+    let json: ValidateResponse = ::json(&mut response);
+    assert!(!json.ok);
+    assert_eq!(json.errors.len(), 2, "{:?}", json.errors);
+    assert!(
+        json.errors[0].detail.contains("bogus-license"),
+        "{:?}",
+        json.errors
+    );
+    assert_eq!(
+        json.errors[1].detail,
+        "`homepage` has an invalid url scheme: `ftp`"
+    );
+}
+
 #[test]
 fn new_krate_gzip_bomb() {
     let (_b, app, middle) = ::app();
@@ -903,15 +1424,11 @@ fn new_krate_git_upload() {
     ::sign_in(&mut req, &app);
     let mut response = ok_resp!(middle.call(&mut req));
     ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
 
-    let path = ::git::checkout().join("3/f/fgt");
-    assert!(path.exists());
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
-    let p: git::Crate = serde_json::from_str(&contents).unwrap();
+    let entries = app.index.entries("fgt").unwrap();
+    assert_eq!(entries.len(), 1);
+    let p = &entries[0];
     assert_eq!(p.name, "fgt");
     assert_eq!(p.vers, "1.0.0");
     assert!(p.deps.is_empty());
@@ -922,58 +1439,187 @@ fn new_krate_git_upload() {
 }
 
 #[test]
-fn new_krate_git_upload_appends() {
-    let (_b, app, middle) = ::app();
-    let path = ::git::checkout().join("3/f/fpp");
-    fs::create_dir_all(path.parent().unwrap()).unwrap();
-    File::create(&path)
-        .unwrap()
-        .write_all(
-            br#"{"name":"FPP","vers":"0.0.1","deps":[],"features":{},"cksum":"3j3"}
-"#,
-        )
-        .unwrap();
-
-    let mut req = ::new_req(Arc::clone(&app), "FPP", "1.0.0");
-    ::sign_in(&mut req, &app);
-    let mut response = ok_resp!(middle.call(&mut req));
-    ::json::<GoodCrate>(&mut response);
+fn new_krate_git_upload_appends() {
+    let (_b, app, middle) = ::app();
+    app.index
+        .add_version(&git::Crate {
+            name: "FPP".to_string(),
+            vers: "0.0.1".to_string(),
+            deps: Vec::new(),
+            cksum: "3j3".to_string(),
+            features: HashMap::new(),
+            yanked: None,
+        })
+        .unwrap();
+
+    let mut req = ::new_req(Arc::clone(&app), "FPP", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
+
+    let entries = app.index.entries("FPP").unwrap();
+    assert_eq!(entries.len(), 2);
+    let (p1, p2) = (&entries[0], &entries[1]);
+    assert_eq!(p1.name, "FPP");
+    assert_eq!(p1.vers, "0.0.1");
+    assert!(p1.deps.is_empty());
+    assert_eq!(p2.name, "FPP");
+    assert_eq!(p2.vers, "1.0.0");
+    assert!(p2.deps.is_empty());
+}
+
+#[test]
+fn new_krate_git_upload_with_conflicts() {
+    // The only test pushing to a remote index, the others keep it in memory
+    let (_b, app, middle) = ::app_with_config(|config| config.index = ::git::remote_index());
+
+    {
+        let repo = git2::Repository::open(&::git::bare()).unwrap();
+        let target = repo.head().unwrap().target().unwrap();
+        let sig = repo.signature().unwrap();
+        let parent = repo.find_commit(target).unwrap();
+        let tree = repo.find_tree(parent.tree_id()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "empty commit", &tree, &[&parent])
+            .unwrap();
+    }
+
+    let mut req = ::new_req(Arc::clone(&app), "foo_conflicts", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: GoodCrate = ::json(&mut response);
+    ::run_background_jobs(&app);
+
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        &format!("/api/v1/publish_jobs/{}", json.publish_job.id),
+    );
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: PublishJobResponse = ::json(&mut response);
+    assert_eq!(json.publish_job.status, PublishJobStatus::Succeeded);
+
+    let repo = git2::Repository::open(&::git::bare()).unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent(0).unwrap().message(), Some("empty commit"));
+    assert_eq!(app.index.entries("foo_conflicts").unwrap().len(), 1);
+}
+
+#[test]
+fn new_krate_publish_job() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_job", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: GoodCrate = ::json(&mut response);
+    assert_eq!(json.publish_job.krate, "foo_job");
+    assert_eq!(json.publish_job.num, "1.0.0");
+    assert_eq!(json.publish_job.status, PublishJobStatus::Pending);
+
+    // Nothing is pushed to the index until the job runs
+    assert_eq!(app.index.read_file("foo_job").unwrap(), None);
+
+    let url = format!("/api/v1/publish_jobs/{}", json.publish_job.id);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get).with_path(&url)));
+    let json: PublishJobResponse = ::json(&mut response);
+    assert_eq!(json.publish_job.status, PublishJobStatus::Pending);
+    assert_eq!(json.publish_job.error, None);
+
+    ::run_background_jobs(&app);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get).with_path(&url)));
+    let json: PublishJobResponse = ::json(&mut response);
+    assert_eq!(json.publish_job.status, PublishJobStatus::Succeeded);
+    assert_eq!(json.publish_job.error, None);
+    assert!(app.index.read_file("foo_job").unwrap().is_some());
+}
+
+#[test]
+fn publish_job_picks_up_pending_yanks() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_job_yank", "1.0.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    ::run_background_jobs(&app);
+
+    // 1.1.0 is yanked before its publish job runs
+    let mut req = ::new_req(Arc::clone(&app), "foo_job_yank", "1.1.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/foo_job_yank/1.1.0/yank")
+        )
+    );
+    ::run_background_jobs(&app);
+
+    let entries = app.index.entries("foo_job_yank").unwrap();
+    let versions = entries
+        .iter()
+        .map(|e| (&*e.vers, e.yanked))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        versions,
+        vec![("1.0.0", Some(false)), ("1.1.0", Some(true))]
+    );
+}
+
+#[test]
+fn yanking_a_new_crate_before_its_publish_job_runs() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_job_new_yank", "1.0.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+
+    // The crate isn't in the index yet
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/foo_job_new_yank/1.0.0/yank")
+        )
+    );
+    assert_eq!(app.index.read_file("foo_job_new_yank").unwrap(), None);
+    ::run_background_jobs(&app);
+
+    let entries = app.index.entries("foo_job_new_yank").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].yanked, Some(true));
+}
+
+#[test]
+fn publish_jobs_are_added_to_the_index_together() {
+    let (_b, app, middle) = ::app_with_config(|config| config.index = ::git::local_index());
+    for name in &["foo_batch_a", "foo_batch_b", "foo_batch_c"] {
+        let mut req = ::new_req(Arc::clone(&app), name, "1.0.0");
+        ::sign_in(&mut req, &app);
+        ok_resp!(middle.call(&mut req));
+    }
+    ::run_background_jobs(&app);
 
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
-    let mut lines = contents.lines();
-    let p1: git::Crate = serde_json::from_str(lines.next().unwrap().trim()).unwrap();
-    let p2: git::Crate = serde_json::from_str(lines.next().unwrap().trim()).unwrap();
-    assert!(lines.next().is_none());
-    assert_eq!(p1.name, "FPP");
-    assert_eq!(p1.vers, "0.0.1");
-    assert!(p1.deps.is_empty());
-    assert_eq!(p2.name, "FPP");
-    assert_eq!(p2.vers, "1.0.0");
-    assert!(p2.deps.is_empty());
+    for name in &["foo_batch_a", "foo_batch_b", "foo_batch_c"] {
+        assert_eq!(app.index.entries(name).unwrap().len(), 1);
+    }
+    let repo = git2::Repository::open(&::git::checkout()).unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(
+        head.message().unwrap().starts_with("Updating 3 crates"),
+        "{:?}",
+        head.message()
+    );
+    assert_eq!(head.parent(0).unwrap().message(), Some("Initial Commit"));
 }
 
 #[test]
-fn new_krate_git_upload_with_conflicts() {
+fn publish_job_not_found() {
     let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/api/v1/publish_jobs/1");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 404);
 
-    {
-        let repo = git2::Repository::open(&::git::bare()).unwrap();
-        let target = repo.head().unwrap().target().unwrap();
-        let sig = repo.signature().unwrap();
-        let parent = repo.find_commit(target).unwrap();
-        let tree = repo.find_tree(parent.tree_id()).unwrap();
-        repo.commit(Some("HEAD"), &sig, &sig, "empty commit", &tree, &[&parent])
-            .unwrap();
-    }
-
-    let mut req = ::new_req(Arc::clone(&app), "foo_conflicts", "1.0.0");
-    ::sign_in(&mut req, &app);
+    let mut req = ::req(Arc::clone(&app), Method::Get, "/api/v1/publish_jobs/foo");
     let mut response = ok_resp!(middle.call(&mut req));
-    ::json::<GoodCrate>(&mut response);
+    ::json::<::Bad>(&mut response);
 }
 
 #[test]
@@ -987,6 +1633,8 @@ fn new_krate_dependency_missing() {
         version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0.0.0").unwrap()),
         target: None,
         kind: None,
+        explicit_name_in_toml: None,
+        registry: None,
     };
     let mut req = ::new_req_full(Arc::clone(&app), ::krate("foo_missing"), "1.0.0", vec![dep]);
     ::sign_in(&mut req, &app);
@@ -1301,18 +1949,14 @@ fn yank() {
         version: EncodableVersion,
     }
     let (_b, app, middle) = ::app();
-    let path = ::git::checkout().join("3/f/fyk");
 
     // Upload a new crate, putting it in the git index
     let mut req = ::new_req(Arc::clone(&app), "fyk", "1.0.0");
     ::sign_in(&mut req, &app);
     let mut response = ok_resp!(middle.call(&mut req));
     ::json::<GoodCrate>(&mut response);
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
+    ::run_background_jobs(&app);
+    let contents = app.index.read_file("fyk").unwrap().unwrap();
     assert!(contents.contains("\"yanked\":false"));
 
     // make sure it's not yanked
@@ -1332,11 +1976,7 @@ fn yank() {
         )
     );
     assert!(::json::<O>(&mut r).ok);
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
+    let contents = app.index.read_file("fyk").unwrap().unwrap();
     assert!(contents.contains("\"yanked\":true"));
     let mut r = ok_resp!(
         middle.call(
@@ -1354,11 +1994,7 @@ fn yank() {
         )
     );
     assert!(::json::<O>(&mut r).ok);
-    let mut contents = String::new();
-    File::open(&path)
-        .unwrap()
-        .read_to_string(&mut contents)
-        .unwrap();
+    let contents = app.index.read_file("fyk").unwrap().unwrap();
     assert!(contents.contains("\"yanked\":false"));
     let mut r = ok_resp!(
         middle.call(
@@ -1369,6 +2005,173 @@ fn yank() {
     assert!(!::json::<V>(&mut r).version.yanked);
 }
 
+#[test]
+fn yank_with_reason() {
+    #[derive(Deserialize)]
+    struct V {
+        version: EncodableVersion,
+    }
+    #[derive(Deserialize)]
+    struct H {
+        yank_history: Vec<EncodableVersionYankEvent>,
+    }
+    let (_b, app, middle) = ::app();
+
+    let mut req = ::new_req(Arc::clone(&app), "fyk", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
+
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/fyk/1.0.0/yank")
+                .with_query("reason=broken+build")
+        )
+    );
+    let mut r = ok_resp!(
+        middle.call(
+            req.with_method(Method::Get)
+                .with_path("/api/v1/crates/fyk/1.0.0")
+                .with_query("")
+        )
+    );
+    let version = ::json::<V>(&mut r).version;
+    assert!(version.yanked);
+    assert_eq!(version.yank_reason, Some("broken build".to_string()));
+
+    // Yanking again only updates the reason
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/fyk/1.0.0/yank")
+                .with_query("reason=security")
+        )
+    );
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Put)
+                .with_path("/api/v1/crates/fyk/1.0.0/unyank")
+                .with_query("")
+        )
+    );
+    let mut r = ok_resp!(
+        middle.call(
+            req.with_method(Method::Get)
+                .with_path("/api/v1/crates/fyk/1.0.0")
+        )
+    );
+    assert_eq!(::json::<V>(&mut r).version.yank_reason, None);
+
+    let mut r = ok_resp!(
+        middle.call(
+            req.with_method(Method::Get)
+                .with_path("/api/v1/crates/fyk/1.0.0/yank_history")
+        )
+    );
+    let history = ::json::<H>(&mut r).yank_history;
+    assert_eq!(history.len(), 3);
+    assert!(history[0].yanked);
+    assert_eq!(history[0].reason, Some("broken build".to_string()));
+    assert_eq!(history[0].user.login, "foo");
+    assert!(history[1].yanked);
+    assert_eq!(history[1].reason, Some("security".to_string()));
+    assert!(!history[2].yanked);
+    assert_eq!(history[2].reason, None);
+
+    let reason = format!("reason={}", "a".repeat(300));
+    let json = bad_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/fyk/1.0.0/yank")
+                .with_query(&reason)
+        )
+    );
+    assert!(
+        json.errors[0].detail.contains("at most 256 characters"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn yank_range() {
+    #[derive(Deserialize)]
+    struct R {
+        versions: Vec<String>,
+    }
+    let (_b, app, middle) = ::app_with_config(|config| config.index = ::git::local_index());
+
+    let mut req = ::new_req(Arc::clone(&app), "fyk_range", "1.0.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    for version in &["1.2.0", "1.2.3"] {
+        let body = ::new_req_body(
+            ::krate("fyk_range"),
+            version,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            HashMap::new(),
+        );
+        ok_resp!(middle.call(req.with_body(&body)));
+    }
+    ::run_background_jobs(&app);
+
+    let mut r = ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/fyk_range/yank")
+                .with_query("req=%3E%3D1.2.0%2C+%3C1.2.5&reason=bad+release")
+        )
+    );
+    assert_eq!(::json::<R>(&mut r).versions, vec!["1.2.0", "1.2.3"]);
+
+    let yanked = app.index
+        .entries("fyk_range")
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.vers, c.yanked))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        yanked,
+        vec![
+            ("1.0.0".to_string(), Some(false)),
+            ("1.2.0".to_string(), Some(true)),
+            ("1.2.3".to_string(), Some(true)),
+        ]
+    );
+    {
+        let repo = git2::Repository::open(&::git::checkout()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(
+            head.message().unwrap(),
+            "Yanking crate `fyk_range` versions 1.2.0, 1.2.3"
+        );
+    }
+
+    // Versions which are already yanked are left alone
+    let mut r = ok_resp!(middle.call(req.with_query("req=%5E1")));
+    assert_eq!(::json::<R>(&mut r).versions, vec!["1.0.0"]);
+
+    let mut r = ok_resp!(
+        middle.call(
+            req.with_method(Method::Put)
+                .with_path("/api/v1/crates/fyk_range/unyank")
+                .with_query("req=%3C1.2.3")
+        )
+    );
+    assert_eq!(::json::<R>(&mut r).versions, vec!["1.0.0", "1.2.0"]);
+
+    let json = bad_resp!(middle.call(req.with_query("req=not+a+requirement")));
+    assert!(
+        json.errors[0].detail.contains("invalid version requirement"),
+        "{:?}",
+        json.errors
+    );
+}
+
 #[test]
 fn yank_not_owner() {
     let (_b, app, middle) = ::app();
@@ -1408,6 +2211,7 @@ fn yank_max_version() {
     );
     let json: GoodCrate = ::json(&mut response);
     assert_eq!(json.krate.max_version, "2.0.0");
+    ::run_background_jobs(&app);
 
     // yank version 1.0.0
     let mut r = ok_resp!(
@@ -1528,6 +2332,7 @@ fn publish_after_yank_max_version() {
     // double check the max version
     let json: GoodCrate = ::json(&mut response);
     assert_eq!(json.krate.max_version, "1.0.0");
+    ::run_background_jobs(&app);
 
     // yank version 1.0.0
     let mut r = ok_resp!(
@@ -1557,6 +2362,7 @@ fn publish_after_yank_max_version() {
     );
     let json: GoodCrate = ::json(&mut response);
     assert_eq!(json.krate.max_version, "2.0.0");
+    ::run_background_jobs(&app);
 
     // unyank version 1.0.0
     let mut r = ok_resp!(
@@ -1946,6 +2752,72 @@ fn author_license_and_description_required() {
     );
 }
 
+#[test]
+fn new_krate_with_invalid_features() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Put, "/api/v1/crates/new");
+    ::sign_in(&mut req, &app);
+
+    let mut new_crate = new_crate("foo_features");
+    new_crate.features.insert(
+        u::CrateName("default".to_string()),
+        vec![u::Feature("std".to_string())],
+    );
+    req.with_body(&::new_crate_to_body(&new_crate, &[]));
+    let json = bad_resp!(middle.call(&mut req));
+    assert_eq!(
+        json.errors[0].detail,
+        "feature `default` enables `std`, which is neither a feature nor an optional dependency"
+    );
+
+    new_crate.features.insert(
+        u::CrateName("std".to_string()),
+        vec![u::Feature("default".to_string())],
+    );
+    req.with_body(&::new_crate_to_body(&new_crate, &[]));
+    let json = bad_resp!(middle.call(&mut req));
+    assert_eq!(
+        json.errors[0].detail,
+        "feature `default` enables itself: default -> std -> default"
+    );
+}
+
+#[test]
+fn validate_warns_about_non_optional_dependency_features() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Put, "/api/v1/crates/validate");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::CrateBuilder::new("foo_dep", user.id).expect_build(&conn);
+    }
+
+    let mut new_crate = new_crate("foo_features");
+    new_crate.deps.push(u::CrateDependency {
+        name: u::CrateName("foo_dep".to_string()),
+        optional: false,
+        default_features: true,
+        features: Vec::new(),
+        version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+        target: None,
+        kind: None,
+        explicit_name_in_toml: None,
+        registry: None,
+    });
+    new_crate.features.insert(
+        u::CrateName("std".to_string()),
+        vec![u::Feature("foo_dep/std".to_string())],
+    );
+    let mut response = ok_resp!(middle.call(req.with_body(&::new_crate_to_body(&new_crate, &[]))));
+    let json: ValidateResponse = ::json(&mut response);
+    assert!(json.ok, "{:?}", json.errors);
+    assert_eq!(
+        json.warnings.non_optional_dependency_features,
+        vec!["foo_dep/std"]
+    );
+}
+
 /*  Given two crates, one with downloads less than 90 days ago, the
     other with all downloads greater than 90 days ago, check that
     the order returned is by recent downloads, descending. Check
@@ -2164,6 +3036,140 @@ fn test_cargo_invite_owners() {
     )
 }
 
+#[test]
+fn delete_crate() {
+    #[derive(Deserialize)]
+    struct O {
+        ok: bool,
+    }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_delete", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    ::json::<GoodCrate>(&mut response);
+    ::run_background_jobs(&app);
+    assert!(app.index.read_file("foo_delete").unwrap().is_some());
+
+    let mut response = ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/foo_delete")
+        )
+    );
+    assert!(::json::<O>(&mut response).ok);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let crates = crates::table.count().get_result::<i64>(&*conn).unwrap();
+        assert_eq!(crates, 0);
+        let versions = versions::table.count().get_result::<i64>(&*conn).unwrap();
+        assert_eq!(versions, 0);
+    }
+
+    ::run_background_jobs(&app);
+    assert_eq!(app.index.read_file("foo_delete").unwrap(), None);
+
+    // The name can't be taken again right away
+    let mut req = ::new_req(Arc::clone(&app), "foo_delete", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("was recently deleted"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn delete_crate_before_its_publish_job_runs() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_delete", "1.0.0");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    ok_resp!(
+        middle.call(
+            req.with_method(Method::Delete)
+                .with_path("/api/v1/crates/foo_delete")
+        )
+    );
+
+    // The job adding the crate to the index has nothing left to do, rather
+    // than failing until it's given up on
+    ::run_background_jobs(&app);
+    let conn = app.diesel_database.get().unwrap();
+    let jobs = background_jobs::table
+        .count()
+        .get_result::<i64>(&*conn)
+        .unwrap();
+    assert_eq!(jobs, 0);
+    assert_eq!(app.index.read_file("foo_delete").unwrap(), None);
+}
+
+#[test]
+fn delete_crate_requires_full_owner() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Delete, "/api/v1/crates/foo_delete");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::CrateBuilder::new("foo_delete", owner.id).expect_build(&conn);
+        ::sign_in_as(&mut req, &other);
+    }
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("only owners"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn delete_crate_after_deletion_window() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Delete, "/api/v1/crates/foo_delete");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::CrateBuilder::new("foo_delete", user.id).expect_build(&conn);
+        let four_days_ago = Utc::now().naive_utc() - ::chrono::Duration::days(4);
+        update(&krate)
+            .set(crates::created_at.eq(four_days_ago))
+            .execute(&*conn)
+            .unwrap();
+        ::sign_in_as(&mut req, &user);
+    }
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("within 72 hours"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn delete_crate_with_reverse_dependencies() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(Arc::clone(&app), Method::Delete, "/api/v1/crates/foo_delete");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::CrateBuilder::new("foo_delete", user.id).expect_build(&conn);
+        let dependent = ::CrateBuilder::new("foo_dependent", user.id).expect_build(&conn);
+        let version = ::new_version(dependent.id, "1.0.0")
+            .save(&conn, &[])
+            .unwrap();
+        ::new_dependency(&conn, &version, &krate);
+        ::sign_in_as(&mut req, &user);
+    }
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains("other crates depend on"),
+        "{:?}",
+        json.errors
+    );
+}
+
 // #[test]
 // fn new_crate_bad_tarball() {
 //     let (_b, app, middle) = ::app();
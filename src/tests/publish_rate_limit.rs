@@ -0,0 +1,211 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use conduit::{Handler, Method};
+use diesel;
+use diesel::prelude::*;
+
+use cargo_registry::publish_rate_limit::{PublishAction, PublishRateLimit, PublishRateOverride,
+                                         RateLimit};
+use cargo_registry::schema::{publish_limit_buckets, publish_rate_overrides};
+
+#[derive(Deserialize)]
+struct R {
+    publish_rate_override: PublishRateOverride,
+}
+
+fn limit(burst: i32) -> PublishRateLimit {
+    PublishRateLimit {
+        new_crate: RateLimit {
+            rate: Duration::from_secs(60),
+            burst: burst,
+        },
+        new_version: RateLimit {
+            rate: Duration::from_secs(60),
+            burst: burst,
+        },
+    }
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn exhausting_the_bucket_is_rejected() {
+    let (_b, app, _) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let user = ::new_user("foo").create_or_update(&conn).unwrap();
+    let limit = limit(2);
+    let ip = ip("10.0.0.1");
+
+    t!(limit.check(&conn, PublishAction::NewCrate, &user, ip));
+    t!(limit.check(&conn, PublishAction::NewCrate, &user, ip));
+    let err = limit
+        .check(&conn, PublishAction::NewCrate, &user, ip)
+        .unwrap_err();
+    assert!(err.to_string().contains("Please try again in"), "{}", err);
+
+    // New versions have their own budget
+    t!(limit.check(&conn, PublishAction::NewVersion, &user, ip));
+}
+
+#[test]
+fn buckets_are_refilled_over_time() {
+    let (_b, app, _) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let user = ::new_user("foo").create_or_update(&conn).unwrap();
+    let limit = limit(1);
+    let ip = ip("10.0.0.1");
+
+    t!(limit.check(&conn, PublishAction::NewCrate, &user, ip));
+    assert!(limit.check(&conn, PublishAction::NewCrate, &user, ip).is_err());
+
+    let two_minutes_ago = Utc::now().naive_utc() - ::chrono::Duration::minutes(2);
+    t!(
+        diesel::update(publish_limit_buckets::table)
+            .set(publish_limit_buckets::last_refill.eq(two_minutes_ago))
+            .execute(&*conn)
+    );
+    t!(limit.check(&conn, PublishAction::NewCrate, &user, ip));
+}
+
+#[test]
+fn ip_address_is_shared_between_users() {
+    let (_b, app, _) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+    let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+    let limit = limit(1);
+
+    t!(limit.check(&conn, PublishAction::NewCrate, &foo, ip("10.0.0.1")));
+    assert!(
+        limit
+            .check(&conn, PublishAction::NewCrate, &bar, ip("10.0.0.1"))
+            .is_err()
+    );
+    t!(limit.check(&conn, PublishAction::NewCrate, &bar, ip("10.0.0.2")));
+}
+
+#[test]
+fn override_raises_burst_and_skips_ip() {
+    let (_b, app, _) = ::app();
+    let conn = app.diesel_database.get().unwrap();
+    let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+    let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+    let limit = limit(1);
+    let ip = ip("10.0.0.1");
+
+    t!(
+        diesel::insert_into(publish_rate_overrides::table)
+            .values(&PublishRateOverride {
+                user_id: bar.id,
+                new_crate_burst: Some(3),
+                new_version_burst: None,
+            })
+            .execute(&*conn)
+    );
+
+    t!(limit.check(&conn, PublishAction::NewCrate, &foo, ip));
+    for _ in 0..3 {
+        t!(limit.check(&conn, PublishAction::NewCrate, &bar, ip));
+    }
+    assert!(limit.check(&conn, PublishAction::NewCrate, &bar, ip).is_err());
+}
+
+#[test]
+fn publish_is_rejected_with_retry_after() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_rate_limited", "1.0.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        t!(
+            diesel::insert_into(publish_limit_buckets::table)
+                .values((
+                    publish_limit_buckets::bucket.eq(format!("user:{}", user.id)),
+                    publish_limit_buckets::action.eq(PublishAction::NewCrate as i32),
+                    publish_limit_buckets::tokens.eq(0),
+                    publish_limit_buckets::last_refill.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&*conn)
+        );
+    }
+
+    let mut response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 429);
+    let retry_after = &response.headers["Retry-After"][0];
+    assert!(retry_after.parse::<u64>().unwrap() > 0);
+    let json = ::json::<::Bad>(&mut response);
+    assert!(
+        json.errors[0]
+            .detail
+            .contains("published too many crates"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn update_override_requires_admin() {
+    let (_b, app, middle) = ::app();
+    let user = {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_user("foo").create_or_update(&conn).unwrap()
+    };
+    let path = format!("/api/v1/admin/users/{}/publish_rate_override", user.id);
+    let mut req = ::req(Arc::clone(&app), Method::Put, &path);
+    ::sign_in_as(&mut req, &user);
+    let mut response = ok_resp!(
+        middle.call(req.with_body(br#"{"new_crate_burst":10,"new_version_burst":null}"#))
+    );
+    let json = ::json::<::Bad>(&mut response);
+    assert!(
+        json.errors[0].detail.contains("must be an admin"),
+        "{:?}",
+        json.errors
+    );
+}
+
+#[test]
+fn admin_can_set_and_remove_override() {
+    let (_b, app, middle) = ::app();
+    let user = {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_user("foo").create_or_update(&conn).unwrap()
+    };
+    let path = format!("/api/v1/admin/users/{}/publish_rate_override", user.id);
+
+    let mut req = ::req(Arc::clone(&app), Method::Put, &path);
+    ::sign_in_as_admin(&mut req, &app);
+    let mut response = ok_resp!(
+        middle.call(req.with_body(br#"{"new_crate_burst":10,"new_version_burst":null}"#))
+    );
+    let json = ::json::<R>(&mut response);
+    assert_eq!(json.publish_rate_override.user_id, user.id);
+    assert_eq!(json.publish_rate_override.new_crate_burst, Some(10));
+    assert_eq!(json.publish_rate_override.new_version_burst, None);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let stored = publish_rate_overrides::table
+            .find(user.id)
+            .first::<PublishRateOverride>(&*conn)
+            .unwrap();
+        assert_eq!(stored.new_crate_burst, Some(10));
+    }
+
+    let mut response = ok_resp!(
+        middle.call(req.with_body(br#"{"new_crate_burst":null,"new_version_burst":null}"#))
+    );
+    ::json::<R>(&mut response);
+    let conn = app.diesel_database.get().unwrap();
+    let stored = publish_rate_overrides::table
+        .find(user.id)
+        .first::<PublishRateOverride>(&*conn)
+        .optional()
+        .unwrap();
+    assert!(stored.is_none());
+}
@@ -1,9 +1,13 @@
-use std::sync::ONCE_INIT;
+use std::sync::{Arc, ONCE_INIT};
 use conduit::{Handler, Method};
+use conduit_middleware::MiddlewareBuilder;
+use conduit_test::MockRequest;
 use diesel::*;
 
 use cargo_registry::user::NewUser;
 use cargo_registry::krate::{Crate, EncodableCrate};
+use cargo_registry::owner::{NewTeam, Team};
+use cargo_registry::reserved_prefix::EncodableReservedPrefix;
 use record::GhUser;
 
 // Users: `crates-tester-1` and `crates-tester-2`
@@ -459,3 +463,139 @@ fn crates_by_team_id_not_including_deleted_owners() {
     let response: Response = ::json(&mut response);
     assert_eq!(response.crates.len(), 0);
 }
+
+fn reserve_prefix(conn: &PgConnection, prefix: &str, team: &Team) {
+    use cargo_registry::schema::reserved_prefixes;
+
+    insert_into(reserved_prefixes::table)
+        .values((
+            reserved_prefixes::prefix.eq(prefix),
+            reserved_prefixes::team_id.eq(team.id),
+        ))
+        .execute(conn)
+        .unwrap();
+}
+
+// Test publishing a new crate under a prefix reserved for a team the user
+// isn't a member of
+#[test]
+fn publish_reserved_prefix_as_non_member() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_reserved_bar", "1.0.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = mock_user_on_only_x().create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let team = NewTeam::new("github:crates-test-org:just-for-crates-2", 1699379, None, None)
+            .create_or_update(&conn)
+            .unwrap();
+        reserve_prefix(&conn, "foo-reserved-", &team);
+    }
+
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(
+        json.errors[0].detail.contains(
+            "the prefix `foo-reserved-` is reserved for crates owned by \
+             `github:crates-test-org:just-for-crates-2`"
+        ),
+        "{:?}",
+        json.errors
+    );
+}
+
+// Test that members of the team can use a reserved prefix
+#[test]
+fn validate_reserved_prefix_as_member() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(Arc::clone(&app), "foo_reserved_bar", "1.0.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = mock_user_on_only_x().create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let team = NewTeam::new("github:crates-test-org:core", 1699377, None, None)
+            .create_or_update(&conn)
+            .unwrap();
+        reserve_prefix(&conn, "foo-reserved-", &team);
+    }
+
+    #[derive(Deserialize)]
+    struct Validate {
+        ok: bool,
+        errors: Vec<::Error>,
+    }
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/validate")));
+    let json: Validate = ::json(&mut response);
+    assert!(json.ok, "{:?}", json.errors);
+}
+
+#[test]
+fn reserved_prefixes_are_managed_by_admins() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_team("github:crates-test-org:core")
+            .create_or_update(&conn)
+            .unwrap();
+    }
+
+    #[derive(Deserialize)]
+    struct PrefixList {
+        reserved_prefixes: Vec<EncodableReservedPrefix>,
+    }
+    fn list(middle: &MiddlewareBuilder, req: &mut MockRequest) -> PrefixList {
+        let mut response = ok_resp!(
+            middle.call(
+                req.with_path("/api/v1/admin/reserved_prefixes")
+                    .with_method(Method::Get)
+            )
+        );
+        ::json(&mut response)
+    }
+
+    let body = r#"{"prefix":"foo-","team":"github:crates-test-org:core"}"#;
+    let mut req = ::req(Arc::clone(&app), Method::Put, "/api/v1/admin/reserved_prefixes");
+    ::sign_in(&mut req, &app);
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(
+        json.errors[0].detail.contains("must be an admin"),
+        "{:?}",
+        json.errors
+    );
+
+    ::sign_in_as_admin(&mut req, &app);
+    ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let json = list(&middle, &mut req);
+    assert_eq!(json.reserved_prefixes.len(), 1);
+    assert_eq!(json.reserved_prefixes[0].prefix, "foo-");
+    assert_eq!(json.reserved_prefixes[0].team, "github:crates-test-org:core");
+
+    let body = r#"{"prefix":"FOO_","team":"github:crates-test-org:core"}"#;
+    let json = bad_resp!(
+        middle.call(
+            req.with_method(Method::Put)
+                .with_body(body.as_bytes()),
+        )
+    );
+    assert!(
+        json.errors[0].detail.contains("is already reserved"),
+        "{:?}",
+        json.errors
+    );
+
+    let body = r#"{"prefix":"bar-","team":"github:crates-test-org:nobody"}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(
+        json.errors[0].detail.contains("could not find the team"),
+        "{:?}",
+        json.errors
+    );
+
+    ok_resp!(
+        middle.call(
+            req.with_path("/api/v1/admin/reserved_prefixes/foo-")
+                .with_method(Method::Delete),
+        )
+    );
+    let json = list(&middle, &mut req);
+    assert!(json.reserved_prefixes.is_empty());
+}
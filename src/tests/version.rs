@@ -71,6 +71,35 @@ fn show() {
     assert_eq!(json.version.id, v.id);
 }
 
+#[test]
+fn show_sizes() {
+    let (_b, app, middle) = ::app();
+    let v = {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::CrateBuilder::new("foo_vers_sizes", user.id).expect_build(&conn);
+        ::new_version(krate.id, "2.0.0").save(&conn, &[]).unwrap()
+    };
+    let mut req = ::req(
+        Arc::clone(&app),
+        Method::Get,
+        &format!("/api/v1/versions/{}", v.id),
+    );
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: VersionResponse = ::json(&mut response);
+    assert_eq!(json.version.crate_size, None);
+    assert_eq!(json.version.unpacked_size, None);
+
+    {
+        let conn = app.diesel_database.get().unwrap();
+        v.record_sizes(&conn, 1024, 4096).unwrap();
+    }
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: VersionResponse = ::json(&mut response);
+    assert_eq!(json.version.crate_size, Some(1024));
+    assert_eq!(json.version.unpacked_size, Some(4096));
+}
+
 #[test]
 fn authors() {
     let (_b, app, middle) = ::app();
@@ -58,6 +58,12 @@ pub struct CrateDependency {
     pub version_req: CrateVersionReq,
     pub target: Option<String>,
     pub kind: Option<DependencyKind>,
+    /// The name the dependency is renamed to with `package = "..."` in
+    /// `Cargo.toml`, in which case `name` is the name of the crate.
+    pub explicit_name_in_toml: Option<CrateName>,
+    /// The URL of the index of the registry the dependency is from, if it
+    /// isn't this one.
+    pub registry: Option<String>,
 }
 
 impl<'de> Deserialize<'de> for CrateName {
@@ -34,6 +34,7 @@ pub struct User {
     pub name: Option<String>,
     pub gh_avatar: Option<String>,
     pub gh_id: i32,
+    pub admin: bool,
 }
 
 #[derive(Insertable, Debug)]
@@ -191,6 +192,16 @@ impl User {
         Ok(users.collect())
     }
 
+    /// Returns an error unless this user is allowed to use the `/admin`
+    /// routes. Admins are flagged directly in the database.
+    pub fn require_admin(&self) -> CargoResult<()> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(human("must be an admin to perform that action"))
+        }
+    }
+
     /// Converts this `User` model into an `EncodablePrivateUser` for JSON serialization.
     pub fn encodable_private(
         self,
@@ -287,6 +287,45 @@ impl fmt::Display for Unauthorized {
     }
 }
 
+/// Returned when a rate limit has been exhausted. `retry_after` is the number
+/// of seconds until the request is allowed again.
+#[derive(Debug, Clone, Copy)]
+pub struct TooManyRequests {
+    pub retry_after: u64,
+}
+
+impl CargoError for TooManyRequests {
+    fn description(&self) -> &str {
+        "too many requests"
+    }
+
+    fn response(&self) -> Option<Response> {
+        let mut response = json_response(&Bad {
+            errors: vec![
+                StringError {
+                    detail: self.to_string(),
+                },
+            ],
+        });
+        response.status = (429, "Too Many Requests");
+        response
+            .headers
+            .insert("Retry-After".to_string(), vec![self.retry_after.to_string()]);
+        Some(response)
+    }
+}
+
+impl fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "You have published too many crates in a short period of time. \
+             Please try again in {} seconds.",
+            self.retry_after
+        )
+    }
+}
+
 struct BadRequest(String);
 
 impl CargoError for BadRequest {
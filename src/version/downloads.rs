@@ -33,8 +33,7 @@ pub fn download(req: &mut Request) -> CargoResult<Response> {
     }
 
     let redirect_url = req.app()
-        .config
-        .uploader
+        .storage
         .crate_location(crate_name, version)
         .ok_or_else(|| human("crate files not found"))?;
 
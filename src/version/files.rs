@@ -0,0 +1,173 @@
+//! Endpoints for browsing the files contained in a published version.
+//!
+//! The list of files is recorded when the crate is uploaded, so listing them
+//! doesn't touch the stored tarball. Reading a single file streams the
+//! tarball from the configured `Storage` and extracts that one entry.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use flate2::read::GzDecoder;
+use tar;
+
+use app::RequestApp;
+use db::RequestTransaction;
+use schema::version_files;
+use tarball::TarballFile;
+use util::{human, internal, CargoResult, ChainError, RequestUtils};
+
+use super::{version_and_crate, Version};
+
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[belongs_to(Version)]
+pub struct VersionFile {
+    pub id: i32,
+    pub version_id: i32,
+    pub path: String,
+    pub size: i64,
+    pub mode: Option<i32>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "version_files"]
+struct NewVersionFile<'a> {
+    version_id: i32,
+    path: &'a str,
+    size: i64,
+    mode: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodableVersionFile {
+    pub path: String,
+    pub size: i64,
+    pub mode: Option<i32>,
+}
+
+impl VersionFile {
+    /// Records the files found in the tarball of a version.
+    pub fn insert_all(
+        conn: &PgConnection,
+        version_id: i32,
+        files: &[TarballFile],
+    ) -> QueryResult<usize> {
+        let new_files = files
+            .iter()
+            .map(|file| {
+                NewVersionFile {
+                    version_id: version_id,
+                    path: &file.path,
+                    size: file.size,
+                    mode: file.mode,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // A tarball can contain the same path twice, in which case the last
+        // entry is the one that gets unpacked. We only need the path once.
+        diesel::insert_into(version_files::table)
+            .values(&new_files)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+
+    pub fn encodable(self) -> EncodableVersionFile {
+        EncodableVersionFile {
+            path: self.path,
+            size: self.size,
+            mode: self.mode,
+        }
+    }
+}
+
+/// Handles the `GET /crates/:crate_id/:version/files` route.
+pub fn files(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let files = VersionFile::belonging_to(&version)
+        .order(version_files::path)
+        .load::<VersionFile>(&*conn)?
+        .into_iter()
+        .map(VersionFile::encodable)
+        .collect();
+
+    #[derive(Serialize)]
+    struct R {
+        files: Vec<EncodableVersionFile>,
+    }
+    Ok(req.json(&R { files: files }))
+}
+
+/// Handles the `GET /crates/:crate_id/:version/files/*path` route.
+///
+/// Responds with the raw contents of the file, as `text/plain` if it is valid
+/// UTF-8 and as `application/octet-stream` otherwise.
+pub fn file(req: &mut Request) -> CargoResult<Response> {
+    let (version, krate) = version_and_crate(req)?;
+    let path = req.params()["path"].clone();
+
+    let conn = req.db_conn()?;
+    VersionFile::belonging_to(&version)
+        .filter(version_files::path.eq(&path))
+        .first::<VersionFile>(&*conn)
+        .optional()?
+        .ok_or_else(|| {
+            human(&format_args!(
+                "crate `{}` version `{}` has no file `{}`",
+                krate.name,
+                version.num,
+                path
+            ))
+        })?;
+
+    let app = req.app();
+    let vers = version.num.to_string();
+    let tarball = app.storage.read_crate(&krate.name, &vers)?;
+    let prefix = format!("{}-{}", krate.name, vers);
+    let contents = read_tarball_file(tarball, &Path::new(&prefix).join(&path))?;
+
+    let content_type = if ::std::str::from_utf8(&contents).is_ok() {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    };
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), vec![content_type.to_string()]);
+    headers.insert(
+        "Content-Length".to_string(),
+        vec![contents.len().to_string()],
+    );
+    Ok(Response {
+        status: (200, "OK"),
+        headers: headers,
+        body: Box::new(Cursor::new(contents)),
+    })
+}
+
+/// Returns the contents of the last entry at `path` in a gzipped tarball.
+///
+/// The tarball is read as it is decompressed, only the contents of the
+/// matching entries are kept in memory.
+fn read_tarball_file<R: Read>(tarball: R, path: &Path) -> CargoResult<Vec<u8>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball)?);
+    let mut contents = None;
+    for entry in archive.entries()? {
+        let mut entry = entry.chain_error(|| internal("stored tarball is malformed"))?;
+        if *entry.path()? == *path && entry.header().entry_type().is_file() {
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            contents = Some(buf);
+        }
+    }
+    contents.ok_or_else(|| {
+        internal(&format_args!(
+            "`{}` is missing from the stored tarball",
+            path.display()
+        ))
+    })
+}
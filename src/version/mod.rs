@@ -18,6 +18,7 @@ use license_exprs;
 
 pub mod deprecated;
 pub mod downloads;
+pub mod files;
 pub mod metadata;
 pub mod yank;
 
@@ -34,6 +35,13 @@ pub struct Version {
     pub features: HashMap<String, Vec<String>>,
     pub yanked: bool,
     pub license: Option<String>,
+    pub crate_size: Option<i32>,
+    pub unpacked_size: Option<i32>,
+    /// Why the version was yanked, if it is and a reason was given.
+    pub yank_reason: Option<String>,
+    /// The SHA256 checksum of the `.crate` file, as a hex string. Missing for
+    /// versions published before checksums were recorded.
+    pub checksum: Option<String>,
 }
 
 #[derive(Insertable, Debug)]
@@ -57,7 +65,10 @@ pub struct EncodableVersion {
     pub downloads: i32,
     pub features: HashMap<String, Vec<String>>,
     pub yanked: bool,
+    pub yank_reason: Option<String>,
     pub license: Option<String>,
+    pub crate_size: Option<i32>,
+    pub unpacked_size: Option<i32>,
     pub links: VersionLinks,
 }
 
@@ -66,6 +77,7 @@ pub struct VersionLinks {
     pub dependencies: String,
     pub version_downloads: String,
     pub authors: String,
+    pub yank_history: String,
 }
 
 impl Version {
@@ -79,6 +91,9 @@ impl Version {
             features,
             yanked,
             license,
+            crate_size,
+            unpacked_size,
+            yank_reason,
             ..
         } = self;
         let num = num.to_string();
@@ -93,22 +108,41 @@ impl Version {
             downloads: downloads,
             features: features,
             yanked: yanked,
+            yank_reason: yank_reason,
             license: license,
+            crate_size: crate_size,
+            unpacked_size: unpacked_size,
             links: VersionLinks {
                 dependencies: format!("/api/v1/crates/{}/{}/dependencies", crate_name, num),
                 version_downloads: format!("/api/v1/crates/{}/{}/downloads", crate_name, num),
                 authors: format!("/api/v1/crates/{}/{}/authors", crate_name, num),
+                yank_history: format!("/api/v1/crates/{}/{}/yank_history", crate_name, num),
             },
         }
     }
 
     /// Returns (dependency, crate dependency name)
+    ///
+    /// For dependencies on crates from other registries, the name is the one
+    /// of the crate in that registry.
     pub fn dependencies(&self, conn: &PgConnection) -> QueryResult<Vec<(Dependency, String)>> {
-        Dependency::belonging_to(self)
-            .inner_join(crates::table)
-            .select((dependencies::all_columns, crates::name))
-            .order((dependencies::optional, crates::name))
-            .load(conn)
+        let deps = Dependency::belonging_to(self)
+            .left_outer_join(crates::table)
+            .select((dependencies::all_columns, crates::name.nullable()))
+            .order((
+                dependencies::optional,
+                crates::name,
+                dependencies::registry_crate_name,
+            ))
+            .load::<(Dependency, Option<String>)>(conn)?;
+        Ok(deps.into_iter()
+            .map(|(dep, crate_name)| {
+                let name = crate_name
+                    .or_else(|| dep.registry_crate_name.clone())
+                    .unwrap_or_default();
+                (dep, name)
+            })
+            .collect())
     }
 
     pub fn max<T>(versions: T) -> semver::Version
@@ -134,6 +168,29 @@ impl Version {
             .set(readme_rendered_at.eq(now.nullable()))
             .execute(conn)
     }
+
+    /// Records the size of the uploaded `.crate` file, and the total size of
+    /// the files it contains.
+    pub fn record_sizes(
+        &self,
+        conn: &PgConnection,
+        crate_size: i32,
+        unpacked_size: i32,
+    ) -> QueryResult<usize> {
+        diesel::update(self)
+            .set((
+                versions::crate_size.eq(crate_size),
+                versions::unpacked_size.eq(unpacked_size),
+            ))
+            .execute(conn)
+    }
+
+    /// Records the checksum of the uploaded `.crate` file.
+    pub fn record_checksum(&self, conn: &PgConnection, checksum: &str) -> QueryResult<usize> {
+        diesel::update(self)
+            .set(versions::checksum.eq(checksum))
+            .execute(conn)
+    }
 }
 
 impl NewVersion {
@@ -227,6 +284,10 @@ impl Queryable<versions::SqlType, Pg> for Version {
         bool,
         Option<String>,
         Option<NaiveDateTime>,
+        Option<i32>,
+        Option<i32>,
+        Option<String>,
+        Option<String>,
     );
 
     fn build(row: Self::Row) -> Self {
@@ -243,6 +304,10 @@ impl Queryable<versions::SqlType, Pg> for Version {
             features: features,
             yanked: row.7,
             license: row.8,
+            crate_size: row.10,
+            unpacked_size: row.11,
+            yank_reason: row.12,
+            checksum: row.13,
         }
     }
 }
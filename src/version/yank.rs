@@ -1,29 +1,80 @@
 //! Endpoints for yanking and unyanking specific versions of crates
 
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
 use conduit::{Request, Response};
+use conduit_router::RequestParams;
 use diesel;
 use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::types::Integer;
+use semver;
 
 use app::RequestApp;
 use db::RequestTransaction;
-use git;
 use owner::{rights, Rights};
 use schema::*;
-use user::RequestUser;
+use user::{EncodablePublicUser, RequestUser};
 use util::errors::CargoError;
 use util::{human, CargoResult, RequestUtils};
 
+use {Crate, User, Version};
+
 use super::version_and_crate;
 
+/// Yank reasons longer than this are rejected.
+const MAX_REASON_LENGTH: usize = 256;
+
+#[derive(Queryable, Identifiable, Associations, Debug, Clone)]
+#[belongs_to(Version)]
+pub struct VersionYankEvent {
+    pub id: i32,
+    pub version_id: i32,
+    pub user_id: i32,
+    pub yanked: bool,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "version_yank_events"]
+struct NewVersionYankEvent<'a> {
+    version_id: i32,
+    user_id: i32,
+    yanked: bool,
+    reason: Option<&'a str>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodableVersionYankEvent {
+    pub yanked: bool,
+    pub reason: Option<String>,
+    pub user: EncodablePublicUser,
+    #[serde(with = "::util::rfc3339")] pub created_at: NaiveDateTime,
+}
+
+impl VersionYankEvent {
+    pub fn encodable(self, user: User) -> EncodableVersionYankEvent {
+        EncodableVersionYankEvent {
+            yanked: self.yanked,
+            reason: self.reason,
+            user: user.encodable_public(),
+            created_at: self.created_at,
+        }
+    }
+}
+
 /// Handles the `DELETE /crates/:crate_id/:version/yank` route.
 /// This does not delete a crate version, it makes the crate
 /// version accessible only to crates that already have a
 /// `Cargo.lock` containing this version.
 ///
 /// Notes:
-/// Crate deletion is not implemented to avoid breaking builds,
-/// and the goal of yanking a crate is to prevent crates
-/// beginning to depend on the yanked crate version.
+/// Crates can only be deleted shortly after they are first published
+/// (see `krate::delete`) to avoid breaking builds, and the goal of
+/// yanking a crate is to prevent crates beginning to depend on the
+/// yanked crate version.
 pub fn yank(req: &mut Request) -> CargoResult<Response> {
     modify_yank(req, true)
 }
@@ -33,9 +84,55 @@ pub fn unyank(req: &mut Request) -> CargoResult<Response> {
     modify_yank(req, false)
 }
 
+/// Handles the `GET /crates/:crate_id/:version/yank_history` route.
+///
+/// Lists every yank and unyank of the version, oldest first.
+pub fn history(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let events = version_yank_events::table
+        .inner_join(users::table)
+        .filter(version_yank_events::version_id.eq(version.id))
+        .order(version_yank_events::id)
+        .load::<(VersionYankEvent, User)>(&*conn)?
+        .into_iter()
+        .map(|(event, user)| event.encodable(user))
+        .collect();
+
+    #[derive(Serialize)]
+    struct R {
+        yank_history: Vec<EncodableVersionYankEvent>,
+    }
+    Ok(req.json(&R {
+        yank_history: events,
+    }))
+}
+
+/// Handles the `DELETE /crates/:crate_id/yank` route.
+///
+/// Yanks every version of the crate matching the semver requirement given
+/// in the `req` query parameter, e.g. `>=1.2.0, <1.2.5`, updating the index
+/// with a single commit. Takes the same optional `reason` as `yank`.
+pub fn yank_range(req: &mut Request) -> CargoResult<Response> {
+    modify_yank_range(req, true)
+}
+
+/// Handles the `PUT /crates/:crate_id/unyank` route.
+///
+/// Unyanks every version of the crate matching the `req` query parameter.
+pub fn unyank_range(req: &mut Request) -> CargoResult<Response> {
+    modify_yank_range(req, false)
+}
+
 /// Changes `yanked` flag on a crate version record
+///
+/// Yanking takes an optional `reason` query parameter, which is recorded
+/// along with the yank. Yanking a version which is already yanked only
+/// updates the reason.
 fn modify_yank(req: &mut Request, yanked: bool) -> CargoResult<Response> {
     let (version, krate) = version_and_crate(req)?;
+    let reason = yank_reason(&mut req.query(), yanked)?;
+
     let user = req.user()?;
     let conn = req.db_conn()?;
     let owners = krate.owners(&conn)?;
@@ -43,12 +140,28 @@ fn modify_yank(req: &mut Request, yanked: bool) -> CargoResult<Response> {
         return Err(human("must already be an owner to yank or unyank"));
     }
 
-    if version.yanked != yanked {
+    let reason_changed = yanked && reason.is_some() && reason != version.yank_reason;
+    if version.yanked != yanked || reason_changed {
         conn.transaction::<_, Box<CargoError>, _>(|| {
             diesel::update(&version)
-                .set(versions::yanked.eq(yanked))
+                .set((
+                    versions::yanked.eq(yanked),
+                    versions::yank_reason.eq(&reason),
+                ))
+                .execute(&*conn)?;
+            diesel::insert_into(version_yank_events::table)
+                .values(&NewVersionYankEvent {
+                    version_id: version.id,
+                    user_id: user.id,
+                    yanked: yanked,
+                    reason: reason.as_ref().map(|s| &**s),
+                })
                 .execute(&*conn)?;
-            git::yank(&**req.app(), &krate.name, &version.num, yanked)?;
+            if version.yanked != yanked {
+                req.app()
+                    .index
+                    .set_yanked(&krate.name, &[version.num.clone()], yanked)?;
+            }
             Ok(())
         })?;
     }
@@ -59,3 +172,94 @@ fn modify_yank(req: &mut Request, yanked: bool) -> CargoResult<Response> {
     }
     Ok(req.json(&R { ok: true }))
 }
+
+/// Changes the `yanked` flag of every version of a crate matching a semver
+/// requirement. Versions which already have the requested state are left
+/// alone, and the numbers of the versions which changed are returned.
+fn modify_yank_range(req: &mut Request, yanked: bool) -> CargoResult<Response> {
+    let mut query = req.query();
+    let version_req = query
+        .remove("req")
+        .ok_or_else(|| human("missing the `req` query parameter"))?;
+    let version_req = semver::VersionReq::parse(&version_req)
+        .map_err(|_| human(&format_args!("invalid version requirement: {}", version_req)))?;
+    let reason = yank_reason(&mut query, yanked)?;
+
+    let crate_name = &req.params()["crate_id"];
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
+    let owners = krate.owners(&conn)?;
+    if rights(req.app(), &owners, user)? < Rights::Publish {
+        return Err(human("must already be an owner to yank or unyank"));
+    }
+
+    // The versions are locked until they're updated, so that a concurrent
+    // yank either finishes first and is seen here, or waits for this one.
+    // The versions returned are then the ones which were actually changed.
+    let versions = conn.transaction::<_, Box<CargoError>, _>(|| {
+        sql_query("SELECT id FROM versions WHERE crate_id = $1 ORDER BY id FOR UPDATE")
+            .bind::<Integer, _>(krate.id)
+            .execute(&*conn)?;
+        let versions = Version::belonging_to(&krate)
+            .order(versions::id)
+            .load::<Version>(&*conn)?
+            .into_iter()
+            .filter(|v| v.yanked != yanked && version_req.matches(&v.num))
+            .collect::<Vec<_>>();
+        if versions.is_empty() {
+            return Ok(versions);
+        }
+
+        let ids = versions.iter().map(|v| v.id).collect::<Vec<_>>();
+        let nums = versions.iter().map(|v| v.num.clone()).collect::<Vec<_>>();
+        let events = versions
+            .iter()
+            .map(|v| NewVersionYankEvent {
+                version_id: v.id,
+                user_id: user.id,
+                yanked: yanked,
+                reason: reason.as_ref().map(|s| &**s),
+            })
+            .collect::<Vec<_>>();
+
+        diesel::update(versions::table.filter(versions::id.eq_any(&ids)))
+            .set((
+                versions::yanked.eq(yanked),
+                versions::yank_reason.eq(&reason),
+            ))
+            .execute(&*conn)?;
+        diesel::insert_into(version_yank_events::table)
+            .values(&events)
+            .execute(&*conn)?;
+        req.app().index.set_yanked(&krate.name, &nums, yanked)?;
+        Ok(versions)
+    })?;
+
+    #[derive(Serialize)]
+    struct R {
+        versions: Vec<String>,
+    }
+    Ok(req.json(&R {
+        versions: versions.iter().map(|v| v.num.to_string()).collect(),
+    }))
+}
+
+/// Takes the `reason` query parameter of a yank, which is ignored when
+/// unyanking, and checks its length.
+fn yank_reason(query: &mut HashMap<String, String>, yanked: bool) -> CargoResult<Option<String>> {
+    let reason = if yanked {
+        query
+            .remove("reason")
+            .and_then(|r| if r.is_empty() { None } else { Some(r) })
+    } else {
+        None
+    };
+    if reason.as_ref().map_or(0, |r| r.chars().count()) > MAX_REASON_LENGTH {
+        return Err(human(&format_args!(
+            "the yank reason must be at most {} characters long",
+            MAX_REASON_LENGTH
+        )));
+    }
+    Ok(reason)
+}